@@ -0,0 +1,121 @@
+//! Benchmarks of the book's hot path: applying snapshots and updates and reading the top.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use hft_orderbook::{BookUpdate, Orderbook, PriceLevel, UpdateKind};
+use rust_decimal::Decimal;
+use std::hint::black_box;
+
+/// levels per side of the book the updates are applied to, Kraken's deepest subscription
+const DEPTH: usize = 500;
+
+const MID: i64 = 3_000_000;
+
+fn level(price: i64, qty: i64) -> PriceLevel {
+    PriceLevel {
+        price: Decimal::new(price, 1),
+        qty: Decimal::new(qty, 8),
+    }
+}
+
+fn book_update(kind: UpdateKind, bids: Vec<PriceLevel>, asks: Vec<PriceLevel>) -> BookUpdate {
+    BookUpdate {
+        symbol: String::from("BTC/USD"),
+        kind,
+        bids,
+        asks,
+        checksum: None,
+        timestamp: None,
+    }
+}
+
+/// `levels` bids and asks one tick apart around the mid.
+fn snapshot(levels: usize) -> BookUpdate {
+    let side = |direction: i64| {
+        (1..=levels as i64)
+            .map(|offset| level(MID + direction * offset, 10_000_000 + offset))
+            .collect()
+    };
+    book_update(UpdateKind::Snapshot, side(-1), side(1))
+}
+
+/// `levels` changes per side within the book's depth, roughly a third of them deletes;
+/// drawn from a fixed linear congruential generator so every run applies the same updates.
+fn mixed_update(levels: usize, seed: &mut u64) -> BookUpdate {
+    let mut next = || {
+        *seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (*seed >> 33) as i64
+    };
+    let mut side = |direction: i64| {
+        (0..levels)
+            .map(|_| {
+                let offset = 1 + next() % DEPTH as i64;
+                let qty = if next() % 3 == 0 {
+                    0
+                } else {
+                    next() % 100_000_000
+                };
+                level(MID + direction * offset, qty)
+            })
+            .collect()
+    };
+    let bids = side(-1);
+    let asks = side(1);
+    book_update(UpdateKind::Update, bids, asks)
+}
+
+fn full_book() -> Orderbook {
+    let mut orderbook = Orderbook::new("BTC/USD").with_max_depth(DEPTH);
+    orderbook.handle_snapshot(&snapshot(DEPTH));
+    orderbook
+}
+
+fn bench_snapshot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handle_snapshot");
+    for levels in [10, 100, DEPTH] {
+        let snapshot = snapshot(levels);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(levels),
+            &snapshot,
+            |b, snapshot| {
+                let mut orderbook = Orderbook::new("BTC/USD").with_max_depth(DEPTH);
+                b.iter(|| orderbook.handle_snapshot(black_box(snapshot)));
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handle_update");
+    for levels in [1, 10, 100] {
+        let mut seed = 42;
+        let updates: Vec<BookUpdate> = (0..64).map(|_| mixed_update(levels, &mut seed)).collect();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(levels),
+            &updates,
+            |b, updates| {
+                b.iter_batched_ref(
+                    full_book,
+                    |orderbook| {
+                        for update in updates {
+                            orderbook.handle_update(black_box(update));
+                        }
+                    },
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_top_of_book(c: &mut Criterion) {
+    let orderbook = full_book();
+    c.bench_function("best_bid", |b| b.iter(|| black_box(&orderbook).best_bid()));
+    c.bench_function("best_ask", |b| b.iter(|| black_box(&orderbook).best_ask()));
+}
+
+criterion_group!(benches, bench_snapshot, bench_update, bench_top_of_book);
+criterion_main!(benches);
@@ -0,0 +1,125 @@
+use crate::orderbook::{Orderbook, PriceLevel};
+use rust_decimal::prelude::ToPrimitive;
+
+/// Buying at the top of one book and selling at the top of another with a profit left
+/// after fees. Prices are in the quote currency of the first book passed to
+/// [`cross_spread`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arbitrage {
+    pub buy_symbol: String,
+    pub buy_price: f64,
+    pub sell_symbol: String,
+    pub sell_price: f64,
+    /// quantity available at both prices, the smaller of the two levels
+    pub qty: f64,
+    /// profit relative to the cost of the buy, after paying `fee` on both trades
+    pub profit: f64,
+}
+
+/// Checks whether buying on one of the books and selling on the other is profitable.
+/// `rate` converts the prices of `book_b` into the quote currency of `book_a`, e.g. the
+/// EUR/USD rate for BTC/USD and BTC/EUR, and `fee` is the fraction of the traded amount
+/// charged on each trade. Returns the more profitable direction, `None` when neither makes
+/// a profit or a book lacks a side.
+pub fn cross_spread(
+    book_a: &Orderbook,
+    book_b: &Orderbook,
+    rate: f64,
+    fee: f64,
+) -> Option<Arbitrage> {
+    let buy_a = opportunity(book_a, 1.0, book_b, rate, fee);
+    let buy_b = opportunity(book_b, rate, book_a, 1.0, fee);
+    [buy_a, buy_b]
+        .into_iter()
+        .flatten()
+        .max_by(|a, b| a.profit.total_cmp(&b.profit))
+}
+
+/// Buying the best ask of `buy` and selling to the best bid of `sell`, if profitable.
+fn opportunity(
+    buy: &Orderbook,
+    buy_rate: f64,
+    sell: &Orderbook,
+    sell_rate: f64,
+    fee: f64,
+) -> Option<Arbitrage> {
+    let (ask, ask_qty) = converted(buy.best_ask()?, buy_rate)?;
+    let (bid, bid_qty) = converted(sell.best_bid()?, sell_rate)?;
+    let cost = ask * (1.0 + fee);
+    let proceeds = bid * (1.0 - fee);
+    if proceeds <= cost {
+        return None;
+    }
+    Some(Arbitrage {
+        buy_symbol: buy.symbol().to_string(),
+        buy_price: ask,
+        sell_symbol: sell.symbol().to_string(),
+        sell_price: bid,
+        qty: ask_qty.min(bid_qty),
+        profit: (proceeds - cost) / cost,
+    })
+}
+
+fn converted(level: PriceLevel, rate: f64) -> Option<(f64, f64)> {
+    Some((level.price.to_f64()? * rate, level.qty.to_f64()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::{BookUpdate, UpdateKind};
+
+    fn book(symbol: &str, bid: (f64, f64), ask: (f64, f64)) -> Orderbook {
+        let level = |(price, qty): (f64, f64)| PriceLevel {
+            price: price.to_string().parse().unwrap(),
+            qty: qty.to_string().parse().unwrap(),
+        };
+        let mut orderbook = Orderbook::new(symbol);
+        orderbook.evaluate(&BookUpdate {
+            symbol: String::from(symbol),
+            kind: UpdateKind::Snapshot,
+            bids: vec![level(bid)],
+            asks: vec![level(ask)],
+            checksum: None,
+            timestamp: None,
+        });
+        orderbook
+    }
+
+    #[test]
+    fn converted_prices_are_compared_after_fees() {
+        let usd = book("BTC/USD", (100.0, 1.0), (101.0, 2.0));
+        // 100 EUR at 1.1 USD per EUR, so the EUR bid is worth 110 USD
+        let eur = book("BTC/EUR", (100.0, 0.5), (100.5, 1.0));
+
+        let arbitrage = cross_spread(&usd, &eur, 1.1, 0.0).unwrap();
+        assert_eq!(arbitrage.buy_symbol, "BTC/USD");
+        assert_eq!(arbitrage.sell_symbol, "BTC/EUR");
+        assert_eq!(arbitrage.buy_price, 101.0);
+        assert!((arbitrage.sell_price - 110.0).abs() < 1e-9);
+        assert_eq!(arbitrage.qty, 0.5);
+        assert!((arbitrage.profit - 9.0 / 101.0).abs() < 1e-9);
+
+        // 5% on each side eats the 9% edge
+        assert_eq!(cross_spread(&usd, &eur, 1.1, 0.05), None);
+        assert!(cross_spread(&usd, &eur, 1.1, 0.04).is_some());
+    }
+
+    #[test]
+    fn either_direction_is_found() {
+        let usd = book("BTC/USD", (100.0, 1.0), (101.0, 2.0));
+        let eur = book("BTC/EUR", (80.0, 1.0), (90.0, 3.0));
+
+        let arbitrage = cross_spread(&usd, &eur, 1.1, 0.0).unwrap();
+        assert_eq!(arbitrage.buy_symbol, "BTC/EUR");
+        assert!((arbitrage.buy_price - 99.0).abs() < 1e-9);
+        assert_eq!(arbitrage.sell_price, 100.0);
+        assert_eq!(arbitrage.qty, 1.0);
+
+        assert_eq!(cross_spread(&usd, &eur, 1.2, 0.0), None);
+        assert_eq!(
+            cross_spread(&usd, &Orderbook::new("BTC/EUR"), 1.1, 0.0),
+            None
+        );
+    }
+}
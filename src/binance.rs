@@ -0,0 +1,509 @@
+//! Binance's diff depth stream. Binance sends no snapshots over the websocket, so the
+//! [`BinanceFeed`] fetches one over REST and sequences the streamed diffs onto it by
+//! update id, as described in Binance's guide to managing a local order book.
+
+use crate::clock::{Clock, SystemClock};
+use crate::feed::{BookUpdate, ExchangeFeed, FeedEvent, UpdateKind};
+use crate::orderbook::PriceLevel;
+use crate::snapshot_fetch::SnapshotFetch;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// websocket endpoint taking `SUBSCRIBE` requests for `<symbol>@depth` streams
+pub const BINANCE_URL: &str = "wss://stream.binance.com:9443/ws";
+
+/// REST endpoint serving the depth snapshots the streamed diffs are applied to
+const DEPTH_SNAPSHOT_URL: &str = "https://api.binance.com/api/v3/depth";
+
+/// Levels requested per side when fetching a snapshot, Binance's maximum.
+const SNAPSHOT_LIMIT: u32 = 5000;
+
+/// longest a snapshot request may take, connecting included
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most diffs kept per symbol while waiting for a snapshot; older ones are dropped beyond.
+const MAX_BUFFERED: usize = 1000;
+
+/// Fetches the depth snapshot of a symbol, called on a thread of its own.
+pub type SnapshotSource = Arc<dyn Fn(&str) -> Result<DepthSnapshot, String> + Send + Sync>;
+
+/// One event of a `<symbol>@depth` stream.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DepthUpdate {
+    #[serde(rename = "e")]
+    pub event: String,
+    /// event time in milliseconds since the unix epoch
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    #[serde(rename = "u")]
+    pub final_update_id: u64,
+    #[serde(rename = "b")]
+    pub bids: Vec<PriceLevel>,
+    #[serde(rename = "a")]
+    pub asks: Vec<PriceLevel>,
+}
+
+impl DepthUpdate {
+    fn into_update(self) -> BookUpdate {
+        BookUpdate {
+            symbol: self.symbol,
+            kind: UpdateKind::Update,
+            bids: self.bids,
+            asks: self.asks,
+            checksum: None,
+            timestamp: Some(UNIX_EPOCH + Duration::from_millis(self.event_time)),
+        }
+    }
+}
+
+/// Response of the REST depth endpoint.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DepthSnapshot {
+    pub last_update_id: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+impl DepthSnapshot {
+    fn into_update(self, symbol: &str) -> BookUpdate {
+        BookUpdate {
+            symbol: String::from(symbol),
+            kind: UpdateKind::Snapshot,
+            bids: self.bids,
+            asks: self.asks,
+            checksum: None,
+            timestamp: None,
+        }
+    }
+}
+
+/// Answer to a `SUBSCRIBE` or `UNSUBSCRIBE` request.
+#[derive(Serialize, Deserialize)]
+struct RequestResponse {
+    id: u64,
+    error: Option<RequestError>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RequestError {
+    code: i64,
+    msg: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum IncomingMessage {
+    Depth(DepthUpdate),
+    Response(RequestResponse),
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    method: &'a str,
+    params: Vec<String>,
+    id: u64,
+}
+
+/// Where a symbol's book stands relative to its depth stream.
+#[derive(Default)]
+struct Sequence {
+    /// final update id of the snapshot or diff last applied, `None` until synced
+    last_update_id: Option<u64>,
+    /// diffs received while waiting for a usable snapshot, at most [`MAX_BUFFERED`]
+    buffered: VecDeque<DepthUpdate>,
+    fetch: SnapshotFetch<DepthSnapshot>,
+}
+
+enum Step {
+    /// the diff is already contained in the book
+    Stale,
+    /// the diff continues the book
+    Apply,
+    /// diffs between the book and this one are missing
+    Gap,
+}
+
+fn step(last_update_id: u64, update: &DepthUpdate) -> Step {
+    if update.final_update_id <= last_update_id {
+        Step::Stale
+    } else if update.first_update_id <= last_update_id + 1 {
+        Step::Apply
+    } else {
+        Step::Gap
+    }
+}
+
+/// Diff depth streams for a fixed set of symbols, spelled as Binance does, e.g. `BTCUSDT`.
+pub struct BinanceFeed {
+    symbols: Vec<String>,
+    fetch_snapshot: SnapshotSource,
+    sequences: HashMap<String, Sequence>,
+    /// time the delays between failed fetches are measured in
+    clock: Arc<dyn Clock>,
+}
+
+impl BinanceFeed {
+    /// Creates a feed fetching its snapshots from Binance's REST API.
+    pub fn new(symbols: &[String]) -> Self {
+        BinanceFeed::with_snapshot_source(symbols, Arc::new(fetch_depth_snapshot))
+    }
+
+    /// Creates a feed taking its snapshots from `fetch_snapshot` instead of the REST API.
+    /// The snapshots are fetched in the background: a symbol's diffs are buffered until its
+    /// snapshot arrives and handed on with the next diff after that. A failed fetch is
+    /// retried after a delay that doubles with each failure in a row.
+    pub fn with_snapshot_source(symbols: &[String], fetch_snapshot: SnapshotSource) -> Self {
+        BinanceFeed {
+            symbols: symbols.to_vec(),
+            fetch_snapshot,
+            sequences: HashMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Measures the delays between failed fetches with `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn request(&self, method: &str) -> String {
+        let request = Request {
+            method,
+            params: self
+                .symbols
+                .iter()
+                .map(|symbol| format!("{}@depth", symbol.to_lowercase()))
+                .collect(),
+            id: 1,
+        };
+        serde_json::to_string(&request).unwrap()
+    }
+
+    /// Sequences a diff, returning the book updates that became applicable.
+    fn sequence(&mut self, update: DepthUpdate) -> Vec<BookUpdate> {
+        let sequence = self.sequences.entry(update.symbol.clone()).or_default();
+        if let Some(last_update_id) = sequence.last_update_id {
+            match step(last_update_id, &update) {
+                Step::Stale => return Vec::new(),
+                Step::Apply => {
+                    sequence.last_update_id = Some(update.final_update_id);
+                    return vec![update.into_update()];
+                }
+                Step::Gap => {
+                    warn!(
+                        "Missed depth updates for {}: expected {}, got {}; fetching a new snapshot",
+                        update.symbol,
+                        last_update_id + 1,
+                        update.first_update_id
+                    );
+                    sequence.last_update_id = None;
+                }
+            }
+        }
+
+        let symbol = update.symbol.clone();
+        if sequence.buffered.len() == MAX_BUFFERED {
+            warn!(
+                "Dropping the oldest buffered depth update for {}, still waiting for a snapshot",
+                symbol
+            );
+            sequence.buffered.pop_front();
+        }
+        sequence.buffered.push_back(update);
+        let now = self.clock.instant();
+        let mut updates = Vec::new();
+        match sequence.fetch.poll() {
+            Some(Ok(snapshot)) => {
+                updates = sync(&symbol, sequence, snapshot);
+                if updates.is_empty() {
+                    sequence.fetch.failed(now);
+                } else {
+                    sequence.fetch.succeeded();
+                }
+            }
+            Some(Err(error)) => {
+                // the buffered diffs are kept for the next attempt
+                error!("Couldn't fetch depth snapshot for {}. {}", symbol, error);
+                sequence.fetch.failed(now);
+            }
+            None => {}
+        }
+        if sequence.last_update_id.is_none() {
+            let fetch_snapshot = Arc::clone(&self.fetch_snapshot);
+            sequence.fetch.start(now, move || fetch_snapshot(&symbol));
+        }
+        updates
+    }
+
+    /// Blocks until the snapshots being fetched arrived, so that the next diff of their
+    /// symbols is sequenced onto them.
+    #[cfg(test)]
+    fn wait_for_snapshots(&mut self) {
+        for sequence in self.sequences.values_mut() {
+            sequence.fetch.wait();
+        }
+    }
+}
+
+/// Applies the buffered diffs on top of `snapshot`. If the snapshot doesn't connect to
+/// them, the diffs stay buffered for the next attempt and nothing is returned.
+fn sync(symbol: &str, sequence: &mut Sequence, snapshot: DepthSnapshot) -> Vec<BookUpdate> {
+    let mut last_update_id = snapshot.last_update_id;
+    let mut updates = vec![snapshot.into_update(symbol)];
+    let mut buffered = mem::take(&mut sequence.buffered).into_iter();
+    while let Some(update) = buffered.next() {
+        match step(last_update_id, &update) {
+            Step::Stale => {}
+            Step::Apply => {
+                last_update_id = update.final_update_id;
+                updates.push(update.into_update());
+            }
+            Step::Gap => {
+                warn!(
+                    "Depth snapshot for {} ends at {} but buffered updates start at {}",
+                    symbol, last_update_id, update.first_update_id
+                );
+                sequence.buffered = std::iter::once(update).chain(buffered).collect();
+                return Vec::new();
+            }
+        }
+    }
+    sequence.last_update_id = Some(last_update_id);
+    updates
+}
+
+fn fetch_depth_snapshot(symbol: &str) -> Result<DepthSnapshot, String> {
+    ureq::get(DEPTH_SNAPSHOT_URL)
+        .query("symbol", symbol)
+        .query("limit", SNAPSHOT_LIMIT.to_string())
+        .config()
+        .timeout_global(Some(SNAPSHOT_TIMEOUT))
+        .build()
+        .call()
+        .and_then(|mut response| response.body_mut().read_json())
+        .map_err(|error| error.to_string())
+}
+
+impl ExchangeFeed for BinanceFeed {
+    fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    fn subscribe_message(&self) -> String {
+        self.request("SUBSCRIBE")
+    }
+
+    fn unsubscribe_message(&self) -> String {
+        self.request("UNSUBSCRIBE")
+    }
+
+    fn parse(&mut self, text: &str) -> Option<FeedEvent> {
+        match serde_json::from_str::<IncomingMessage>(text) {
+            Ok(IncomingMessage::Depth(update)) => {
+                let updates = self.sequence(update);
+                (!updates.is_empty()).then_some(FeedEvent::Book(updates))
+            }
+            Ok(IncomingMessage::Response(RequestResponse {
+                error: Some(error), ..
+            })) => {
+                error!("Request rejected: {} (code {})", error.msg, error.code);
+                Some(FeedEvent::Rejected(error.msg))
+            }
+            Ok(IncomingMessage::Response(_)) => None,
+            Err(err) => {
+                error!("Error while parsing message: {}", err);
+                None
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.sequences.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::SystemTime;
+
+    /// Captured from `wss://stream.binance.com:9443/ws/btcusdt@depth`.
+    const DEPTH_UPDATE: &str = r#"{"e":"depthUpdate","E":1696613755440,"s":"BTCUSDT","U":40074593588,"u":40074593601,"b":[["27566.01000000","4.69960000"],["27565.97000000","0.00000000"]],"a":[["27566.02000000","2.72504000"]]}"#;
+
+    fn diff(first_update_id: u64, final_update_id: u64) -> String {
+        format!(
+            r#"{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{},"u":{},"b":[["100.0","1.0"]],"a":[]}}"#,
+            first_update_id, final_update_id
+        )
+    }
+
+    /// Feed whose snapshots end at the given update ids, one per fetch.
+    fn feed_with_snapshots(last_update_ids: Vec<u64>) -> (BinanceFeed, Arc<AtomicUsize>) {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&fetches);
+        let source: SnapshotSource = Arc::new(move |_| {
+            let last_update_id = last_update_ids[counter.fetch_add(1, Ordering::SeqCst)];
+            Ok(DepthSnapshot {
+                last_update_id,
+                bids: vec![],
+                asks: vec![],
+            })
+        });
+        let feed = BinanceFeed::with_snapshot_source(&[String::from("BTCUSDT")], source);
+        (feed, fetches)
+    }
+
+    fn kinds(event: Option<FeedEvent>) -> Vec<UpdateKind> {
+        match event {
+            Some(FeedEvent::Book(updates)) => updates.iter().map(|update| update.kind).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_captured_depth_update() {
+        let update: DepthUpdate = serde_json::from_str(DEPTH_UPDATE).unwrap();
+
+        assert_eq!(update.symbol, "BTCUSDT");
+        assert_eq!(update.first_update_id, 40074593588);
+        assert_eq!(update.final_update_id, 40074593601);
+        assert_eq!(update.bids.len(), 2);
+        assert_eq!(update.bids[1].qty, rust_decimal::Decimal::ZERO);
+        assert_eq!(update.asks[0].price, "27566.02".parse().unwrap());
+
+        let update = update.into_update();
+        assert_eq!(update.kind, UpdateKind::Update);
+        assert_eq!(
+            update.timestamp,
+            Some(UNIX_EPOCH + Duration::from_millis(1696613755440))
+        );
+    }
+
+    #[test]
+    fn diffs_are_sequenced_onto_the_rest_snapshot() {
+        let (mut feed, fetches) = feed_with_snapshots(vec![102]);
+
+        // the first diff starts the fetch and waits for it with the next ones
+        assert_eq!(kinds(feed.parse(&diff(95, 100))), vec![]);
+        assert_eq!(kinds(feed.parse(&diff(101, 101))), vec![]);
+        feed.wait_for_snapshots();
+        // both are already contained in the snapshot
+        assert_eq!(
+            kinds(feed.parse(&diff(101, 102))),
+            vec![UpdateKind::Snapshot]
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(kinds(feed.parse(&diff(101, 105))), vec![UpdateKind::Update]);
+        assert_eq!(kinds(feed.parse(&diff(106, 110))), vec![UpdateKind::Update]);
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn buffered_diffs_follow_the_snapshot() {
+        let (mut feed, _) = feed_with_snapshots(vec![102]);
+
+        assert_eq!(kinds(feed.parse(&diff(100, 104))), vec![]);
+        feed.wait_for_snapshots();
+        assert_eq!(
+            kinds(feed.parse(&diff(105, 106))),
+            vec![UpdateKind::Snapshot, UpdateKind::Update, UpdateKind::Update]
+        );
+    }
+
+    #[test]
+    fn gaps_fetch_a_new_snapshot() {
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        let (feed, fetches) = feed_with_snapshots(vec![100, 90, 120]);
+        let mut feed = feed.with_clock(clock.clone());
+
+        feed.parse(&diff(101, 105));
+        feed.wait_for_snapshots();
+        assert_eq!(
+            kinds(feed.parse(&diff(106, 106))),
+            vec![UpdateKind::Snapshot, UpdateKind::Update, UpdateKind::Update]
+        );
+        // 107..=109 went missing
+        assert_eq!(kinds(feed.parse(&diff(110, 115))), vec![]);
+        feed.wait_for_snapshots();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        // the second snapshot is too old to connect, so the next fetch waits a while
+        assert_eq!(kinds(feed.parse(&diff(116, 120))), vec![]);
+        assert_eq!(kinds(feed.parse(&diff(121, 121))), vec![]);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        clock.advance(Duration::from_secs(1));
+        feed.parse(&diff(122, 122));
+        feed.wait_for_snapshots();
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+        // the third one covers the buffer up to 120
+        assert_eq!(
+            kinds(feed.parse(&diff(123, 125))),
+            vec![
+                UpdateKind::Snapshot,
+                UpdateKind::Update,
+                UpdateKind::Update,
+                UpdateKind::Update
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_fetches_are_retried_later_and_diffs_buffered_up_to_a_limit() {
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&fetches);
+        let source: SnapshotSource = Arc::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Err(String::from("timed out"))
+        });
+        let mut feed = BinanceFeed::with_snapshot_source(&[String::from("BTCUSDT")], source)
+            .with_clock(clock.clone());
+
+        feed.parse(&diff(1, 1));
+        feed.wait_for_snapshots();
+        for id in 2..=MAX_BUFFERED as u64 + 10 {
+            assert_eq!(feed.parse(&diff(id, id)), None);
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(feed.sequences["BTCUSDT"].buffered.len(), MAX_BUFFERED);
+        assert_eq!(feed.sequences["BTCUSDT"].buffered[0].first_update_id, 11);
+
+        clock.advance(Duration::from_secs(1));
+        feed.parse(&diff(1011, 1011));
+        feed.wait_for_snapshots();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn subscribes_to_lowercase_depth_streams() {
+        let (feed, _) = feed_with_snapshots(vec![]);
+
+        assert_eq!(
+            feed.subscribe_message(),
+            r#"{"method":"SUBSCRIBE","params":["btcusdt@depth"],"id":1}"#
+        );
+    }
+
+    #[test]
+    fn request_errors_reject_the_subscription() {
+        let (mut feed, _) = feed_with_snapshots(vec![]);
+
+        assert_eq!(feed.parse(r#"{"result":null,"id":1}"#), None);
+        assert_eq!(
+            feed.parse(r#"{"error":{"code":2,"msg":"Invalid request"},"id":1}"#),
+            Some(FeedEvent::Rejected(String::from("Invalid request")))
+        );
+    }
+}
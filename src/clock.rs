@@ -0,0 +1,95 @@
+use std::fmt::Debug;
+use std::time::{Instant, SystemTime};
+
+/// Source of the current time for the time dependent parts of the books, stats and feed
+/// loop, so that tests can control it.
+pub trait Clock: Debug + Send + Sync {
+    /// Wall clock time, compared with the exchange's timestamps.
+    fn now(&self) -> SystemTime;
+
+    /// Monotonic time, for measuring how long something took.
+    fn instant(&self) -> Instant;
+}
+
+/// The system's clocks, used unless another one is given.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::MockClock;
+
+#[cfg(any(test, feature = "test-util"))]
+mod mock {
+    use super::Clock;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant, SystemTime};
+
+    /// Clock standing still until advanced, for deterministic tests of the time dependent
+    /// features. Shared through an `Arc`, so advancing it moves the time of everything it
+    /// was handed to.
+    #[derive(Debug)]
+    pub struct MockClock {
+        start: SystemTime,
+        start_instant: Instant,
+        elapsed: Mutex<Duration>,
+    }
+
+    impl MockClock {
+        /// Starts the clock at the wall clock time `start`.
+        pub fn new(start: SystemTime) -> Self {
+            MockClock {
+                start,
+                start_instant: Instant::now(),
+                elapsed: Mutex::new(Duration::ZERO),
+            }
+        }
+
+        /// Moves the clock forward by `duration`.
+        pub fn advance(&self, duration: Duration) {
+            *self.elapsed.lock().unwrap() += duration;
+        }
+
+        fn elapsed(&self) -> Duration {
+            *self.elapsed.lock().unwrap()
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> SystemTime {
+            self.start + self.elapsed()
+        }
+
+        fn instant(&self) -> Instant {
+            self.start_instant + self.elapsed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn mock_clocks_only_move_when_advanced() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = MockClock::new(start);
+        let instant = clock.instant();
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_millis(1500));
+
+        assert_eq!(clock.now(), start + Duration::from_millis(1500));
+        assert_eq!(clock.instant() - instant, Duration::from_millis(1500));
+    }
+}
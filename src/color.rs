@@ -0,0 +1,104 @@
+use clap::ValueEnum;
+
+/// header colors handed out to the symbols in the order they were given
+const SYMBOL_COLORS: [&str; 4] = ["36", "35", "33", "34"];
+
+const GREEN: &str = "32";
+const RED: &str = "31";
+
+/// When the in-place output modes are colored.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ColorChoice {
+    /// Only when stdout is a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+/// Wraps rendered text in ANSI colors, or leaves it untouched when coloring is off.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    /// Resolves `choice` for an output that is a terminal or not, with `no_color` holding
+    /// the NO_COLOR environment variable.
+    pub fn new(choice: ColorChoice, is_terminal: bool, no_color: Option<&str>) -> Self {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // NO_COLOR only counts when it's non-empty, see https://no-color.org
+            ColorChoice::Auto => is_terminal && no_color.is_none_or(str::is_empty),
+        };
+        Palette { enabled }
+    }
+
+    /// Colors the header of the `index`th symbol, cycling through a few distinct colors.
+    pub fn symbol(&self, index: usize, text: &str) -> String {
+        self.paint(SYMBOL_COLORS[index % SYMBOL_COLORS.len()], text)
+    }
+
+    /// Colors a line of [`Orderbook::format_top_of_book`](hft_orderbook::Orderbook), the
+    /// bid green and the ask red.
+    pub fn top_of_book(&self, line: &str) -> String {
+        let Some((bid, rest)) = line.split_once(" <-> ") else {
+            return String::from(line);
+        };
+        let (ask, rest) = rest
+            .split_once(" spread ")
+            .map_or((rest, None), |(ask, rest)| (ask, Some(rest)));
+        let mut painted = format!("{} <-> {}", self.paint(GREEN, bid), self.paint(RED, ask));
+        if let Some(rest) = rest {
+            painted.push_str(" spread ");
+            painted.push_str(rest);
+        }
+        painted
+    }
+
+    /// Colors a row of [`Orderbook::render_ladder`](hft_orderbook::Orderbook) by its side.
+    pub fn ladder_row(&self, row: &str) -> String {
+        if row.starts_with("BID") {
+            self.paint(GREEN, row)
+        } else if row.starts_with("ASK") {
+            self.paint(RED, row)
+        } else {
+            String::from(row)
+        }
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", color, text)
+        } else {
+            String::from(text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_colors_only_terminals_without_no_color() {
+        assert!(Palette::new(ColorChoice::Auto, true, None).enabled);
+        assert!(Palette::new(ColorChoice::Auto, true, Some("")).enabled);
+        assert!(!Palette::new(ColorChoice::Auto, true, Some("1")).enabled);
+        assert!(!Palette::new(ColorChoice::Auto, false, None).enabled);
+        assert!(Palette::new(ColorChoice::Always, false, Some("1")).enabled);
+        assert!(!Palette::new(ColorChoice::Never, true, None).enabled);
+    }
+
+    #[test]
+    fn top_of_book_colors_each_side() {
+        let palette = Palette::new(ColorChoice::Always, false, None);
+        let line = "BID 100 1 <-> ASK 101 2 spread 1 mid 100.5";
+
+        assert_eq!(
+            palette.top_of_book(line),
+            "\x1b[32mBID 100 1\x1b[0m <-> \x1b[31mASK 101 2\x1b[0m spread 1 mid 100.5"
+        );
+        assert_eq!(Palette::default().top_of_book(line), line);
+    }
+}
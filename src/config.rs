@@ -0,0 +1,101 @@
+use crate::{parse_depth, Args, OutputMode};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings read from the file given with `--config`. Every field is optional, and a
+/// value only applies when the matching flag wasn't passed on the command line.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub symbols: Option<Vec<String>>,
+    pub depth: Option<u32>,
+    pub url: Option<String>,
+    pub output: Option<OutputMode>,
+    pub record: Option<PathBuf>,
+    /// price increment per symbol, `--tick-size` overrides it for every symbol
+    pub tick_sizes: HashMap<String, Decimal>,
+}
+
+impl Config {
+    /// Reads and validates a TOML config file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let config: Config = toml::from_str(&contents).map_err(|error| error.to_string())?;
+        if let Some(depth) = config.depth {
+            parse_depth(&depth.to_string())?;
+        }
+        Ok(config)
+    }
+
+    /// Copies the configured values into `args` for every flag `matches` didn't get from
+    /// the command line.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let from_file = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        if let Some(symbols) = self.symbols.filter(|_| from_file("symbols")) {
+            args.symbols = symbols;
+        }
+        if let Some(depth) = self.depth.filter(|_| from_file("depth")) {
+            args.depth = depth;
+        }
+        if let Some(url) = self.url.filter(|_| from_file("url")) {
+            args.url = Some(url);
+        }
+        if let Some(output) = self.output.filter(|_| from_file("output")) {
+            args.output = output;
+        }
+        if let Some(record) = self.record.filter(|_| from_file("record")) {
+            args.record = Some(record);
+        }
+        args.tick_sizes = self.tick_sizes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    const SAMPLE: &str = r#"
+symbols = ["BTC/USD", "ETH/USD"]
+depth = 100
+url = "wss://example.com/v2"
+output = "json"
+
+[tick_sizes]
+"BTC/USD" = 0.1
+"ETH/USD" = "0.01"
+"#;
+
+    #[test]
+    fn config_round_trips_and_yields_to_command_line_flags() {
+        let config: Config = toml::from_str(SAMPLE).unwrap();
+        let serialized = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&serialized).unwrap(), config);
+
+        let matches = Args::command().get_matches_from(["hft-orderbook", "--depth", "10"]);
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        config.apply(&mut args, &matches);
+
+        assert_eq!(args.symbols, vec!["BTC/USD", "ETH/USD"]);
+        assert_eq!(args.depth, 10);
+        assert_eq!(args.url.as_deref(), Some("wss://example.com/v2"));
+        assert_eq!(args.output, OutputMode::Json);
+        assert_eq!(args.record, None);
+        assert_eq!(args.tick_sizes["ETH/USD"], "0.01".parse().unwrap());
+        assert_eq!(args.tick_sizes["BTC/USD"], "0.1".parse().unwrap());
+    }
+
+    #[test]
+    fn config_rejects_depths_kraken_does_not_offer() {
+        let path = std::env::temp_dir().join("hft-orderbook-invalid-depth.toml");
+        fs::write(&path, "depth = 50").unwrap();
+
+        assert!(Config::load(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+//! Why connecting to the websocket failed, told apart by the error so each case can be
+//! explained and retried or not.
+
+use std::fmt;
+use std::io;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Error;
+
+/// Common reasons a connection attempt fails.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectFailure {
+    /// the host name of the url didn't resolve
+    Dns,
+    /// nothing listens on the url's host and port
+    Refused,
+    /// the TLS handshake failed, usually over the server's certificate
+    Tls,
+    /// the exchange answered the upgrade with 429 Too Many Requests
+    RateLimited,
+    /// the exchange answered the upgrade with another HTTP status
+    Http(u16),
+    Other,
+}
+
+impl ConnectFailure {
+    /// Classifies the error of a connection attempt to `url`. The IO error of a host name
+    /// that didn't resolve has no kind of its own, so the name is looked up again for the
+    /// IO errors that aren't told apart otherwise.
+    pub async fn classify(url: &str, error: &Error) -> Self {
+        match ConnectFailure::from_error(error) {
+            ConnectFailure::Other if matches!(error, Error::Io(_)) && !resolves(url).await => {
+                ConnectFailure::Dns
+            }
+            failure => failure,
+        }
+    }
+
+    fn from_error(error: &Error) -> Self {
+        match error {
+            Error::Http(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                ConnectFailure::RateLimited
+            }
+            Error::Http(response) => ConnectFailure::Http(response.status().as_u16()),
+            Error::Tls(_) => ConnectFailure::Tls,
+            Error::Io(error) if error.kind() == io::ErrorKind::ConnectionRefused => {
+                ConnectFailure::Refused
+            }
+            _ => ConnectFailure::Other,
+        }
+    }
+
+    /// Whether another attempt may succeed. A failed handshake or a rejected upgrade needs
+    /// the url or the TLS settings changed first.
+    pub fn retries(self) -> bool {
+        match self {
+            ConnectFailure::Tls => false,
+            ConnectFailure::Http(status) => !(400..500).contains(&status),
+            _ => true,
+        }
+    }
+}
+
+/// Whether the host of `url` resolves to an address.
+async fn resolves(url: &str) -> bool {
+    let Ok(request) = url.into_client_request() else {
+        return false;
+    };
+    let uri = request.uri();
+    let Some(host) = uri.host() else {
+        return false;
+    };
+    let port = uri
+        .port_u16()
+        .unwrap_or(if uri.scheme_str() == Some("wss") {
+            443
+        } else {
+            80
+        });
+    // IPv6 literals come in brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    tokio::net::lookup_host((host, port))
+        .await
+        .is_ok_and(|mut addresses| addresses.next().is_some())
+}
+
+impl fmt::Display for ConnectFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectFailure::Dns => write!(
+                f,
+                "the host name didn't resolve, check the url and the network"
+            ),
+            ConnectFailure::Refused => write!(
+                f,
+                "the connection was refused, the exchange may be down or the url's port wrong"
+            ),
+            ConnectFailure::Tls => write!(
+                f,
+                "the TLS handshake failed, check --tls-ca or the system's certificates"
+            ),
+            ConnectFailure::RateLimited => write!(f, "the exchange is rate limiting connections"),
+            ConnectFailure::Http(status) => write!(
+                f,
+                "the exchange answered with HTTP {}, check the url",
+                status
+            ),
+            ConnectFailure::Other => write!(f, "the exchange couldn't be reached"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::error::TlsError;
+    use tokio_tungstenite::tungstenite::http::Response;
+
+    fn http_error(status: u16) -> Error {
+        Error::Http(Box::new(
+            Response::builder().status(status).body(None).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn connection_errors_are_classified_by_their_kind() {
+        let Err(tls) = native_tls::Certificate::from_pem(b"not a certificate") else {
+            panic!("garbage was read as a certificate");
+        };
+        let cases = [
+            (
+                Error::Io(io::ErrorKind::ConnectionRefused.into()),
+                ConnectFailure::Refused,
+            ),
+            (Error::Tls(TlsError::from(tls)), ConnectFailure::Tls),
+            (http_error(429), ConnectFailure::RateLimited),
+            (http_error(403), ConnectFailure::Http(403)),
+            (http_error(503), ConnectFailure::Http(503)),
+            (
+                Error::Io(io::ErrorKind::ConnectionReset.into()),
+                ConnectFailure::Other,
+            ),
+        ];
+
+        for (error, failure) in cases {
+            assert_eq!(ConnectFailure::from_error(&error), failure, "{}", error);
+        }
+        assert!(ConnectFailure::RateLimited.retries());
+        assert!(ConnectFailure::Http(503).retries());
+        assert!(!ConnectFailure::Http(403).retries());
+        assert!(!ConnectFailure::Tls.retries());
+    }
+
+    #[tokio::test]
+    async fn io_errors_of_hosts_that_dont_resolve_are_dns_failures() {
+        let reset = Error::Io(io::ErrorKind::ConnectionReset.into());
+        assert_eq!(
+            ConnectFailure::classify("wss://exchange.invalid/v2", &reset).await,
+            ConnectFailure::Dns
+        );
+        assert_eq!(
+            ConnectFailure::classify("ws://127.0.0.1:9/v2", &reset).await,
+            ConnectFailure::Other
+        );
+        assert_eq!(
+            ConnectFailure::classify("wss://exchange.invalid/v2", &http_error(403)).await,
+            ConnectFailure::Http(403)
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use crate::feed::ExchangeFeed;
+use crate::orderbook::Orderbook;
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::{Error, Message, Result};
+use tokio_tungstenite::WebSocketStream;
+
+/// A websocket client together with the feed whose books it is subscribed to.
+pub struct Connection<S, F: ExchangeFeed> {
+    client: WebSocketStream<S>,
+    feed: F,
+    subscribed: bool,
+    /// whether the feed's session messages went out already
+    session_started: bool,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin, F: ExchangeFeed> Connection<S, F> {
+    pub fn new(client: WebSocketStream<S>, feed: F) -> Self {
+        Connection {
+            client,
+            feed,
+            subscribed: false,
+            session_started: false,
+        }
+    }
+
+    pub fn feed(&self) -> &F {
+        &self.feed
+    }
+
+    pub fn feed_mut(&mut self) -> &mut F {
+        &mut self.feed
+    }
+
+    /// Subscribes to the feed's books, preceded by its session messages on the first call.
+    pub async fn subscribe(&mut self) -> Result<()> {
+        if !self.session_started {
+            for message in self.feed.session_messages() {
+                self.client.send(Message::text(message)).await?;
+            }
+            self.session_started = true;
+        }
+        let message = self.feed.subscribe_message();
+        self.client.send(Message::text(message)).await?;
+        self.subscribed = true;
+        Ok(())
+    }
+
+    /// Ends the current subscription, if any.
+    pub async fn unsubscribe(&mut self) -> Result<()> {
+        if !self.subscribed {
+            return Ok(());
+        }
+        self.subscribed = false;
+        let message = self.feed.unsubscribe_message();
+        self.client.send(Message::text(message)).await
+    }
+
+    /// Unsubscribes from the current symbols, replaces `books` with fresh ones for the new
+    /// feed and subscribes, so the next message for each book is a snapshot.
+    pub async fn swap_subscription(
+        &mut self,
+        feed: F,
+        books: &mut HashMap<String, Orderbook>,
+        new_book: impl Fn(&str) -> Orderbook,
+    ) -> Result<()> {
+        self.unsubscribe().await?;
+        self.feed = feed;
+        self.rebuild(books, new_book);
+        self.subscribe().await
+    }
+
+    /// Renews the current subscription to force fresh snapshots.
+    pub async fn resubscribe(
+        &mut self,
+        books: &mut HashMap<String, Orderbook>,
+        new_book: impl Fn(&str) -> Orderbook,
+    ) -> Result<()> {
+        self.unsubscribe().await?;
+        self.feed.reset();
+        self.rebuild(books, new_book);
+        self.subscribe().await
+    }
+
+    fn rebuild(
+        &self,
+        books: &mut HashMap<String, Orderbook>,
+        new_book: impl Fn(&str) -> Orderbook,
+    ) {
+        books.clear();
+        books.extend(
+            self.feed
+                .symbols()
+                .iter()
+                .map(|symbol| (symbol.clone(), new_book(symbol))),
+        );
+    }
+
+    /// Waits for the next message, `None` once the connection is closed.
+    pub async fn recv_message(&mut self) -> Option<Result<Message>> {
+        self.client.next().await
+    }
+
+    pub async fn send_message(&mut self, message: Message) -> Result<()> {
+        self.client.send(message).await
+    }
+
+    /// Sends a close frame and waits up to `timeout` for the server's answer to it,
+    /// dropping whatever else arrives in the meantime. A connection the server closed
+    /// already is left as it is.
+    pub async fn close(&mut self, timeout: Duration) -> Result<()> {
+        match self.client.send(Message::Close(None)).await {
+            Err(Error::ConnectionClosed | Error::AlreadyClosed) => return Ok(()),
+            sent => sent?,
+        }
+        let answered = async {
+            while let Some(message) = self.client.next().await {
+                if let Message::Close(_) = message? {
+                    break;
+                }
+            }
+            Ok(())
+        };
+        match tokio::time::timeout(timeout, answered).await {
+            Ok(closed) => closed,
+            Err(_) => Err(Error::Io(io::ErrorKind::TimedOut.into())),
+        }
+    }
+}
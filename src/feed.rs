@@ -0,0 +1,165 @@
+use crate::orderbook::PriceLevel;
+use rust_decimal::Decimal;
+use std::time::SystemTime;
+
+/// Whether a [`BookUpdate`] replaces the book or amends it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateKind {
+    Snapshot,
+    Update,
+}
+
+/// Venue-neutral change to one symbol's book. Levels whose quantity falls below the
+/// book's epsilon delete the level at that price.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookUpdate {
+    pub symbol: String,
+    pub kind: UpdateKind,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    /// Kraken-style CRC32 of the book after applying this update, if the venue sends one
+    pub checksum: Option<u32>,
+    /// time at which the venue produced the update
+    pub timestamp: Option<SystemTime>,
+}
+
+/// Side of the taker, i.e. whether the trade bought the ask or sold into the bid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+/// Venue-neutral execution of one symbol.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trade {
+    pub symbol: String,
+    pub side: TradeSide,
+    pub price: Decimal,
+    pub qty: Decimal,
+    /// time at which the venue matched the trade
+    pub timestamp: Option<SystemTime>,
+}
+
+/// The part of a venue message the feed loop has to act on.
+#[derive(Debug, PartialEq)]
+pub enum FeedEvent {
+    /// book changes, one per symbol the message touched
+    Book(Vec<BookUpdate>),
+    /// executed trades, oldest first
+    Trades(Vec<Trade>),
+    /// the venue refused the subscription for the given reason; reconnecting won't help
+    Rejected(String),
+}
+
+/// Translates between a venue's websocket protocol and the crate's book types.
+pub trait ExchangeFeed {
+    /// Symbols the feed subscribes to, as the venue spells them.
+    fn symbols(&self) -> &[String];
+
+    /// Text message that subscribes to the books of all [`ExchangeFeed::symbols`].
+    fn subscribe_message(&self) -> String;
+
+    /// Text message that ends the subscription made with [`ExchangeFeed::subscribe_message`].
+    fn unsubscribe_message(&self) -> String;
+
+    /// Text messages sent once per connection, before the first book subscription, for
+    /// subscriptions that outlive resubscribing to the books.
+    fn session_messages(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Parses one text message. Messages that need no action, like heartbeats or
+    /// acknowledgements, as well as malformed ones yield `None`.
+    fn parse(&mut self, text: &str) -> Option<FeedEvent>;
+
+    /// Forgets any state tied to the current books, called whenever they're rebuilt.
+    fn reset(&mut self) {}
+}
+
+impl<F: ExchangeFeed + ?Sized> ExchangeFeed for Box<F> {
+    fn symbols(&self) -> &[String] {
+        (**self).symbols()
+    }
+
+    fn subscribe_message(&self) -> String {
+        (**self).subscribe_message()
+    }
+
+    fn unsubscribe_message(&self) -> String {
+        (**self).unsubscribe_message()
+    }
+
+    fn session_messages(&self) -> Vec<String> {
+        (**self).session_messages()
+    }
+
+    fn parse(&mut self, text: &str) -> Option<FeedEvent> {
+        (**self).parse(text)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{route, Orderbook};
+    use std::collections::HashMap;
+
+    /// Venue whose messages are `<symbol> <bid price> <ask price>` lines.
+    struct MockFeed {
+        symbols: Vec<String>,
+    }
+
+    impl ExchangeFeed for MockFeed {
+        fn symbols(&self) -> &[String] {
+            &self.symbols
+        }
+
+        fn subscribe_message(&self) -> String {
+            format!("subscribe {}", self.symbols.join(","))
+        }
+
+        fn unsubscribe_message(&self) -> String {
+            format!("unsubscribe {}", self.symbols.join(","))
+        }
+
+        fn parse(&mut self, text: &str) -> Option<FeedEvent> {
+            let mut fields = text.split(' ');
+            let symbol = fields.next()?;
+            let mut level = || {
+                Some(PriceLevel {
+                    price: fields.next()?.parse().ok()?,
+                    qty: "1".parse().unwrap(),
+                })
+            };
+            let (bid, ask) = (level()?, level()?);
+            Some(FeedEvent::Book(vec![BookUpdate {
+                symbol: String::from(symbol),
+                kind: UpdateKind::Snapshot,
+                bids: vec![bid],
+                asks: vec![ask],
+                checksum: None,
+                timestamp: None,
+            }]))
+        }
+    }
+
+    #[test]
+    fn books_are_built_from_any_feed() {
+        let mut feed = MockFeed {
+            symbols: vec![String::from("BTC/USD")],
+        };
+        let mut books = HashMap::from([(String::from("BTC/USD"), Orderbook::new("BTC/USD"))]);
+
+        let Some(FeedEvent::Book(updates)) = feed.parse("BTC/USD 100.5 101") else {
+            panic!("mock message wasn't parsed");
+        };
+        assert_eq!(route(&mut books, updates), vec!["BTC/USD"]);
+        assert_eq!(books["BTC/USD"].spread(), Some("0.5".parse().unwrap()));
+        assert_eq!(feed.parse("garbage"), None);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::orderbook::{Orderbook, PriceLevel};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// Latest top of book per symbol, answered on request for dashboards polling the books.
+/// Shared between the feed loop, which updates it, and the HTTP server reading it.
+#[derive(Default)]
+pub struct BookEndpoint {
+    books: Mutex<BTreeMap<String, TopOfBook>>,
+}
+
+/// Top of book of one symbol as served by `/book`, prices and quantities as decimal strings;
+/// values are `null` while a side is empty.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TopOfBook {
+    pub best_bid: Option<PriceLevel>,
+    pub best_ask: Option<PriceLevel>,
+    pub spread: Option<Decimal>,
+    pub mid: Option<Decimal>,
+    /// milliseconds since the unix epoch of the book's last update
+    pub ts: Option<u64>,
+}
+
+impl BookEndpoint {
+    pub fn new() -> Self {
+        BookEndpoint::default()
+    }
+
+    /// Refreshes the top of book of every symbol in `updated`.
+    pub fn observe(&self, books: &HashMap<String, Orderbook>, updated: &[String]) {
+        let mut tops = self.books.lock().unwrap();
+        for orderbook in updated.iter().filter_map(|symbol| books.get(symbol)) {
+            let top = TopOfBook {
+                best_bid: orderbook.best_bid(),
+                best_ask: orderbook.best_ask(),
+                spread: orderbook.spread(),
+                mid: orderbook.mid_price(),
+                ts: orderbook.last_update().map(|last_update| {
+                    last_update
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64
+                }),
+            };
+            tops.insert(orderbook.symbol().to_string(), top);
+        }
+    }
+
+    /// Answers a GET of `path` with a status code and a JSON body: the top of book keyed by
+    /// symbol for `/book`, an error for anything else.
+    pub fn handle(&self, path: &str) -> (u16, String) {
+        match path.split('?').next() {
+            Some("/book") => {
+                let tops = self.books.lock().unwrap();
+                (200, serde_json::to_string(&*tops).unwrap())
+            }
+            _ => (404, String::from(r#"{"error":"not found"}"#)),
+        }
+    }
+}
+
+/// Serves `endpoint` on `addr` from a background thread until the process exits.
+#[cfg(feature = "http")]
+pub fn serve_book_endpoint(
+    addr: &str,
+    endpoint: std::sync::Arc<BookEndpoint>,
+) -> Result<std::thread::JoinHandle<()>, Box<dyn std::error::Error + Send + Sync>> {
+    let server = tiny_http::Server::http(addr)?;
+    let content_type = tiny_http::Header::from_bytes("Content-Type", "application/json").unwrap();
+    Ok(std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let (status, body) = endpoint.handle(request.url());
+            let response = tiny_http::Response::from_string(body)
+                .with_status_code(status)
+                .with_header(content_type.clone());
+            if let Err(error) = request.respond(response) {
+                log::warn!("Couldn't answer book request. {}", error);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::{BookUpdate, UpdateKind};
+
+    #[test]
+    fn book_requests_return_the_top_of_every_book() {
+        let level = |price: &str, qty: &str| PriceLevel {
+            price: price.parse().unwrap(),
+            qty: qty.parse().unwrap(),
+        };
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&BookUpdate {
+            symbol: String::from("BTC/USD"),
+            kind: UpdateKind::Snapshot,
+            bids: vec![level("100.0", "1"), level("99.5", "3")],
+            asks: vec![level("100.5", "2")],
+            checksum: None,
+            timestamp: None,
+        });
+        let books = HashMap::from([
+            (String::from("BTC/USD"), orderbook),
+            (String::from("ETH/USD"), Orderbook::new("ETH/USD")),
+        ]);
+        let endpoint = BookEndpoint::new();
+        assert_eq!(endpoint.handle("/book"), (200, String::from("{}")));
+
+        endpoint.observe(&books, &[String::from("BTC/USD"), String::from("ETH/USD")]);
+        let (status, body) = endpoint.handle("/book?pretty=false");
+
+        assert_eq!(status, 200);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            json["BTC/USD"]["best_bid"],
+            serde_json::json!(["100.0", "1"])
+        );
+        assert_eq!(
+            json["BTC/USD"]["best_ask"],
+            serde_json::json!(["100.5", "2"])
+        );
+        assert_eq!(json["BTC/USD"]["spread"], "0.5");
+        assert_eq!(json["BTC/USD"]["mid"], "100.25");
+        assert!(json["BTC/USD"]["ts"].is_u64());
+        assert!(json["ETH/USD"]["best_bid"].is_null());
+        assert_eq!(endpoint.handle("/metrics").0, 404);
+    }
+}
@@ -0,0 +1,120 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
+
+/// lines a subscriber may fall behind by before it's dropped as too slow
+#[cfg(unix)]
+const SUBSCRIBER_BACKLOG: usize = 1024;
+
+/// Hands lines to every process connected to a Unix domain socket. Each subscriber is
+/// written to from its own thread through a bounded queue, so publishing never blocks:
+/// subscribers that fall too far behind or disconnect are dropped.
+pub struct IpcPublisher {
+    path: PathBuf,
+    subscribers: Arc<Mutex<Vec<SyncSender<Arc<str>>>>>,
+}
+
+impl IpcPublisher {
+    /// Listens on a socket at `path`, replacing a stale socket left behind by an earlier
+    /// run, and accepts subscribers from a background thread until the process exits.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixListener;
+
+        if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let subscribers: Arc<Mutex<Vec<SyncSender<Arc<str>>>>> = Arc::default();
+        let accepted = Arc::clone(&subscribers);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => accepted.lock().unwrap().push(spawn_writer(stream)),
+                    Err(error) => log::warn!("Couldn't accept IPC subscriber. {}", error),
+                }
+            }
+        });
+        Ok(IpcPublisher {
+            path: path.to_path_buf(),
+            subscribers,
+        })
+    }
+
+    /// Queues `line` for every subscriber, dropping the ones whose queue is full.
+    pub fn publish(&self, line: &str) {
+        let line: Arc<str> = Arc::from(line);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.try_send(Arc::clone(&line)).is_ok());
+    }
+}
+
+impl Drop for IpcPublisher {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Writes the lines sent to the returned queue to `stream` until it or the queue closes.
+#[cfg(unix)]
+fn spawn_writer(mut stream: std::os::unix::net::UnixStream) -> SyncSender<Arc<str>> {
+    use std::io::Write;
+
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<Arc<str>>(SUBSCRIBER_BACKLOG);
+    std::thread::spawn(move || {
+        for line in receiver {
+            if writeln!(stream, "{}", line).is_err() {
+                break;
+            }
+        }
+    });
+    sender
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn subscribers_receive_published_lines() {
+        let path = std::env::temp_dir().join("hft-orderbook-ipc-test.sock");
+        let publisher = IpcPublisher::bind(&path).unwrap();
+        let client = UnixStream::connect(&path).unwrap();
+
+        // the subscriber is registered by the accept thread shortly after connecting
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while publisher.subscribers.lock().unwrap().is_empty() {
+            assert!(Instant::now() < deadline, "subscriber wasn't accepted");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        publisher.publish(r#"{"symbol":"BTC/USD"}"#);
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"symbol\":\"BTC/USD\"}\n");
+
+        drop(publisher);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn full_subscribers_are_dropped() {
+        let (sender, _receiver) = std::sync::mpsc::sync_channel(1);
+        let publisher = IpcPublisher {
+            path: std::env::temp_dir().join("hft-orderbook-ipc-unbound.sock"),
+            subscribers: Arc::new(Mutex::new(vec![sender])),
+        };
+
+        publisher.publish("first");
+        assert_eq!(publisher.subscribers.lock().unwrap().len(), 1);
+        publisher.publish("second");
+        assert!(publisher.subscribers.lock().unwrap().is_empty());
+    }
+}
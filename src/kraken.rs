@@ -0,0 +1,862 @@
+//! Kraken's v2 websocket protocol: request and message schemas and the [`KrakenFeed`]
+//! adapter that maps them onto the venue-neutral [`BookUpdate`].
+
+use crate::clock::{Clock, SystemClock};
+use crate::feed::{BookUpdate, ExchangeFeed, FeedEvent, UpdateKind};
+use crate::orderbook::PriceLevel;
+use crate::snapshot_fetch::SnapshotFetch;
+use log::{error, info, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+mod auth;
+mod rest;
+mod ticker;
+mod trade;
+
+pub use auth::{fetch_websockets_token, sign};
+pub use rest::{fetch_depth_snapshot, parse_depth_snapshot, DepthSnapshot};
+pub use ticker::{
+    get_ticker_subscription, get_ticker_unsubscription, TickerEntry, TickerMessage,
+    TickerSubscription, TickerSubscriptionParams,
+};
+pub use trade::{
+    get_trade_subscription, TradeEntry, TradeMessage, TradeSubscription, TradeSubscriptionParams,
+};
+
+/// Fetches the REST depth snapshot of a symbol at the given depth, called on a thread of
+/// its own.
+pub type SnapshotSource = Arc<dyn Fn(&str, u32) -> Result<DepthSnapshot, String> + Send + Sync>;
+
+/// depth served by the ticker channel instead of the book channel, which starts at 10
+pub const TICKER_DEPTH: u32 = 1;
+
+/// Most updates kept per symbol while waiting for a REST snapshot; older ones are dropped
+/// beyond.
+const MAX_BUFFERED: usize = 1000;
+
+/// A book waiting for its REST snapshot.
+#[derive(Default)]
+struct Seeding {
+    /// updates received meanwhile, at most [`MAX_BUFFERED`]
+    buffered: VecDeque<BookUpdate>,
+    fetch: SnapshotFetch<DepthSnapshot>,
+}
+
+/// Book channel subscription for a fixed set of symbols and depth. At [`TICKER_DEPTH`] the
+/// feed subscribes to the ticker instead and turns its best bid and ask into single level
+/// snapshots, which carry no checksum.
+pub struct KrakenFeed {
+    symbols: Vec<String>,
+    depth: u32,
+    /// token from [`fetch_websockets_token`] and the private channels to subscribe with it
+    private: Option<(String, Vec<String>)>,
+    /// whether the trade channel of the symbols is subscribed as well
+    trades: bool,
+    /// source of the snapshots seeding the books instead of the websocket's
+    fetch_snapshot: Option<SnapshotSource>,
+    /// time of the REST snapshot each seeded book was built from
+    seeded: HashMap<String, Option<SystemTime>>,
+    /// books whose REST snapshot is being fetched
+    seeding: HashMap<String, Seeding>,
+    /// time the delays between failed fetches are measured in
+    clock: Arc<dyn Clock>,
+}
+
+impl KrakenFeed {
+    pub fn new(symbols: &[String], depth: u32) -> Self {
+        KrakenFeed {
+            symbols: symbols.to_vec(),
+            depth,
+            private: None,
+            trades: false,
+            fetch_snapshot: None,
+            seeded: HashMap::new(),
+            seeding: HashMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Seeds the books from Kraken's REST `Depth` endpoint and subscribes without a
+    /// websocket snapshot, see [`KrakenFeed::with_snapshot_source`].
+    pub fn with_rest_snapshots(self) -> Self {
+        self.with_snapshot_source(Arc::new(fetch_depth_snapshot))
+    }
+
+    /// Subscribes without a websocket snapshot and seeds each book from `fetch_snapshot`
+    /// once its subscription is acknowledged. The snapshots are fetched in the background:
+    /// a book's updates are buffered until its snapshot arrives and handed on after it with
+    /// the next update, dropping the ones the snapshot already contains by their
+    /// timestamps. A failed fetch is retried after a delay that doubles with each failure
+    /// in a row.
+    pub fn with_snapshot_source(mut self, fetch_snapshot: SnapshotSource) -> Self {
+        self.fetch_snapshot = Some(fetch_snapshot);
+        self
+    }
+
+    /// Measures the delays between failed snapshot fetches with `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Also subscribes to the given private channels, such as `executions` or `balances`,
+    /// authenticated with `token`. Private channels are only served on the
+    /// `ws-auth.kraken.com` endpoint.
+    pub fn with_private_channels(mut self, token: String, channels: &[String]) -> Self {
+        self.private = Some((token, channels.to_vec()));
+        self
+    }
+
+    /// Also subscribes to the trades of the symbols, parsed into [`FeedEvent::Trades`].
+    pub fn with_trades(mut self) -> Self {
+        self.trades = true;
+        self
+    }
+}
+
+impl ExchangeFeed for KrakenFeed {
+    fn symbols(&self) -> &[String] {
+        &self.symbols
+    }
+
+    fn subscribe_message(&self) -> String {
+        if self.depth == TICKER_DEPTH {
+            return serde_json::to_string(&get_ticker_subscription(&self.symbols)).unwrap();
+        }
+        let mut subscription = get_subscription(&self.symbols, self.depth);
+        if self.fetch_snapshot.is_some() {
+            subscription.params.snapshot = Some(false);
+        }
+        serde_json::to_string(&subscription).unwrap()
+    }
+
+    fn unsubscribe_message(&self) -> String {
+        if self.depth == TICKER_DEPTH {
+            return serde_json::to_string(&get_ticker_unsubscription(&self.symbols)).unwrap();
+        }
+        serde_json::to_string(&get_unsubscription(&self.symbols, self.depth)).unwrap()
+    }
+
+    fn session_messages(&self) -> Vec<String> {
+        let mut messages = Vec::new();
+        if self.trades {
+            messages.push(serde_json::to_string(&get_trade_subscription(&self.symbols)).unwrap());
+        }
+        if let Some((token, channels)) = &self.private {
+            messages.extend(channels.iter().map(|channel| {
+                serde_json::to_string(&get_private_subscription(channel, token)).unwrap()
+            }));
+        }
+        messages
+    }
+
+    fn parse(&mut self, text: &str) -> Option<FeedEvent> {
+        match parse_message(text) {
+            Ok(Some(IncomingMessage::Response(response))) => {
+                let event = handle_response(&response);
+                let acknowledged = response.method == "subscribe"
+                    && response.success
+                    && self.depth != TICKER_DEPTH;
+                // only the book's acknowledgement seeds it, not the trades' for the same symbol
+                let book_symbol = response
+                    .result
+                    .filter(|result| result.channel.as_deref() == Some("book"))
+                    .and_then(|result| result.symbol);
+                if let Some(symbol) = book_symbol.filter(|_| acknowledged) {
+                    // a resubscribed book starts over from a new snapshot
+                    if self.seeded.remove(&symbol).is_some() {
+                        self.seeding.remove(&symbol);
+                    }
+                    self.start_seed(&symbol);
+                }
+                event
+            }
+            Ok(Some(IncomingMessage::Channel(message))) if self.fetch_snapshot.is_some() => {
+                let updates: Vec<BookUpdate> = message
+                    .into_updates()
+                    .into_iter()
+                    .flat_map(|update| self.reconcile(update))
+                    .collect();
+                (!updates.is_empty()).then_some(FeedEvent::Book(updates))
+            }
+            Ok(Some(IncomingMessage::Channel(message))) => {
+                let updates = message.into_updates();
+                (!updates.is_empty()).then_some(FeedEvent::Book(updates))
+            }
+            Ok(Some(IncomingMessage::Ticker(message))) => {
+                let updates = message.into_updates();
+                (!updates.is_empty()).then_some(FeedEvent::Book(updates))
+            }
+            Ok(Some(IncomingMessage::Trades(message))) => {
+                let trades = message.into_trades();
+                (!trades.is_empty()).then_some(FeedEvent::Trades(trades))
+            }
+            Ok(None) => None,
+            Err(err) => {
+                error!("Error while parsing message: {}", err);
+                None
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.seeded.clear();
+        self.seeding.clear();
+    }
+}
+
+impl KrakenFeed {
+    /// Starts fetching the REST snapshot of `symbol`, unless one is in flight already or
+    /// the last one failed too recently.
+    fn start_seed(&mut self, symbol: &str) {
+        let Some(fetch_snapshot) = &self.fetch_snapshot else {
+            return;
+        };
+        let fetch_snapshot = Arc::clone(fetch_snapshot);
+        let depth = self.depth;
+        let seeding = self.seeding.entry(String::from(symbol)).or_default();
+        let symbol = String::from(symbol);
+        seeding
+            .fetch
+            .start(self.clock.instant(), move || fetch_snapshot(&symbol, depth));
+    }
+
+    /// The REST snapshot of `symbol` followed by the updates buffered for it once its fetch
+    /// is done, nothing before or if the fetch failed.
+    fn poll_seed(&mut self, symbol: &str) -> Vec<BookUpdate> {
+        let Some(seeding) = self.seeding.get_mut(symbol) else {
+            return Vec::new();
+        };
+        match seeding.fetch.poll() {
+            Some(Ok(snapshot)) => {
+                info!("Seeded {} from a REST snapshot", symbol);
+                let buffered = self
+                    .seeding
+                    .remove(symbol)
+                    .map(|seeding| seeding.buffered)
+                    .unwrap_or_default();
+                self.seeded.insert(String::from(symbol), snapshot.timestamp);
+                let seeded_at = snapshot.timestamp;
+                let mut updates = vec![BookUpdate {
+                    symbol: String::from(symbol),
+                    kind: UpdateKind::Snapshot,
+                    bids: snapshot.bids,
+                    asks: snapshot.asks,
+                    checksum: None,
+                    timestamp: seeded_at,
+                }];
+                updates.extend(
+                    buffered
+                        .into_iter()
+                        .filter(|update| !contained(update, seeded_at)),
+                );
+                updates
+            }
+            Some(Err(error)) => {
+                // the buffered updates are kept for the next attempt
+                error!("Couldn't fetch a REST snapshot for {}. {}", symbol, error);
+                seeding.fetch.failed(self.clock.instant());
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Puts a websocket update in line with the REST snapshot its book was seeded from,
+    /// buffering it while the snapshot is being fetched: updates the snapshot already
+    /// contains are dropped. Updates set absolute quantities, so the ones made while the
+    /// snapshot was served are safe to apply again.
+    fn reconcile(&mut self, update: BookUpdate) -> Vec<BookUpdate> {
+        if let Some(&seeded_at) = self.seeded.get(&update.symbol) {
+            return if contained(&update, seeded_at) {
+                Vec::new()
+            } else {
+                vec![update]
+            };
+        }
+        let symbol = update.symbol.clone();
+        let seeding = self.seeding.entry(symbol.clone()).or_default();
+        if seeding.buffered.len() == MAX_BUFFERED {
+            warn!(
+                "Dropping the oldest buffered book update for {}, still waiting for a REST snapshot",
+                symbol
+            );
+            seeding.buffered.pop_front();
+        }
+        seeding.buffered.push_back(update);
+        let updates = self.poll_seed(&symbol);
+        if !self.seeded.contains_key(&symbol) {
+            self.start_seed(&symbol);
+        }
+        updates
+    }
+
+    /// Blocks until the snapshots being fetched arrived, so that the next update of their
+    /// symbols is put in line with them.
+    #[cfg(test)]
+    fn wait_for_snapshots(&mut self) {
+        for seeding in self.seeding.values_mut() {
+            seeding.fetch.wait();
+        }
+    }
+}
+
+/// Whether `update` was made no later than a snapshot taken at `seeded_at`.
+fn contained(update: &BookUpdate, seeded_at: Option<SystemTime>) -> bool {
+    update
+        .timestamp
+        .zip(seeded_at)
+        .is_some_and(|(at, seeded_at)| at <= seeded_at)
+}
+
+/// Just enough of a message to tell what it is before parsing the rest.
+#[derive(Deserialize)]
+struct Envelope {
+    method: Option<String>,
+    channel: Option<String>,
+}
+
+/// Parses the messages the feed acts on: method responses, book, ticker and trade channel
+/// data. Anything else, like heartbeats, status updates, pongs or other channels, is
+/// `Ok(None)`, so only messages that claim to be one of the former but don't match its
+/// schema are errors.
+pub fn parse_message(text: &str) -> Result<Option<IncomingMessage>, serde_json::Error> {
+    let envelope: Envelope = serde_json::from_str(text)?;
+    match (envelope.method.as_deref(), envelope.channel.as_deref()) {
+        (Some("pong"), _) => Ok(None),
+        (Some(_), _) => {
+            serde_json::from_str(text).map(|response| Some(IncomingMessage::Response(response)))
+        }
+        (None, Some("book")) => {
+            serde_json::from_str(text).map(|message| Some(IncomingMessage::Channel(message)))
+        }
+        (None, Some("ticker")) => {
+            serde_json::from_str(text).map(|message| Some(IncomingMessage::Ticker(message)))
+        }
+        (None, Some("trade")) => {
+            serde_json::from_str(text).map(|message| Some(IncomingMessage::Trades(message)))
+        }
+        (None, _) => Ok(None),
+    }
+}
+
+/// Logs the exchange's answer to a request; only a rejected subscription needs action.
+fn handle_response(response: &MethodResponse) -> Option<FeedEvent> {
+    let subject = response
+        .result
+        .as_ref()
+        .and_then(|result| result.symbol.as_deref().or(result.channel.as_deref()))
+        .unwrap_or("book");
+    let reason = response.error.as_deref().unwrap_or("no reason given");
+    match (response.method.as_str(), response.success) {
+        ("subscribe", true) => info!("Subscribed to {}", subject),
+        ("subscribe", false) => {
+            error!("Subscription rejected: {}", reason);
+            return Some(FeedEvent::Rejected(String::from(reason)));
+        }
+        (method, true) => info!("{} succeeded for {}", method, subject),
+        (method, false) => error!("{} failed: {}", method, reason),
+    }
+    None
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Subscription {
+    pub method: String,
+    pub params: SubscriptionParams,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SubscriptionParams {
+    pub channel: String,
+    pub symbol: Vec<String>,
+    pub depth: u32,
+    /// whether Kraken starts with a snapshot, which it does unless this is `false`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<bool>,
+}
+
+/// Subscription to a private channel such as `executions`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PrivateSubscription {
+    pub method: String,
+    pub params: PrivateSubscriptionParams,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PrivateSubscriptionParams {
+    pub channel: String,
+    /// websocket token from [`fetch_websockets_token`]
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OrderbookMessage {
+    pub data: Option<Vec<OrderbookEntry>>,
+    #[serde(rename = "type")]
+    pub type_name: Option<String>,
+    pub channel: Option<String>,
+}
+
+impl OrderbookMessage {
+    /// Converts every entry of a book channel message into a [`BookUpdate`]; a message
+    /// without data has none.
+    pub fn into_updates(self) -> Vec<BookUpdate> {
+        let kind = match self.type_name.as_deref() {
+            Some("snapshot") => UpdateKind::Snapshot,
+            Some("update") => UpdateKind::Update,
+            _ => return Vec::new(),
+        };
+        let Some(data) = self.data else {
+            warn!("Received book message without data");
+            return Vec::new();
+        };
+        data.into_iter()
+            .filter_map(|entry| entry.into_update(kind))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OrderbookEntry {
+    pub symbol: Option<String>,
+    pub bids: Option<Vec<Entry>>,
+    pub asks: Option<Vec<Entry>>,
+    pub checksum: Option<u32>,
+    /// RFC3339 time at which the exchange produced the entry
+    pub timestamp: Option<String>,
+}
+
+impl OrderbookEntry {
+    /// Parses the exchange timestamp, `None` if it's missing or malformed.
+    pub fn parsed_timestamp(&self) -> Option<SystemTime> {
+        self.timestamp.as_deref().and_then(parse_timestamp)
+    }
+
+    /// Converts the entry, `None` if it doesn't name its symbol.
+    pub fn into_update(self, kind: UpdateKind) -> Option<BookUpdate> {
+        let timestamp = self.parsed_timestamp();
+        let Some(symbol) = self.symbol.as_deref().map(normalize_symbol) else {
+            warn!("Received book entry without a symbol");
+            return None;
+        };
+        let levels = |entries: Option<Vec<Entry>>| {
+            entries
+                .unwrap_or_default()
+                .into_iter()
+                .map(PriceLevel::from)
+                .collect()
+        };
+        Some(BookUpdate {
+            symbol,
+            kind,
+            bids: levels(self.bids),
+            asks: levels(self.asks),
+            checksum: self.checksum,
+            timestamp,
+        })
+    }
+}
+
+/// Spells a trading pair the way Kraken does, uppercase with a slash between base and quote,
+/// so `btc/usd`, `BTC-USD` and ` btc_usd ` all become `BTC/USD`.
+pub fn normalize_symbol(symbol: &str) -> String {
+    symbol.trim().to_uppercase().replace(['-', '_', ':'], "/")
+}
+
+/// Parses an RFC3339 timestamp as sent by Kraken, e.g. `2023-10-06T17:35:55.440295Z`.
+pub fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    humantime::parse_rfc3339(timestamp).ok()
+}
+
+/// A text message Kraken sends on the websocket; see [`parse_message`] for the ones the
+/// feed looks at.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum IncomingMessage {
+    /// Acknowledgement or rejection of a request such as `subscribe`
+    Response(MethodResponse),
+    /// Data pushed on a channel, including heartbeats and status updates
+    Channel(OrderbookMessage),
+    /// Best bid and ask pushed on the ticker channel
+    Ticker(TickerMessage),
+    /// Trades pushed on the trade channel
+    Trades(TradeMessage),
+}
+
+/// Kraken's answer to a `subscribe` or `unsubscribe` request.
+#[derive(Serialize, Deserialize)]
+pub struct MethodResponse {
+    pub method: String,
+    pub success: bool,
+    /// reason given by the exchange when `success` is false
+    pub error: Option<String>,
+    pub result: Option<SubscriptionResult>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubscriptionResult {
+    pub channel: Option<String>,
+    pub symbol: Option<String>,
+    pub depth: Option<u32>,
+    pub snapshot: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub price: Decimal,
+    pub qty: Decimal,
+}
+
+impl From<Entry> for PriceLevel {
+    fn from(entry: Entry) -> Self {
+        PriceLevel {
+            price: entry.price,
+            qty: entry.qty,
+        }
+    }
+}
+
+/// Builds the book channel subscription Kraken expects for the given symbols and depth.
+pub fn get_subscription(symbols: &[String], depth: u32) -> Subscription {
+    book_request("subscribe", symbols, depth)
+}
+
+/// Builds the message that ends a book subscription made with [`get_subscription`].
+pub fn get_unsubscription(symbols: &[String], depth: u32) -> Subscription {
+    book_request("unsubscribe", symbols, depth)
+}
+
+/// Builds the subscription to a private channel, authenticated with a websocket token.
+pub fn get_private_subscription(channel: &str, token: &str) -> PrivateSubscription {
+    PrivateSubscription {
+        method: String::from("subscribe"),
+        params: PrivateSubscriptionParams {
+            channel: String::from(channel),
+            token: String::from(token),
+        },
+    }
+}
+
+fn book_request(method: &str, symbols: &[String], depth: u32) -> Subscription {
+    Subscription {
+        method: String::from(method),
+        params: SubscriptionParams {
+            channel: String::from("book"),
+            symbol: symbols.to_vec(),
+            depth,
+            snapshot: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::test_log::logged;
+    use log::Level;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn symbols_are_normalized_to_kraken_pairs() {
+        for symbol in [
+            "BTC/USD",
+            "btc/usd",
+            "BTC-USD",
+            "btc_usd",
+            "Btc:Usd",
+            " btc/usd\n",
+        ] {
+            assert_eq!(normalize_symbol(symbol), "BTC/USD", "{:?}", symbol);
+        }
+        assert_eq!(normalize_symbol("eth/eur"), "ETH/EUR");
+    }
+
+    #[test]
+    fn entries_are_routed_by_their_normalized_symbol() {
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10);
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"btc/usd","bids":[],"asks":[]}]}"#;
+
+        let Some(FeedEvent::Book(updates)) = feed.parse(update) else {
+            panic!("expected a book update");
+        };
+        assert_eq!(updates[0].symbol, "BTC/USD");
+    }
+
+    #[test]
+    fn parses_kraken_timestamps() {
+        let parsed = parse_timestamp("2023-10-06T17:35:55.440295Z").unwrap();
+
+        let since_epoch = parsed.duration_since(std::time::UNIX_EPOCH).unwrap();
+        assert_eq!(since_epoch.as_secs(), 1696613755);
+        assert_eq!(since_epoch.subsec_micros(), 440295);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    fn logged_errors(f: impl FnOnce()) -> Vec<String> {
+        logged(Level::Error, f)
+    }
+
+    #[test]
+    fn non_book_messages_are_skipped_quietly() {
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10);
+        let heartbeat = r#"{"channel":"heartbeat"}"#;
+        let status = r#"{"channel":"status","type":"update","data":[{"api_version":"v2","connection_id":12893948786037996000,"system":"online","version":"2.0.0"}]}"#;
+        let instrument =
+            r#"{"channel":"instrument","type":"snapshot","data":{"assets":[],"pairs":[]}}"#;
+        let pong = r#"{"method":"pong","req_id":7,"time_in":"2023-10-06T17:35:55.440295Z","time_out":"2023-10-06T17:35:55.440327Z"}"#;
+
+        let errors = logged_errors(|| {
+            for text in [heartbeat, status, instrument, pong] {
+                assert_eq!(feed.parse(text), None);
+            }
+        });
+        assert_eq!(errors, Vec::<String>::new());
+    }
+
+    #[test]
+    fn book_messages_without_data_are_skipped() {
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10);
+        let without_data = r#"{"channel":"book","type":"update"}"#;
+        let empty_data = r#"{"channel":"book","type":"update","data":[]}"#;
+
+        let warnings = logged(Level::Warn, || assert_eq!(feed.parse(without_data), None));
+        assert_eq!(warnings, vec!["Received book message without data"]);
+        let warnings = logged(Level::Warn, || assert_eq!(feed.parse(empty_data), None));
+        assert_eq!(warnings, Vec::<String>::new());
+    }
+
+    #[test]
+    fn malformed_book_messages_are_logged() {
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10);
+        let malformed = r#"{"channel":"book","type":"update","data":{"symbol":"BTC/USD"}}"#;
+
+        let errors = logged_errors(|| assert_eq!(feed.parse(malformed), None));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Error while parsing message"));
+    }
+
+    #[test]
+    fn parses_subscription_responses() {
+        let rejected = r#"{"error":"Currency pair not supported XYZ/USD","method":"subscribe","success":false,"time_in":"2023-10-06T17:35:55.440295Z","time_out":"2023-10-06T17:35:55.440327Z"}"#;
+        let Ok(IncomingMessage::Response(response)) = serde_json::from_str(rejected) else {
+            panic!("error payload wasn't parsed as a method response");
+        };
+        assert_eq!(response.method, "subscribe");
+        assert!(!response.success);
+        assert_eq!(
+            response.error.as_deref(),
+            Some("Currency pair not supported XYZ/USD")
+        );
+
+        let acknowledged = r#"{"method":"subscribe","result":{"channel":"book","depth":10,"snapshot":true,"symbol":"BTC/USD"},"success":true,"time_in":"2023-10-06T17:35:55.440295Z","time_out":"2023-10-06T17:35:55.440327Z"}"#;
+        let Ok(IncomingMessage::Response(response)) = serde_json::from_str(acknowledged) else {
+            panic!("ack payload wasn't parsed as a method response");
+        };
+        assert!(response.success);
+        assert_eq!(response.result.unwrap().symbol.as_deref(), Some("BTC/USD"));
+
+        let heartbeat = r#"{"channel":"heartbeat"}"#;
+        assert!(matches!(
+            serde_json::from_str(heartbeat),
+            Ok(IncomingMessage::Channel(_))
+        ));
+    }
+
+    #[test]
+    fn only_a_rejected_subscription_needs_action() {
+        let response = |method: &str, success: bool| MethodResponse {
+            method: String::from(method),
+            success,
+            error: Some(String::from("Currency pair not supported XYZ/USD")),
+            result: None,
+        };
+
+        assert_eq!(
+            handle_response(&response("subscribe", false)),
+            Some(FeedEvent::Rejected(String::from(
+                "Currency pair not supported XYZ/USD"
+            )))
+        );
+        assert_eq!(handle_response(&response("subscribe", true)), None);
+        assert_eq!(handle_response(&response("unsubscribe", false)), None);
+    }
+
+    #[test]
+    fn book_messages_become_one_update_per_entry() {
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10);
+        let text = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":0.3,"qty":"1.5"}],"asks":[],"checksum":42,"timestamp":"2023-10-06T17:35:55.440295Z"},{"bids":[]}]}"#;
+
+        let Some(FeedEvent::Book(updates)) = feed.parse(text) else {
+            panic!("book message wasn't parsed into updates");
+        };
+        assert_eq!(
+            updates,
+            vec![BookUpdate {
+                symbol: String::from("BTC/USD"),
+                kind: UpdateKind::Update,
+                bids: vec![PriceLevel {
+                    price: "0.3".parse().unwrap(),
+                    qty: "1.5".parse().unwrap(),
+                }],
+                asks: vec![],
+                checksum: Some(42),
+                timestamp: parse_timestamp("2023-10-06T17:35:55.440295Z"),
+            }]
+        );
+        assert_eq!(feed.parse(r#"{"channel":"heartbeat"}"#), None);
+    }
+
+    #[test]
+    fn messages_with_non_finite_numbers_are_dropped() {
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10);
+        let nan_price = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":"NaN","qty":1.0}],"asks":[]}]}"#;
+        let infinite_qty = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1e400}],"asks":[]}]}"#;
+
+        assert_eq!(feed.parse(nan_price), None);
+        assert_eq!(feed.parse(infinite_qty), None);
+    }
+
+    #[test]
+    fn entries_parse_numbers_and_strings_exactly() {
+        let numeric: Entry = serde_json::from_str(r#"{"price":0.3,"qty":0.00000001}"#).unwrap();
+        let textual: Entry = serde_json::from_str(r#"{"price":"0.3","qty":"1.5"}"#).unwrap();
+
+        assert_eq!(numeric.price.to_string(), "0.3");
+        assert_eq!(numeric.qty.to_string(), "0.00000001");
+        assert_eq!(textual.price, numeric.price);
+    }
+
+    #[test]
+    fn subscription_and_unsubscription_differ_only_in_method() {
+        let symbols = vec![String::from("BTC/USD"), String::from("ETH/USD")];
+
+        assert_eq!(
+            serde_json::to_string(&get_subscription(&symbols, 10)).unwrap(),
+            r#"{"method":"subscribe","params":{"channel":"book","symbol":["BTC/USD","ETH/USD"],"depth":10}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&get_unsubscription(&symbols, 10)).unwrap(),
+            r#"{"method":"unsubscribe","params":{"channel":"book","symbol":["BTC/USD","ETH/USD"],"depth":10}}"#
+        );
+    }
+
+    #[test]
+    fn rest_snapshots_seed_the_books_and_drop_contained_updates() {
+        let seeded_at = parse_timestamp("2023-10-06T17:35:55Z");
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10).with_snapshot_source(
+            Arc::new(move |symbol, depth| {
+                assert_eq!((symbol, depth), ("BTC/USD", 10));
+                Ok(DepthSnapshot {
+                    bids: vec![PriceLevel {
+                        price: "100".parse().unwrap(),
+                        qty: "1".parse().unwrap(),
+                    }],
+                    asks: vec![],
+                    timestamp: seeded_at,
+                })
+            }),
+        );
+        let update = |timestamp: &str| {
+            format!(
+                r#"{{"channel":"book","type":"update","data":[{{"symbol":"BTC/USD","bids":[{{"price":99.0,"qty":1.0}}],"asks":[],"timestamp":"{}"}}]}}"#,
+                timestamp
+            )
+        };
+        let kinds = |event: Option<FeedEvent>| match event {
+            Some(FeedEvent::Book(updates)) => updates.iter().map(|update| update.kind).collect(),
+            _ => Vec::new(),
+        };
+
+        assert!(feed.subscribe_message().contains(r#""snapshot":false"#));
+        let acknowledged = r#"{"method":"subscribe","result":{"channel":"book","depth":10,"snapshot":false,"symbol":"BTC/USD"},"success":true}"#;
+        assert_eq!(kinds(feed.parse(acknowledged)), vec![]);
+        feed.wait_for_snapshots();
+        // the first update is already contained in the snapshot
+        assert_eq!(
+            kinds(feed.parse(&update("2023-10-06T17:35:54.9Z"))),
+            vec![UpdateKind::Snapshot]
+        );
+        assert_eq!(kinds(feed.parse(&update("2023-10-06T17:35:54.9Z"))), vec![]);
+        assert_eq!(
+            kinds(feed.parse(&update("2023-10-06T17:35:55.1Z"))),
+            vec![UpdateKind::Update]
+        );
+
+        // updates wait for the new snapshot after a reset
+        feed.reset();
+        assert_eq!(kinds(feed.parse(&update("2023-10-06T17:35:56Z"))), vec![]);
+        feed.wait_for_snapshots();
+        assert_eq!(
+            kinds(feed.parse(&update("2023-10-06T17:35:57Z"))),
+            vec![UpdateKind::Snapshot, UpdateKind::Update, UpdateKind::Update]
+        );
+    }
+
+    #[test]
+    fn trade_acknowledgements_dont_seed_the_books() {
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10)
+            .with_snapshot_source(Arc::new(|_, _| panic!("fetched a REST snapshot")))
+            .with_trades();
+
+        let acknowledged = r#"{"method":"subscribe","result":{"channel":"trade","snapshot":true,"symbol":"BTC/USD"},"success":true}"#;
+        assert!(feed.parse(acknowledged).is_none());
+        assert!(feed.seeding.is_empty());
+    }
+
+    #[test]
+    fn updates_wait_for_a_rest_snapshot_that_could_not_be_fetched() {
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&fetches);
+        let mut feed = KrakenFeed::new(&[String::from("BTC/USD")], 10)
+            .with_snapshot_source(Arc::new(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(String::from("timed out"))
+            }))
+            .with_clock(clock.clone());
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":99.0,"qty":1.0}],"asks":[]}]}"#;
+
+        assert_eq!(feed.parse(update), None);
+        feed.wait_for_snapshots();
+        let errors = logged_errors(|| {
+            for _ in 0..MAX_BUFFERED + 10 {
+                assert_eq!(feed.parse(update), None);
+            }
+        });
+        assert_eq!(
+            errors,
+            vec!["Couldn't fetch a REST snapshot for BTC/USD. timed out"]
+        );
+        // no retry before the delay is up, and the buffer stays bounded meanwhile
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(feed.seeding["BTC/USD"].buffered.len(), MAX_BUFFERED);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(feed.parse(update), None);
+        feed.wait_for_snapshots();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn private_channels_are_subscribed_with_the_token() {
+        let symbols = vec![String::from("BTC/USD")];
+        assert!(KrakenFeed::new(&symbols, 10).session_messages().is_empty());
+
+        let feed = KrakenFeed::new(&symbols, 10)
+            .with_private_channels(String::from("secret-token"), &[String::from("executions")]);
+        assert_eq!(
+            feed.session_messages(),
+            vec![
+                r#"{"method":"subscribe","params":{"channel":"executions","token":"secret-token"}}"#
+            ]
+        );
+        assert!(!feed.subscribe_message().contains("secret-token"));
+    }
+}
@@ -0,0 +1,133 @@
+//! Book snapshots from Kraken's public REST `Depth` endpoint, used to seed the books
+//! without waiting for the websocket to send a snapshot.
+
+use super::auth::REST_URL;
+use crate::orderbook::PriceLevel;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEPTH_PATH: &str = "/0/public/Depth";
+
+/// longest a snapshot request may take, connecting included
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Book of one symbol as returned by the `Depth` endpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthSnapshot {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    /// last change of the most recently changed level; websocket updates up to this time
+    /// are already contained in the snapshot
+    pub timestamp: Option<SystemTime>,
+}
+
+#[derive(Deserialize)]
+struct DepthResponse {
+    error: Vec<String>,
+    /// keyed by Kraken's name for the pair, e.g. `XXBTZUSD`
+    result: Option<HashMap<String, DepthResult>>,
+}
+
+#[derive(Deserialize)]
+struct DepthResult {
+    bids: Vec<DepthLevel>,
+    asks: Vec<DepthLevel>,
+}
+
+/// `[price, volume, timestamp]` with the timestamp in unix seconds.
+#[derive(Deserialize)]
+struct DepthLevel(Decimal, Decimal, u64);
+
+/// Parses the body of a `Depth` response for a single pair.
+pub fn parse_depth_snapshot(body: &str) -> Result<DepthSnapshot, String> {
+    let response: DepthResponse = serde_json::from_str(body).map_err(|error| error.to_string())?;
+    if !response.error.is_empty() {
+        return Err(response.error.join(", "));
+    }
+    let Some(book) = response
+        .result
+        .into_iter()
+        .flat_map(|result| result.into_values())
+        .next()
+    else {
+        return Err(String::from("response holds no book"));
+    };
+    let timestamp = book
+        .bids
+        .iter()
+        .chain(&book.asks)
+        .map(|DepthLevel(_, _, timestamp)| *timestamp)
+        .max()
+        .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds));
+    let levels = |levels: Vec<DepthLevel>| {
+        levels
+            .into_iter()
+            .map(|DepthLevel(price, qty, _)| PriceLevel { price, qty })
+            .collect()
+    };
+    Ok(DepthSnapshot {
+        bids: levels(book.bids),
+        asks: levels(book.asks),
+        timestamp,
+    })
+}
+
+/// Fetches the top `depth` levels per side of `symbol`, spelled as on the websocket.
+/// Gives up after 10 seconds.
+pub fn fetch_depth_snapshot(symbol: &str, depth: u32) -> Result<DepthSnapshot, String> {
+    let body = ureq::get(format!("{}{}", REST_URL, DEPTH_PATH))
+        .query("pair", symbol)
+        .query("count", depth.to_string())
+        .config()
+        .timeout_global(Some(SNAPSHOT_TIMEOUT))
+        .build()
+        .call()
+        .and_then(|mut response| response.body_mut().read_to_string())
+        .map_err(|error| error.to_string())?;
+    parse_depth_snapshot(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_depth_response() {
+        let body = r#"{"error":[],"result":{"XXBTZUSD":{"asks":[["27567.10000","0.743",1696613755],["27568.00000","1.200",1696613750]],"bids":[["27566.90000","2.145",1696613756]]}}}"#;
+
+        let snapshot = parse_depth_snapshot(body).unwrap();
+
+        assert_eq!(
+            snapshot.asks,
+            vec![
+                PriceLevel {
+                    price: "27567.1".parse().unwrap(),
+                    qty: "0.743".parse().unwrap(),
+                },
+                PriceLevel {
+                    price: "27568".parse().unwrap(),
+                    qty: "1.2".parse().unwrap(),
+                },
+            ]
+        );
+        assert_eq!(snapshot.bids[0].price, "27566.9".parse().unwrap());
+        assert_eq!(
+            snapshot.timestamp,
+            Some(UNIX_EPOCH + Duration::from_secs(1696613756))
+        );
+    }
+
+    #[test]
+    fn depth_errors_are_reported() {
+        let body = r#"{"error":["EQuery:Unknown asset pair"]}"#;
+
+        assert_eq!(
+            parse_depth_snapshot(body),
+            Err(String::from("EQuery:Unknown asset pair"))
+        );
+        assert!(parse_depth_snapshot(r#"{"error":[],"result":{}}"#).is_err());
+        assert!(parse_depth_snapshot("not json").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Kraken's v2 `ticker` channel, which carries the best bid and ask with their quantities
+//! and stands in for the book channel at depth 1, a depth the book channel doesn't offer.
+
+use super::{normalize_symbol, parse_timestamp};
+use crate::feed::{BookUpdate, UpdateKind};
+use crate::orderbook::PriceLevel;
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Subscription to the ticker of a set of symbols.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TickerSubscription {
+    pub method: String,
+    pub params: TickerSubscriptionParams,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TickerSubscriptionParams {
+    pub channel: String,
+    pub symbol: Vec<String>,
+    /// `bbo` to get a message whenever the best bid or ask changes, `trades` for every trade
+    pub event_trigger: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TickerMessage {
+    pub data: Option<Vec<TickerEntry>>,
+    #[serde(rename = "type")]
+    pub type_name: Option<String>,
+    pub channel: Option<String>,
+}
+
+impl TickerMessage {
+    /// Converts every entry of a ticker message into a single level [`BookUpdate`].
+    pub fn into_updates(self) -> Vec<BookUpdate> {
+        let Some(data) = self.data else {
+            warn!("Received ticker message without data");
+            return Vec::new();
+        };
+        data.into_iter()
+            .filter_map(TickerEntry::into_update)
+            .collect()
+    }
+}
+
+/// The part of a ticker entry describing the top of book; the traded volume, last price
+/// and daily statistics are left out.
+#[derive(Serialize, Deserialize)]
+pub struct TickerEntry {
+    pub symbol: Option<String>,
+    pub bid: Decimal,
+    pub bid_qty: Decimal,
+    pub ask: Decimal,
+    pub ask_qty: Decimal,
+    /// RFC3339 time of the ticker, only sent by newer versions of the api
+    pub timestamp: Option<String>,
+}
+
+impl TickerEntry {
+    /// Converts the entry into a snapshot of a book one level deep, `None` if it doesn't
+    /// name its symbol. A side with a zero quantity is empty.
+    pub fn into_update(self) -> Option<BookUpdate> {
+        let Some(symbol) = self.symbol.as_deref().map(normalize_symbol) else {
+            warn!("Received ticker without a symbol");
+            return None;
+        };
+        Some(BookUpdate {
+            symbol,
+            kind: UpdateKind::Snapshot,
+            bids: vec![PriceLevel {
+                price: self.bid,
+                qty: self.bid_qty,
+            }],
+            asks: vec![PriceLevel {
+                price: self.ask,
+                qty: self.ask_qty,
+            }],
+            checksum: None,
+            timestamp: self.timestamp.as_deref().and_then(parse_timestamp),
+        })
+    }
+}
+
+/// Builds the ticker subscription for the given symbols, updated on every change of the
+/// best bid or ask.
+pub fn get_ticker_subscription(symbols: &[String]) -> TickerSubscription {
+    ticker_request("subscribe", symbols)
+}
+
+/// Builds the message that ends a subscription made with [`get_ticker_subscription`].
+pub fn get_ticker_unsubscription(symbols: &[String]) -> TickerSubscription {
+    ticker_request("unsubscribe", symbols)
+}
+
+fn ticker_request(method: &str, symbols: &[String]) -> TickerSubscription {
+    TickerSubscription {
+        method: String::from(method),
+        params: TickerSubscriptionParams {
+            channel: String::from("ticker"),
+            symbol: symbols.to_vec(),
+            event_trigger: String::from("bbo"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_ticker_message() {
+        let text = r#"{"channel":"ticker","type":"update","data":[{"symbol":"BTC/USD","bid":27566.9,"bid_qty":2.145,"ask":27567.1,"ask_qty":0.743,"last":27567.1,"volume":1543.12064815,"vwap":27431.6,"low":27105.0,"high":27720.0,"change":301.2,"change_pct":1.1}]}"#;
+
+        let message: TickerMessage = serde_json::from_str(text).unwrap();
+        let updates = message.into_updates();
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].kind, UpdateKind::Snapshot);
+        assert_eq!(
+            updates[0].bids,
+            vec![PriceLevel {
+                price: "27566.9".parse().unwrap(),
+                qty: "2.145".parse().unwrap(),
+            }]
+        );
+        assert_eq!(updates[0].asks[0].qty, "0.743".parse().unwrap());
+        assert_eq!(updates[0].checksum, None);
+    }
+}
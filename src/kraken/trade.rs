@@ -0,0 +1,141 @@
+//! Kraken's v2 `trade` channel: the subscription and the executed trades it pushes.
+
+use super::{normalize_symbol, parse_timestamp};
+use crate::feed::{Trade, TradeSide};
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Subscription to the trades of a set of symbols.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TradeSubscription {
+    pub method: String,
+    pub params: TradeSubscriptionParams,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TradeSubscriptionParams {
+    pub channel: String,
+    pub symbol: Vec<String>,
+    /// whether Kraken starts with the most recent trades
+    pub snapshot: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TradeMessage {
+    pub data: Option<Vec<TradeEntry>>,
+    #[serde(rename = "type")]
+    pub type_name: Option<String>,
+    pub channel: Option<String>,
+}
+
+impl TradeMessage {
+    /// Converts every entry of a trade channel message into a [`Trade`], skipping the ones
+    /// that can't be.
+    pub fn into_trades(self) -> Vec<Trade> {
+        let Some(data) = self.data else {
+            warn!("Received trade message without data");
+            return Vec::new();
+        };
+        data.into_iter()
+            .filter_map(TradeEntry::into_trade)
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TradeEntry {
+    pub symbol: Option<String>,
+    /// `buy` or `sell`, the side of the taker
+    pub side: String,
+    pub price: Decimal,
+    pub qty: Decimal,
+    pub trade_id: Option<u64>,
+    /// RFC3339 time at which the exchange matched the trade
+    pub timestamp: Option<String>,
+}
+
+impl TradeEntry {
+    /// Converts the entry, `None` if it doesn't name its symbol or a known side.
+    pub fn into_trade(self) -> Option<Trade> {
+        let side = match self.side.as_str() {
+            "buy" => TradeSide::Buy,
+            "sell" => TradeSide::Sell,
+            side => {
+                warn!("Received trade with unknown side {}", side);
+                return None;
+            }
+        };
+        let Some(symbol) = self.symbol.as_deref().map(normalize_symbol) else {
+            warn!("Received trade without a symbol");
+            return None;
+        };
+        Some(Trade {
+            symbol,
+            side,
+            price: self.price,
+            qty: self.qty,
+            timestamp: self.timestamp.as_deref().and_then(parse_timestamp),
+        })
+    }
+}
+
+/// Builds the trade channel subscription for the given symbols, without the snapshot of
+/// recent trades.
+pub fn get_trade_subscription(symbols: &[String]) -> TradeSubscription {
+    TradeSubscription {
+        method: String::from("subscribe"),
+        params: TradeSubscriptionParams {
+            channel: String::from("trade"),
+            symbol: symbols.to_vec(),
+            snapshot: false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn parses_a_trade_message() {
+        let text = r#"{"channel":"trade","type":"update","data":[{"symbol":"BTC/USD","side":"sell","price":27566.9,"qty":0.00125,"ord_type":"market","trade_id":64371852,"timestamp":"2023-10-06T17:35:55.440295Z"},{"symbol":"BTC/USD","side":"buy","price":27567.1,"qty":0.5,"ord_type":"limit","trade_id":64371853,"timestamp":"2023-10-06T17:35:55.512Z"}]}"#;
+
+        let message: TradeMessage = serde_json::from_str(text).unwrap();
+        let trades = message.into_trades();
+
+        assert_eq!(
+            trades[0],
+            Trade {
+                symbol: String::from("BTC/USD"),
+                side: TradeSide::Sell,
+                price: "27566.9".parse().unwrap(),
+                qty: "0.00125".parse().unwrap(),
+                timestamp: Some(UNIX_EPOCH + Duration::from_micros(1696613755440295)),
+            }
+        );
+        assert_eq!(trades[1].side, TradeSide::Buy);
+        assert_eq!(trades[1].qty, "0.5".parse().unwrap());
+        assert_eq!(trades.len(), 2);
+    }
+
+    #[test]
+    fn trades_with_an_unknown_side_are_skipped() {
+        let text = r#"{"channel":"trade","type":"update","data":[{"symbol":"BTC/USD","side":"hold","price":1,"qty":1}]}"#;
+
+        let message: TradeMessage = serde_json::from_str(text).unwrap();
+
+        assert_eq!(message.into_trades(), Vec::new());
+    }
+
+    #[test]
+    fn trade_subscription_names_the_symbols() {
+        let symbols = vec![String::from("BTC/USD"), String::from("ETH/USD")];
+
+        assert_eq!(
+            serde_json::to_string(&get_trade_subscription(&symbols)).unwrap(),
+            r#"{"method":"subscribe","params":{"channel":"trade","symbol":["BTC/USD","ETH/USD"],"snapshot":false}}"#
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Summary of the samples currently in a [`LatencyWindow`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatencyStats {
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    pub p99: Duration,
+}
+
+/// The most recent `capacity` latency samples; older ones are dropped as new ones arrive.
+#[derive(Clone)]
+pub struct LatencyWindow {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl LatencyWindow {
+    pub fn new(capacity: usize) -> Self {
+        LatencyWindow {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, sample: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Statistics over the window, `None` while it's empty.
+    pub fn stats(&self) -> Option<LatencyStats> {
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let count = sorted.len();
+        let total: Duration = sorted.iter().sum();
+        // nearest-rank percentile: the smallest sample at or above 99% of the samples
+        let p99_rank = (count * 99).div_ceil(100).max(1);
+        Some(LatencyStats {
+            min: *sorted.first()?,
+            max: *sorted.last()?,
+            mean: total / count as u32,
+            p99: sorted[p99_rank - 1],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_cover_only_the_latest_samples() {
+        let mut window = LatencyWindow::new(100);
+        assert_eq!(window.stats(), None);
+
+        // 1000ms is pushed out by the 100 samples that follow it
+        window.record(Duration::from_millis(1000));
+        for millis in 1..=100 {
+            window.record(Duration::from_millis(millis));
+        }
+
+        assert_eq!(
+            window.stats(),
+            Some(LatencyStats {
+                min: Duration::from_millis(1),
+                max: Duration::from_millis(100),
+                mean: Duration::from_micros(50500),
+                p99: Duration::from_millis(99),
+            })
+        );
+    }
+
+    #[test]
+    fn a_single_sample_is_every_statistic() {
+        let mut window = LatencyWindow::new(10);
+        window.record(Duration::from_millis(7));
+
+        let stats = window.stats().unwrap();
+        assert_eq!(stats.min, stats.p99);
+        assert_eq!(stats.mean, Duration::from_millis(7));
+    }
+}
@@ -0,0 +1,56 @@
+//! Order book reconstruction from exchange websocket feeds: Kraken's v2 book channel and
+//! Binance's diff depth stream.
+//!
+//! The websocket client in the binary is a thin layer on top of this crate: an
+//! [`ExchangeFeed`] such as [`kraken::KrakenFeed`] or [`binance::BinanceFeed`] turns venue messages into
+//! [`BookUpdate`]s, which are fed into an [`Orderbook`] via [`route`] or the per-book
+//! [`Orderbook::evaluate`].
+
+mod arbitrage;
+pub mod binance;
+mod clock;
+mod connection;
+mod feed;
+mod http;
+mod ipc;
+pub mod kraken;
+mod latency;
+mod matching;
+mod metrics;
+mod ohlc;
+mod orderbook;
+mod recorder;
+mod replay;
+mod snapshot_fetch;
+mod spread;
+mod stats;
+#[cfg(test)]
+mod test_log;
+#[cfg(test)]
+mod test_support;
+
+pub use arbitrage::{cross_spread, Arbitrage};
+#[cfg(any(test, feature = "test-util"))]
+pub use clock::MockClock;
+pub use clock::{Clock, SystemClock};
+pub use connection::Connection;
+pub use feed::{BookUpdate, ExchangeFeed, FeedEvent, Trade, TradeSide, UpdateKind};
+#[cfg(feature = "http")]
+pub use http::serve_book_endpoint;
+pub use http::{BookEndpoint, TopOfBook};
+pub use ipc::IpcPublisher;
+pub use latency::{LatencyStats, LatencyWindow};
+pub use matching::{Fill, MatchingSim, SimOrder};
+#[cfg(feature = "metrics")]
+pub use metrics::serve_metrics;
+pub use metrics::Metrics;
+pub use ohlc::{OhlcAggregator, OhlcBar};
+pub use orderbook::{
+    ema_update, format_decimal, keep_last_checksums, route, route_entries, AppliedEntry, BookDiff,
+    BookError, BookSnapshot, ChangeCallback, Orderbook, PriceLevel, Side, SideDiff, Tick,
+    DEFAULT_QTY_EPSILON, DEFAULT_TICK_CAPACITY,
+};
+pub use recorder::{Recorder, RecordingFile};
+pub use replay::{open_recording, parse_recorded_line, RecordedMessage};
+pub use spread::SpreadWindow;
+pub use stats::{ConnectionState, ConnectionStats, SessionCounts, SessionStats};
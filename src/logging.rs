@@ -0,0 +1,170 @@
+use clap::ValueEnum;
+use env_logger::filter::Filter;
+use log::kv::{Error, Key, Value, VisitSource};
+use log::{Log, Metadata, Record};
+use serde_json::{Map, Value as Json};
+use std::io::Write;
+use std::time::SystemTime;
+use syslog::{BasicLogger, Facility, Formatter3164};
+
+/// How log records are written to stderr.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// env_logger's human readable lines
+    Text,
+    /// One JSON object per record with its timestamp, level, target, message and fields
+    Json,
+}
+
+/// Where log records are written.
+#[derive(Clone, Debug)]
+pub enum LogTarget {
+    /// stderr, in the given format
+    Stderr(LogFormat),
+    /// the system logger's local socket, under `facility` with `tag` as the process name
+    Syslog { facility: Facility, tag: String },
+}
+
+/// Sets up logging to `target`, filtered by RUST_LOG as usual. Without a system logger to
+/// connect to, records go to stderr as text instead.
+pub fn init(target: LogTarget) {
+    let (facility, tag) = match target {
+        LogTarget::Stderr(format) => return init_stderr(format),
+        LogTarget::Syslog { facility, tag } => (facility, tag),
+    };
+    let formatter = Formatter3164 {
+        facility,
+        hostname: None,
+        process: tag,
+        pid: std::process::id(),
+    };
+    match syslog::unix(formatter) {
+        Ok(logger) => {
+            let filter = env_logger::filter::Builder::from_env("RUST_LOG").build();
+            log::set_max_level(filter.filter());
+            let logger = SyslogLogger {
+                logger: BasicLogger::new(logger),
+                filter,
+            };
+            // only fails if a logger was set up before, which main doesn't do
+            let _ = log::set_boxed_logger(Box::new(logger));
+        }
+        Err(error) => {
+            init_stderr(LogFormat::Text);
+            log::warn!("Couldn't connect to syslog, logging to stderr. {}", error);
+        }
+    }
+}
+
+/// Sets up env_logger to write records to stderr in `format`.
+fn init_stderr(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(record, SystemTime::now())));
+    }
+    builder.init();
+}
+
+/// Hands the records RUST_LOG lets through to the system logger.
+struct SyslogLogger {
+    logger: BasicLogger,
+    filter: Filter,
+}
+
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.filter.matches(record) {
+            self.logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush();
+    }
+}
+
+/// Formats `record`, logged at `timestamp`, as a JSON object. Key-value fields given to the
+/// log macros, like `symbol`, are added next to the message, numbers and booleans as such.
+pub fn json_line(record: &Record, timestamp: SystemTime) -> String {
+    let mut line = Map::new();
+    line.insert(
+        String::from("timestamp"),
+        Json::from(humantime::format_rfc3339_micros(timestamp).to_string()),
+    );
+    line.insert(String::from("level"), Json::from(record.level().as_str()));
+    line.insert(String::from("target"), Json::from(record.target()));
+    line.insert(
+        String::from("message"),
+        Json::from(record.args().to_string()),
+    );
+    let mut fields = Fields(&mut line);
+    // visiting only fails if the visitor does, and collecting the fields never fails
+    let _ = record.key_values().visit(&mut fields);
+    Json::Object(line).to_string()
+}
+
+/// Collects the key-value fields of a record into its JSON object.
+struct Fields<'a>(&'a mut Map<String, Json>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let value = if let Some(value) = value.to_bool() {
+            Json::from(value)
+        } else if let Some(value) = value.to_u64() {
+            Json::from(value)
+        } else if let Some(value) = value.to_i64() {
+            Json::from(value)
+        } else if let Some(value) = value.to_f64() {
+            Json::from(value)
+        } else {
+            Json::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn records_become_json_objects_with_their_fields() {
+        let fields: [(&str, Value); 3] = [
+            ("symbol", Value::from("BTC/USD")),
+            ("expected", Value::from(3630198917u32)),
+            ("matched", Value::from(false)),
+        ];
+        // the record borrows its formatted message, so it's only alive for this statement
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("Checksum mismatch for {}", "BTC/USD"))
+                .level(Level::Error)
+                .target("hft_orderbook::orderbook")
+                .key_values(&fields)
+                .build(),
+            UNIX_EPOCH + Duration::from_micros(1696613755440295),
+        );
+
+        let json: Json = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": "2023-10-06T17:35:55.440295Z",
+                "level": "ERROR",
+                "target": "hft_orderbook::orderbook",
+                "message": "Checksum mismatch for BTC/USD",
+                "symbol": "BTC/USD",
+                "expected": 3630198917u32,
+                "matched": false,
+            })
+        );
+        assert!(!line.contains('\n'));
+    }
+}
@@ -1,242 +1,3225 @@
-use log::error;
+mod color;
+mod config;
+mod connect;
+mod logging;
+#[cfg(test)]
+mod mock_server;
+mod sink;
+mod tls;
+
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use color::{ColorChoice, Palette};
+use config::Config;
+use connect::ConnectFailure;
+#[cfg(feature = "http")]
+use hft_orderbook::BookEndpoint;
+use hft_orderbook::{
+    binance::{BinanceFeed, BINANCE_URL},
+    cross_spread, format_decimal, keep_last_checksums,
+    kraken::{fetch_websockets_token, normalize_symbol, KrakenFeed, TICKER_DEPTH},
+    open_recording, parse_recorded_line, route_entries, BookSnapshot, BookUpdate, Clock,
+    Connection, ConnectionState, ConnectionStats, ExchangeFeed, FeedEvent, IpcPublisher, Metrics,
+    Orderbook, Recorder, RecordingFile, SessionCounts, SessionStats, SystemClock, Trade, TradeSide,
+    DEFAULT_QTY_EPSILON,
+};
+use log::{error, info, warn};
+use logging::{LogFormat, LogTarget};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
-use std::collections::BTreeSet;
-use std::io::Write;
-use websocket::stream::sync::NetworkStream;
-use websocket::sync::Client;
-use websocket::{ClientBuilder, Message, OwnedMessage};
+#[cfg(feature = "http")]
+use sink::EndpointSink;
+use sink::{output_sink, BboChangeSink, CsvSink, JsonSink, OutputSink, QueuedSink};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use syslog::Facility;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::Notify;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::{Bytes, Message, Utf8Bytes};
+use tokio_tungstenite::WebSocketStream;
 
-#[derive(Serialize, Deserialize)]
-struct Subscription {
-    method: String,
-    params: SubscriptionParams,
-}
+/// upper bound for the delay between reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// set by the Ctrl-C handler, checked by every loop between messages
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// notified by the Ctrl-C handler to wake the connection loop and reconnection delays
+static SHUTDOWN_SIGNAL: Notify = Notify::const_new();
+
+/// set by SIGUSR1, makes the feed loops dump every book to a file after the next message
+static DUMP_REQUESTED: LazyLock<Arc<AtomicBool>> = LazyLock::new(Default::default);
+
+/// time between checks whether the connection went silent for longer than --idle-timeout
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// longest wait for the server to answer the close frame sent on exit
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// time between redraws of the status line
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
 
-#[derive(Serialize, Deserialize)]
-struct SubscriptionParams {
-    channel: String,
-    symbol: Vec<String>,
-    depth: i32
+/// maximum bar length of the ladder output, reached by the deeper side's cumulative quantity
+const LADDER_WIDTH: usize = 40;
+
+/// public websocket endpoint of Kraken's v2 api
+const KRAKEN_URL: &str = "wss://ws.kraken.com/v2";
+
+/// Kraken's v2 endpoint serving private channels next to the public ones
+const KRAKEN_AUTH_URL: &str = "wss://ws-auth.kraken.com/v2";
+
+/// depth values accepted by Kraken's v2 book channel, and 1 for the top of book from its ticker
+const ALLOWED_DEPTHS: [u32; 6] = [TICKER_DEPTH, 10, 25, 100, 500, 1000];
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputMode {
+    /// Top of book per symbol, redrawn in place
+    Pretty,
+    /// One JSON snapshot per updated book and line
+    Json,
+    /// Depth ladder of the top levels per symbol, redrawn in place
+    Ladder,
+    /// Header row, then one row of top of book values per updated book
+    Csv,
 }
 
-#[derive(Serialize, Deserialize)]
-struct OrderbookMessage {
-    data: Option<Vec<OrderbookEntry>>,
-    #[serde(rename = "type")]
-    type_name: Option<String>,
-    channel: Option<String>,
+/// How symbols are shown in the pretty and ladder output.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum SymbolFormat {
+    /// Kraken's own form, BTC/USD
+    Slash,
+    /// BTC-USD
+    Dash,
+    /// BTCUSD
+    Compact,
 }
 
-#[derive(Serialize, Deserialize)]
-struct OrderbookEntry {
-    symbol: Option<String>,
-    bids: Option<Vec<Entry>>,
-    asks: Option<Vec<Entry>>,
+/// Venue whose book feed is consumed.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Exchange {
+    /// Kraken's v2 book channel, symbols like BTC/USD
+    Kraken,
+    /// Binance's diff depth stream seeded from REST snapshots, symbols like BTCUSDT
+    Binance,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Entry {
-    price: f64,
-    qty: f64,
+/// What the connection loop should do after a text message was handled.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Continue,
+    /// the books are inconsistent and need a fresh snapshot
+    Resubscribe,
+    /// the exchange rejected the subscription, reconnecting won't help, or a spread went
+    /// above --max-spread with --exit-on-wide-spread
+    Exit,
+    /// stdout was closed, e.g. by the reader of a pipe, so there is nobody left to show
+    /// the books to
+    Shutdown,
 }
 
-#[derive(Serialize, Deserialize)]
-struct AskEntry {
-    price: f64,
-    qty: f64,
+#[derive(Clone, Parser)]
+#[command(
+    about = "Streams exchange order books and renders the top of book",
+    after_help = "Send SIGUSR1 to write every book to <symbol>-<unix millis>.json"
+)]
+struct Args {
+    /// TOML file with defaults for the options below; flags given here take precedence
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Format of the log records written to stderr, filtered by RUST_LOG
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Send the log records to the system logger instead of stderr
+    #[arg(long, conflicts_with = "log_format")]
+    syslog: bool,
+
+    /// Syslog facility of the log records, e.g. daemon or local0
+    #[arg(long, default_value = "user", value_parser = parse_facility, requires = "syslog")]
+    syslog_facility: Facility,
+
+    /// Process name the log records are tagged with in syslog
+    #[arg(long, default_value = "hft-orderbook", requires = "syslog")]
+    syslog_tag: String,
+
+    /// Trading pair to subscribe to, may be repeated
+    #[arg(long = "symbol", default_value = "BTC/USD")]
+    symbols: Vec<String>,
+
+    /// Number of price levels per side (1, 10, 25, 100, 500 or 1000); 1 follows just the top
+    /// of book through Kraken's ticker channel, which sends no checksums
+    #[arg(long, visible_alias = "levels", default_value_t = 25, value_parser = parse_depth)]
+    depth: u32,
+
+    /// Decimal places of the instrument's prices, used for checksum validation
+    #[arg(long, default_value_t = 1)]
+    price_precision: usize,
+
+    /// Decimal places of the instrument's quantities, used for checksum validation
+    #[arg(long, default_value_t = 8)]
+    qty_precision: usize,
+
+    /// Decimal places prices are shown with, defaults to --price-precision
+    #[arg(long)]
+    price_decimals: Option<usize>,
+
+    /// Decimal places quantities are shown with, defaults to --qty-precision
+    #[arg(long)]
+    qty_decimals: Option<usize>,
+
+    /// Price increment of the instrument; prices are snapped to it to identify levels
+    #[arg(long)]
+    tick_size: Option<Decimal>,
+
+    /// per symbol price increments from the config file, used when --tick-size isn't given
+    #[arg(skip)]
+    tick_sizes: HashMap<String, Decimal>,
+
+    /// Quantities below this delete their price level, as a zero quantity always does
+    #[arg(long, default_value_t = DEFAULT_QTY_EPSILON)]
+    qty_epsilon: Decimal,
+
+    /// Exchange to stream the books from
+    #[arg(long, value_enum, default_value_t = Exchange::Kraken)]
+    exchange: Exchange,
+
+    /// Websocket endpoint of the exchange, defaults to its public endpoint, or Kraken's
+    /// authenticated one when --api-key is given
+    #[arg(long)]
+    url: Option<String>,
+
+    /// Also trust the CA certificate in this PEM file when connecting to the websocket,
+    /// e.g. of a beta or local endpoint
+    #[arg(long)]
+    tls_ca: Option<PathBuf>,
+
+    /// Accept any certificate and host name from the websocket endpoint, only meant for
+    /// local mock servers
+    #[arg(long)]
+    tls_insecure: bool,
+
+    /// Seed Kraken books from the REST Depth endpoint on every (re)subscription instead of
+    /// waiting for a websocket snapshot
+    #[arg(long)]
+    rest_snapshot: bool,
+
+    /// Kraken API key, subscribes to the --private-channel channels next to the books
+    #[arg(long, requires = "api_secret")]
+    api_key: Option<String>,
+
+    /// Base64 encoded private key of --api-key, signs the websocket token request
+    #[arg(long, requires = "api_key")]
+    api_secret: Option<String>,
+
+    /// Private Kraken channel to subscribe to with --api-key, may be repeated
+    #[arg(long = "private-channel", default_value = "executions")]
+    private_channels: Vec<String>,
+
+    /// Also subscribe to the Kraken trade channel of the symbols and write each trade
+    /// between the book updates
+    #[arg(long)]
+    trades: bool,
+
+    /// How book updates are written to stdout
+    #[arg(long, value_enum, default_value_t = OutputMode::Pretty)]
+    output: OutputMode,
+
+    /// How the pretty and ladder output shows symbols; subscriptions and the JSON and CSV
+    /// output keep the exchange's form
+    #[arg(long, value_enum, default_value_t = SymbolFormat::Slash)]
+    display_symbol_format: SymbolFormat,
+
+    /// Color the pretty and ladder output: bids green, asks red and each symbol's header
+    /// in its own color
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// colors resolved from --color for the actual stdout, plain unless set up by main
+    #[arg(skip)]
+    palette: Palette,
+
+    /// time of the books, stats, render throttling and idle watchdog, the system's unless
+    /// replaced by tests
+    #[arg(skip = Arc::new(SystemClock) as Arc<dyn Clock>)]
+    clock: Arc<dyn Clock>,
+
+    /// Show a status line with the connection state, messages received, the last checksum
+    /// result and the uptime below the pretty and ladder output, refreshed every second
+    #[arg(long)]
+    status: bool,
+
+    /// Keep the books and metrics up to date without writing them to stdout, e.g. to
+    /// benchmark a replay
+    #[arg(long)]
+    quiet: bool,
+
+    /// Wait for a snapshot of every book, print all of their levels as JSON and exit
+    #[arg(long, conflicts_with = "status")]
+    dump_once: bool,
+
+    /// Only write a book when its best bid or ask, price or quantity, changed since it was
+    /// last written, leaving out updates to deeper levels
+    #[arg(long)]
+    on_bbo_change: bool,
+
+    /// Write the output from a thread of its own through a queue of this many updates, so a
+    /// slow output can't hold up the feed; updates arriving while it's full are skipped
+    #[arg(long, conflicts_with = "status")]
+    output_queue: Option<usize>,
+
+    /// Redraw the pretty and ladder output at most once per interval, e.g. 100ms; every
+    /// update is still applied and JSON lines are written for each of them
+    #[arg(long, default_value = "0s", value_parser = humantime::parse_duration)]
+    render_interval: Duration,
+
+    /// Collect the messages received within this window, e.g. 50ms, and apply them
+    /// together, writing the books once per window instead of once per message
+    #[arg(long, value_parser = humantime::parse_duration)]
+    batch_window: Option<Duration>,
+
+    /// Merge levels into price buckets of this size for the ladder and JSON output, e.g. 10
+    /// to group prices to the nearest 10; the books themselves keep every level
+    #[arg(long)]
+    bucket: Option<f64>,
+
+    /// Also write a JSON snapshot of each updated book per line to this file, next to the
+    /// --output written to stdout
+    #[arg(long)]
+    json_file: Option<PathBuf>,
+
+    /// Also write a header and a row of top of book values per updated book to this CSV
+    /// file, next to the --output written to stdout
+    #[arg(long)]
+    csv_file: Option<PathBuf>,
+
+    /// Levels per side shown by the ladder output
+    #[arg(long, default_value_t = 10)]
+    ladder_levels: usize,
+
+    /// Append every received text message to this file for later replay
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Compress the --record file with gzip, which is also done when its name ends in .gz
+    #[arg(long, requires = "record")]
+    record_gzip: bool,
+
+    /// Print the subscription message the configured symbols and depth would send and exit
+    /// without connecting
+    #[arg(long)]
+    print_subscription: bool,
+
+    /// Rebuild the books from a file written by --record instead of connecting
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Start the books from a file written by --dump-once and write them out instead of
+    /// connecting; with --replay the recording is applied on top of them. A connection would
+    /// replace them with the exchange's snapshots, so the options of one are rejected
+    #[arg(
+        long,
+        conflicts_with_all = ["record", "rest_snapshot", "api_key", "duration", "max_retries"]
+    )]
+    load_snapshot: Option<PathBuf>,
+
+    /// Pace the replay using the receive times stored in the recording
+    #[arg(long, requires = "replay")]
+    realtime: bool,
+
+    /// Pace the replay like --realtime with the recorded delays divided by this factor,
+    /// e.g. 2.0 to play twice as fast or 0.5 at half speed
+    #[arg(long, requires = "replay", value_parser = parse_speed)]
+    speed: Option<f64>,
+
+    /// Apply one recorded message per press of Enter
+    #[arg(long, requires = "replay", conflicts_with_all = ["realtime", "speed"])]
+    step: bool,
+
+    /// Resubscribe for a fresh snapshot whenever a book becomes crossed or locked
+    #[arg(long)]
+    resync_on_crossed: bool,
+
+    /// Check every updated book for broken invariants, like unordered levels or a crossed
+    /// book, and log what is wrong with it
+    #[arg(long)]
+    strict: bool,
+
+    /// Warn when either side of a book drops below this many levels
+    #[arg(long)]
+    min_levels: Option<usize>,
+
+    /// Warn when the spread of a book goes above this, e.g. 5.0
+    #[arg(long)]
+    max_spread: Option<Decimal>,
+
+    /// Exit with status 1 once the spread of a book goes above --max-spread
+    #[arg(long, requires = "max_spread")]
+    exit_on_wide_spread: bool,
+
+    /// Resubscribe for a fresh snapshot once this many checksums in a row didn't match a book
+    #[arg(long)]
+    resync_after_checksum_failures: Option<u32>,
+
+    /// Show an exponential moving average of the mid price with this smoothing factor,
+    /// in (0, 1]; higher values follow the mid more closely
+    #[arg(long, value_parser = parse_ema_alpha)]
+    ema_alpha: Option<f64>,
+
+    /// Print a bar with the open, high, low and close of each symbol's mid price whenever
+    /// an interval of this length completes, e.g. 1s or 1m
+    #[arg(long, value_parser = humantime::parse_duration)]
+    ohlc: Option<Duration>,
+
+    /// Log arbitrage between the first two symbols, with the prices of the second multiplied
+    /// by this rate to convert them into the quote currency of the first
+    #[arg(long, value_parser = parse_arbitrage_rate)]
+    arbitrage_rate: Option<f64>,
+
+    /// Fee charged on each trade of an arbitrage, as a fraction of the traded amount
+    #[arg(long, default_value_t = 0.0, requires = "arbitrage_rate")]
+    arbitrage_fee: f64,
+
+    /// Append the capital resting on this many top levels of each side to each line
+    #[arg(long)]
+    notional_levels: Option<usize>,
+
+    /// Append the mean and the widest spread of each book over its last this many updates
+    /// to each line
+    #[arg(long)]
+    spread_window: Option<usize>,
+
+    /// Append the lowest and highest mid price seen since startup to each line
+    #[arg(long)]
+    session_range: bool,
+
+    /// Start the session high and low over whenever the books are rebuilt after a reconnect
+    /// or resubscribe, instead of carrying them over
+    #[arg(long)]
+    reset_session_range: bool,
+
+    /// Append the p99 delay between exchange timestamps and local processing to each line
+    #[arg(long)]
+    show_latency: bool,
+
+    /// Append the mid price weighted by the top of book quantities to each line
+    #[arg(long)]
+    show_weighted_mid: bool,
+
+    /// Flag a book as stale when its last update is older than this, e.g. 5s or 500ms
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    stale_after: Duration,
+
+    /// Drop the connection and reconnect when nothing arrived for this long, e.g. 15s;
+    /// 0s never does
+    #[arg(long, default_value = "15s", value_parser = humantime::parse_duration)]
+    idle_timeout: Duration,
+
+    /// Unsubscribe, close the connection and exit after running this long, e.g. 10s
+    #[arg(long, value_parser = humantime::parse_duration)]
+    duration: Option<Duration>,
+
+    /// Seconds between pings sent to keep the connection alive
+    #[arg(long, default_value_t = 30)]
+    ping_interval: u64,
+
+    /// Number of consecutive reconnection attempts before giving up, unlimited if omitted
+    #[arg(long)]
+    max_retries: Option<u32>,
+
+    /// Unix domain socket to create, every connected process receives a JSON snapshot of
+    /// each updated book per line
+    #[cfg(unix)]
+    #[arg(long)]
+    ipc_socket: Option<PathBuf>,
+
+    /// Serve the top of book of every symbol as JSON at /book on this address, e.g.
+    /// 127.0.0.1:9200
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    http_addr: Option<String>,
+
+    /// Serve Prometheus metrics on this address, e.g. 127.0.0.1:9100
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics_addr: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct BidEntry {
-    price: f64,
-    qty: f64,
+fn parse_facility(value: &str) -> Result<Facility, String> {
+    value
+        .parse()
+        .map_err(|_| format!("'{}' is not a syslog facility", value))
 }
 
-struct Orderbook {
-    bids_heap: BTreeSet<BidEntry>,
-    asks_heap: BTreeSet<AskEntry>,
+fn parse_depth(value: &str) -> Result<u32, String> {
+    let depth = value
+        .parse::<u32>()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if ALLOWED_DEPTHS.contains(&depth) {
+        Ok(depth)
+    } else {
+        Err(format!("depth must be one of {:?}", ALLOWED_DEPTHS))
+    }
 }
 
-impl Eq for AskEntry {}
+fn parse_ema_alpha(value: &str) -> Result<f64, String> {
+    let alpha = value
+        .parse::<f64>()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if alpha > 0.0 && alpha <= 1.0 {
+        Ok(alpha)
+    } else {
+        Err(String::from("alpha must be greater than 0 and at most 1"))
+    }
+}
 
-impl PartialEq<Self> for AskEntry {
-    fn eq(&self, other: &Self) -> bool {
-        self.price.eq(&other.price)
+fn parse_arbitrage_rate(value: &str) -> Result<f64, String> {
+    let rate = value
+        .parse::<f64>()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if rate > 0.0 && rate.is_finite() {
+        Ok(rate)
+    } else {
+        Err(String::from("rate must be a positive number"))
     }
 }
 
-impl PartialOrd<Self> for AskEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.price.partial_cmp(&other.price)
+fn parse_speed(value: &str) -> Result<f64, String> {
+    let speed = value
+        .parse::<f64>()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if speed > 0.0 && speed.is_finite() {
+        Ok(speed)
+    } else {
+        Err(String::from("speed must be a positive number"))
     }
 }
 
-impl Ord for AskEntry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.price.total_cmp(&other.price)
+/// Redraws one line per symbol in place, keeping the cursor on the first line. Each line
+/// starts with the direction the mid moved in since the mid remembered in `mids`, which
+/// is replaced by the current one.
+fn render(
+    out: &mut impl Write,
+    books: &HashMap<String, Orderbook>,
+    args: &Args,
+    mids: &mut HashMap<String, Decimal>,
+) -> io::Result<()> {
+    for (index, symbol) in args.symbols.iter().enumerate() {
+        let Some(orderbook) = books.get(symbol) else {
+            continue;
+        };
+        let mid = orderbook.mid_price();
+        let trend = trend(mids.get(symbol).copied(), mid);
+        if let Some(mid) = mid {
+            mids.insert(symbol.clone(), mid);
+        }
+        let top_of_book = orderbook.format_top_of_book().unwrap_or_default();
+        let mut line = args.palette.top_of_book(&top_of_book);
+        if let Some(levels) = args.notional_levels {
+            // notionals are amounts of the quote currency, quoted like prices
+            let decimals = orderbook.price_decimals();
+            line.push_str(&format!(
+                " notional bid {} ask {}",
+                format_decimal(orderbook.bid_notional(levels), decimals),
+                format_decimal(orderbook.ask_notional(levels), decimals)
+            ));
+        }
+        if let Some(mid) = orderbook.weighted_mid().filter(|_| args.show_weighted_mid) {
+            // mids need one more decimal than prices
+            let decimals = orderbook.price_decimals() + 1;
+            line.push_str(&format!(" wmid {:.*}", decimals, mid));
+        }
+        if let Some(stats) = orderbook.latency_stats().filter(|_| args.show_latency) {
+            line.push_str(&format!(
+                " latency p99 {:.1}ms",
+                stats.p99.as_secs_f64() * 1000.0
+            ));
+        }
+        let window = orderbook
+            .spread_window()
+            .filter(|window| !window.is_empty());
+        if let Some(window) = window {
+            // the mean needs one more decimal than prices
+            let decimals = orderbook.price_decimals() + 1;
+            line.push_str(&format!(
+                " spread avg {} max {}",
+                format_decimal(window.mean().unwrap_or_default(), decimals),
+                format_decimal(window.max().unwrap_or_default(), decimals)
+            ));
+        }
+        let range = orderbook.session_low().zip(orderbook.session_high());
+        if let Some((low, high)) = range.filter(|_| args.session_range) {
+            // mids need one more decimal than prices
+            let decimals = orderbook.price_decimals() + 1;
+            line.push_str(&format!(
+                " session {} - {}",
+                format_decimal(low, decimals),
+                format_decimal(high, decimals)
+            ));
+        }
+        if let Some(age) = orderbook.age().filter(|age| *age > args.stale_after) {
+            line.push_str(&format!(" STALE {:.1}s", age.as_secs_f64()));
+        }
+        writeln!(
+            out,
+            "\x1b[2K{} {} {}",
+            trend,
+            args.palette
+                .symbol(index, &display_symbol(symbol, args.display_symbol_format)),
+            line
+        )?;
     }
+    write!(out, "\x1b[{}A", args.symbols.len())?;
+    out.flush()
 }
 
-impl Eq for BidEntry {}
+/// Shows `symbol`, separated by a slash or a dash, in `format`. Symbols without a
+/// separator, like Binance's, are shown as they are.
+fn display_symbol(symbol: &str, format: SymbolFormat) -> String {
+    let separator = match format {
+        SymbolFormat::Slash => "/",
+        SymbolFormat::Dash => "-",
+        SymbolFormat::Compact => "",
+    };
+    symbol.replace(['/', '-'], separator)
+}
 
-impl PartialEq<Self> for BidEntry {
-    fn eq(&self, other: &Self) -> bool {
-        self.price.eq(&other.price)
+/// `▲` if the mid went up from `previous` to `current`, `▼` if it went down and `=` if it
+/// stayed. Blank before there are two mids to compare.
+fn trend(previous: Option<Decimal>, current: Option<Decimal>) -> char {
+    match previous.zip(current) {
+        Some((previous, current)) if current > previous => '▲',
+        Some((previous, current)) if current < previous => '▼',
+        Some(_) => '=',
+        None => ' ',
     }
 }
 
-impl PartialOrd<Self> for BidEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.price.partial_cmp(&other.price)
+/// Redraws a depth ladder per symbol in place, keeping the cursor on the first line.
+fn render_ladders(
+    out: &mut impl Write,
+    symbols: &[String],
+    books: &HashMap<String, Orderbook>,
+    levels: usize,
+    palette: &Palette,
+    format: SymbolFormat,
+) -> io::Result<()> {
+    for (index, symbol) in symbols.iter().enumerate() {
+        let shown = display_symbol(symbol, format);
+        writeln!(out, "\x1b[2K{}", palette.symbol(index, &shown))?;
+        let ladder = books
+            .get(symbol)
+            .map(|orderbook| orderbook.render_ladder(levels, LADDER_WIDTH))
+            .unwrap_or_default();
+        for line in ladder.split('\n') {
+            writeln!(out, "\x1b[2K{}", palette.ladder_row(line))?;
+        }
     }
+    write!(
+        out,
+        "\x1b[{}A",
+        rendered_lines(symbols.len(), OutputMode::Ladder, levels)
+    )?;
+    out.flush()
 }
 
-impl Ord for BidEntry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.price.total_cmp(&other.price)
+/// Number of lines the in-place output modes draw for `symbols` books.
+fn rendered_lines(symbols: usize, output: OutputMode, ladder_levels: usize) -> usize {
+    match output {
+        OutputMode::Pretty => symbols,
+        OutputMode::Json | OutputMode::Csv => 0,
+        // symbol header, the ask rows, the spread line and the bid rows
+        OutputMode::Ladder => symbols * (2 * ladder_levels + 2),
     }
 }
 
-const SIGMA: f64 = 0.00000001;
+/// Row below the in-place output the status line is drawn on, `None` if it isn't shown.
+fn status_row(args: &Args) -> Option<usize> {
+    let lines = rendered_lines(args.symbols.len(), args.output, args.ladder_levels);
+    (args.status && !args.quiet && lines > 0).then_some(lines)
+}
+
+/// Redraws the status line `row` lines below the cursor and moves back, leaving the lines
+/// in between as they are.
+fn render_status(out: &mut impl Write, stats: &ConnectionStats, row: usize) -> io::Result<()> {
+    write!(
+        out,
+        "{}\x1b[2Kstatus {}\r\x1b[{}A",
+        "\n".repeat(row),
+        stats.format_status(),
+        row
+    )?;
+    out.flush()
+}
+
+/// Updates the connection state and redraws the status line if it's shown.
+fn show_state(args: &Args, stats: &mut ConnectionStats, state: ConnectionState) {
+    stats.set_state(state);
+    if let Some(row) = status_row(args) {
+        // a closed stdout is noticed by the sinks
+        let _ = render_status(&mut io::stdout().lock(), stats, row);
+    }
+}
 
-impl Orderbook {
-    fn new() -> Self {
-        Orderbook {
-            bids_heap: BTreeSet::new(),
-            asks_heap: BTreeSet::new(),
+/// Writes each trade as one line, JSON for the JSON output. Like bars, trades scroll up
+/// above the books the in-place modes redraw below them.
+fn print_trades(out: &mut impl Write, trades: &[Trade], output: OutputMode) -> io::Result<()> {
+    for trade in trades {
+        let side = match trade.side {
+            TradeSide::Buy => "buy",
+            TradeSide::Sell => "sell",
+        };
+        let time = trade.timestamp.map(humantime::format_rfc3339_micros);
+        match output {
+            OutputMode::Json => {
+                let line = serde_json::json!({
+                    "symbol": trade.symbol,
+                    "trade": {
+                        "side": side,
+                        "price": trade.price,
+                        "qty": trade.qty,
+                        "timestamp": time.map(|time| time.to_string()),
+                    },
+                });
+                writeln!(out, "{}", line)?;
+            }
+            // trade rows would break the CSV columns
+            OutputMode::Csv => {}
+            OutputMode::Pretty | OutputMode::Ladder => {
+                let time = time.map(|time| time.to_string()).unwrap_or_default();
+                writeln!(
+                    out,
+                    "\x1b[2K{} {} trade {} {} @ {}",
+                    trade.symbol, time, side, trade.qty, trade.price
+                )?;
+            }
         }
     }
+    out.flush()
+}
+
+/// columns of the CSV output, in the order [`csv_row`] fills them
+const CSV_HEADER: [&str; 8] = [
+    "timestamp",
+    "symbol",
+    "best_bid",
+    "best_bid_qty",
+    "best_ask",
+    "best_ask_qty",
+    "spread",
+    "mid",
+];
+
+/// Top of book values of `orderbook` as CSV fields, see [`CSV_HEADER`]. The timestamp is
+/// the book's last update in unix milliseconds; missing values are empty fields.
+fn csv_row(orderbook: &Orderbook) -> Vec<String> {
+    let timestamp = orderbook.last_update().map(|last_update| {
+        last_update
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    });
+    let field = |value: Option<String>| value.unwrap_or_default();
+    let (bid, ask) = (orderbook.best_bid(), orderbook.best_ask());
+    vec![
+        field(timestamp.map(|timestamp| timestamp.to_string())),
+        orderbook.symbol().to_string(),
+        field(bid.map(|bid| bid.price.to_string())),
+        field(bid.map(|bid| bid.qty.to_string())),
+        field(ask.map(|ask| ask.price.to_string())),
+        field(ask.map(|ask| ask.qty.to_string())),
+        field(orderbook.spread().map(|spread| spread.to_string())),
+        field(orderbook.mid_price().map(|mid| mid.to_string())),
+    ]
+}
+
+/// Writes a CSV row for each given book.
+fn print_csv_rows(
+    out: &mut impl Write,
+    symbols: &[String],
+    books: &HashMap<String, Orderbook>,
+) -> io::Result<()> {
+    for orderbook in symbols.iter().filter_map(|symbol| books.get(symbol)) {
+        writeln!(out, "{}", csv_row(orderbook).join(","))?;
+    }
+    Ok(())
+}
+
+/// Writes the snapshot of each given book as one JSON line.
+fn print_snapshots(
+    out: &mut impl Write,
+    symbols: &[String],
+    books: &HashMap<String, Orderbook>,
+) -> io::Result<()> {
+    for orderbook in symbols.iter().filter_map(|symbol| books.get(symbol)) {
+        let line = serde_json::to_string(&orderbook.to_snapshot()).unwrap();
+        writeln!(out, "{}", line)?;
+    }
+    out.flush()
+}
 
-    pub(crate) fn evaluate(&mut self, message: OrderbookMessage) {
-        if let Some(ref type_name) = message.type_name {
-            match type_name.as_str() {
-                "snapshot" => self.handle_snapshot(message),
-                "update" => self.handle_update(message),
-                _ => {}
+/// Writes the mid price bar each given book just completed as one line, JSON for the
+/// JSON output. Bars scroll up above the books the in-place modes redraw below them.
+fn print_bars(
+    out: &mut impl Write,
+    symbols: &[String],
+    books: &HashMap<String, Orderbook>,
+    output: OutputMode,
+) -> io::Result<()> {
+    for orderbook in symbols.iter().filter_map(|symbol| books.get(symbol)) {
+        let Some(bar) = orderbook.completed_bar() else {
+            continue;
+        };
+        match output {
+            OutputMode::Json => {
+                let line = serde_json::json!({ "symbol": orderbook.symbol(), "ohlc": bar });
+                writeln!(out, "{}", line)?;
+            }
+            // bar rows would break the CSV columns
+            OutputMode::Csv => {}
+            OutputMode::Pretty | OutputMode::Ladder => {
+                let start =
+                    humantime::format_rfc3339_millis(UNIX_EPOCH + Duration::from_millis(bar.start));
+                writeln!(
+                    out,
+                    "\x1b[2K{} {} O {} H {} L {} C {}",
+                    orderbook.symbol(),
+                    start,
+                    bar.open,
+                    bar.high,
+                    bar.low,
+                    bar.close
+                )?;
             }
         }
     }
+    Ok(())
+}
 
-    fn render(&self) {
-        match (
-            self.bids_heap.iter().next(),
-            self.asks_heap.iter().next_back(),
-        ) {
-            (Some(bid), Some(ask)) => {
-                print!(
-                    "\r\x1b[2KBID {:?} {:.10} <-> ASK {:?} {:.10}",
-                    bid.price, bid.qty, bid.price, ask.qty
-                );
-                std::io::stdout().flush().unwrap();
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    logging::init(log_target(&args));
+    if let Some(path) = args.config.clone() {
+        match Config::load(&path) {
+            Ok(config) => config.apply(&mut args, &matches),
+            Err(error) => {
+                error!("Couldn't load config file {:?}. {}", path, error);
+                std::process::exit(1);
             }
-            _ => {}
         }
     }
 
-    fn handle_snapshot(&mut self, message: OrderbookMessage) {
-        self.bids_heap.clear();
-        self.asks_heap.clear();
-        self.handle_update(message);
+    if args.exchange == Exchange::Kraken {
+        normalize_symbols(&mut args);
     }
 
-    fn handle_update(&mut self, message: OrderbookMessage) {
-        message.data.unwrap().iter().for_each(|entry| {
-            match &entry.bids {
-                Some(bids) => {
-                    bids.iter().for_each(|bid| {
-                        if bid.qty < SIGMA {
-                            self.bids_heap
-                                .retain(|entry| entry.price - bid.price > SIGMA);
-                        } else {
-                            self.bids_heap.insert(BidEntry {
-                                price: bid.price,
-                                qty: bid.qty,
-                            });
-                        }
-                    });
-                }
-                _ => {}
-            }
-
-            match &entry.asks {
-                Some(asks) => {
-                    asks.iter().for_each(|ask| {
-                        if ask.qty < SIGMA {
-                            self.asks_heap
-                                .retain(|entry| entry.price - ask.price > SIGMA);
-                        } else {
-                            self.asks_heap.insert(AskEntry {
-                                price: ask.price,
-                                qty: ask.qty,
-                            });
-                        }
-                    });
+    let no_color = std::env::var("NO_COLOR").ok();
+    args.palette = Palette::new(args.color, io::stdout().is_terminal(), no_color.as_deref());
+
+    if let Err(error) = ctrlc::set_handler(|| {
+        if SHUTDOWN.swap(true, Ordering::SeqCst) {
+            // a second Ctrl-C doesn't wait for the connection to close
+            std::process::exit(130);
+        }
+        SHUTDOWN_SIGNAL.notify_one();
+    }) {
+        error!("Couldn't install the Ctrl-C handler. {}", error);
+    }
+    #[cfg(unix)]
+    if let Err(error) =
+        signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&DUMP_REQUESTED))
+    {
+        error!("Couldn't install the SIGUSR1 handler. {}", error);
+    }
+
+    if args.api_key.is_some() && args.exchange != Exchange::Kraken {
+        error!("--api-key is only supported for Kraken");
+        std::process::exit(1);
+    }
+    if args.arbitrage_rate.is_some() && args.symbols.len() < 2 {
+        error!("--arbitrage-rate needs two symbols");
+        std::process::exit(1);
+    }
+    if args.rest_snapshot && args.exchange != Exchange::Kraken {
+        error!("--rest-snapshot is only supported for Kraken, Binance always seeds over REST");
+        std::process::exit(1);
+    }
+
+    if args.trades && args.exchange != Exchange::Kraken {
+        error!("--trades is only supported for Kraken");
+        std::process::exit(1);
+    }
+
+    if args.print_subscription {
+        println!("{}", new_feed(&args, None).subscribe_message());
+        return;
+    }
+
+    // nothing is received to measure without a connection
+    let metrics = match args.replay.is_some() || args.load_snapshot.is_some() {
+        true => None,
+        false => start_metrics(&args),
+    };
+    let sinks = match args.output_queue {
+        Some(capacity) => QueuedSink::spawn(args.clone(), capacity, metrics.clone(), new_sinks)
+            .map(|sink| vec![Box::new(sink) as Box<dyn OutputSink>]),
+        None => new_sinks(&args),
+    };
+    let mut sinks = match sinks {
+        Ok(sinks) => sinks,
+        Err(error) => {
+            error!("Couldn't set up the output. {}", error);
+            return;
+        }
+    };
+    let session = SessionStats::new();
+
+    #[cfg(unix)]
+    let ipc = match args
+        .ipc_socket
+        .as_deref()
+        .map(IpcPublisher::bind)
+        .transpose()
+    {
+        Ok(ipc) => ipc,
+        Err(error) => {
+            error!("Couldn't listen on {:?}. {}", args.ipc_socket, error);
+            return;
+        }
+    };
+    #[cfg(not(unix))]
+    let ipc = None;
+
+    let loaded = match args
+        .load_snapshot
+        .as_deref()
+        .map(|path| load_books(path, &args))
+    {
+        Some(Ok((books, loaded))) => {
+            write_sinks(&mut sinks, |sink| sink.on_update(&args, &books, &loaded));
+            Some(books)
+        }
+        Some(Err(error)) => {
+            error!(
+                "Couldn't load snapshot file {:?}. {}",
+                args.load_snapshot, error
+            );
+            return;
+        }
+        None => None,
+    };
+    if args.replay.is_some() || loaded.is_some() {
+        let mut books = loaded.unwrap_or_else(|| new_books(&args));
+        let mut stats = ConnectionStats::with_clock(Arc::clone(&args.clock));
+        if let Some(path) = &args.replay {
+            replay(
+                path,
+                &args,
+                &mut books,
+                &mut stats,
+                Some(&session),
+                ipc.as_ref(),
+                &mut sinks,
+            );
+        }
+        // a queued output writes out what is left when dropped
+        drop(sinks);
+        finish_render(&args);
+        eprintln!(
+            "{}",
+            summary(&stats, &session.counts(), &books, &args.symbols)
+        );
+        return;
+    }
+
+    let url = websocket_url(&args);
+    if let Err(error) = url.into_client_request() {
+        error!("Invalid websocket url {}. {}", url, error);
+        return;
+    }
+    let connector = match tls::connector(args.tls_ca.as_deref(), args.tls_insecure) {
+        Ok(connector) => connector,
+        Err(error) => {
+            error!("Couldn't set up TLS. {}", error);
+            return;
+        }
+    };
+    let recorder = args
+        .record
+        .as_deref()
+        .map(|path| {
+            Recorder::create(path, args.record_gzip)
+                .map(|recorder| recorder.with_clock(args.clock.clone()))
+        })
+        .transpose();
+    let mut recorder = match recorder {
+        Ok(recorder) => recorder,
+        Err(error) => {
+            error!("Couldn't open recording file {:?}. {}", args.record, error);
+            return;
+        }
+    };
+
+    let mut books = HashMap::new();
+    let mut attempt = 0;
+    let mut connected_before = false;
+    let mut stats = ConnectionStats::with_clock(Arc::clone(&args.clock));
+    loop {
+        let mut rate_limited = false;
+        show_state(&args, &mut stats, ConnectionState::Connecting);
+        let connected =
+            tokio_tungstenite::connect_async_tls_with_config(url, None, false, connector.clone());
+        match connected.await {
+            Ok((client, _)) => {
+                attempt = 0;
+                show_state(&args, &mut stats, ConnectionState::Connected);
+                if connected_before {
+                    session.record_reconnect();
+                }
+                if let Some(metrics) = metrics.as_deref().filter(|_| connected_before) {
+                    metrics.record_reconnect();
+                }
+                connected_before = true;
+                let retry = handle_connection(
+                    client,
+                    &args,
+                    &mut books,
+                    recorder.as_mut(),
+                    metrics.as_deref(),
+                    Some(&session),
+                    ipc.as_ref(),
+                    &mut sinks,
+                    &mut stats,
+                )
+                .await;
+                show_state(&args, &mut stats, ConnectionState::Disconnected);
+                if !retry {
+                    drop(sinks);
+                    finish_render(&args);
+                    finish_recording(recorder);
+                    std::process::exit(1);
                 }
-                _ => {}
+                if SHUTDOWN.load(Ordering::SeqCst) {
+                    break;
+                }
+                warn!("Connection to {} closed", url);
             }
-        })
+            Err(error) => {
+                let failure = ConnectFailure::classify(url, &error).await;
+                error!("Couldn't connect to the websocket, {}. {}", failure, error);
+                show_state(&args, &mut stats, ConnectionState::Disconnected);
+                if !failure.retries() {
+                    drop(sinks);
+                    finish_render(&args);
+                    finish_recording(recorder);
+                    std::process::exit(1);
+                }
+                rate_limited = failure == ConnectFailure::RateLimited;
+            }
+        }
+
+        if run_expired(stats.started(), args.clock.instant(), args.duration) {
+            break;
+        }
+        if args
+            .max_retries
+            .is_some_and(|max_retries| attempt >= max_retries)
+        {
+            error!("Giving up after {} reconnection attempts", attempt);
+            break;
+        }
+        // backing off from the start would only hit the rate limit again
+        let delay = if rate_limited {
+            MAX_BACKOFF
+        } else {
+            backoff_delay(attempt)
+        };
+        warn!("Reconnecting in {:?}", delay);
+        if !sleep_unless_shutdown(delay).await {
+            break;
+        }
+        attempt += 1;
+    }
+    drop(sinks);
+    finish_render(&args);
+    finish_recording(recorder);
+    eprintln!(
+        "{}",
+        summary(&stats, &session.counts(), &books, &args.symbols)
+    );
+}
+
+/// Where `args` asks for the log records to go.
+fn log_target(args: &Args) -> LogTarget {
+    if args.syslog {
+        LogTarget::Syslog {
+            facility: args.syslog_facility,
+            tag: args.syslog_tag.clone(),
+        }
+    } else {
+        LogTarget::Stderr(args.log_format)
+    }
+}
+
+/// Completes the recording, if there is one, so that it can be replayed in full.
+fn finish_recording(recorder: Option<Recorder<RecordingFile>>) {
+    if let Some(Err(error)) = recorder.map(Recorder::finish) {
+        error!("Couldn't finish recording. {}", error);
     }
 }
 
-fn main() {
-    env_logger::init();
+/// Rewrites the symbols and the keys of the configured tick sizes the way Kraken spells
+/// them.
+fn normalize_symbols(args: &mut Args) {
+    args.symbols = args
+        .symbols
+        .iter()
+        .map(|symbol| normalize_symbol(symbol))
+        .collect();
+    args.tick_sizes = args
+        .tick_sizes
+        .drain()
+        .map(|(symbol, tick_size)| (normalize_symbol(&symbol), tick_size))
+        .collect();
+}
 
-    let client_builder = ClientBuilder::new("wss://ws.kraken.com/v2");
-    let websocket_client = client_builder.unwrap().connect(None);
-    match websocket_client {
-        Ok(client) => {
-            handle_connection(client);
+/// Starts the metrics server if `--metrics-addr` was given.
+#[cfg(feature = "metrics")]
+fn start_metrics(args: &Args) -> Option<Arc<Metrics>> {
+    let addr = args.metrics_addr.as_deref()?;
+    let metrics = Arc::new(Metrics::new());
+    match hft_orderbook::serve_metrics(addr, Arc::clone(&metrics)) {
+        Ok(_) => {
+            info!("Serving metrics on {}", addr);
+            Some(metrics)
         }
         Err(error) => {
-            error!("Couldn't connect to the websocket. {}", error);
+            error!("Couldn't serve metrics on {}. {}", addr, error);
+            None
         }
     }
 }
 
-fn handle_connection(mut client: Client<Box<dyn NetworkStream + Send>>) {
-    let subscription = get_subscription();
-    let result = serde_json::to_string(&subscription);
-    let message = Message::text(result.unwrap());
+#[cfg(not(feature = "metrics"))]
+fn start_metrics(_args: &Args) -> Option<Arc<Metrics>> {
+    None
+}
+
+/// Sleeps for `duration`, returning `false` early if a shutdown was requested.
+async fn sleep_unless_shutdown(duration: Duration) -> bool {
+    if SHUTDOWN.load(Ordering::SeqCst) {
+        return false;
+    }
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => true,
+        _ = SHUTDOWN_SIGNAL.notified() => false,
+    }
+}
+
+/// Moves the cursor below the lines render() keeps redrawing.
+fn finish_render(args: &Args) {
+    if args.quiet {
+        return;
+    }
+    let lines = rendered_lines(args.symbols.len(), args.output, args.ladder_levels)
+        + usize::from(status_row(args).is_some());
+    if lines > 0 {
+        // stdout may already be closed, which is fine as nothing is left to move past
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "{}", "\n".repeat(lines)).and_then(|_| stdout.flush());
+    }
+}
+
+/// Delay before the given reconnection attempt, doubling from one second up to a cap.
+fn backoff_delay(attempt: u32) -> Duration {
+    let seconds = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+    Duration::from_secs(seconds).min(MAX_BACKOFF)
+}
 
-    client.send_message(&message).unwrap();
+fn websocket_url(args: &Args) -> &str {
+    match (&args.url, args.exchange) {
+        (Some(url), _) => url,
+        (None, Exchange::Kraken) if args.api_key.is_some() => KRAKEN_AUTH_URL,
+        (None, Exchange::Kraken) => KRAKEN_URL,
+        (None, Exchange::Binance) => BINANCE_URL,
+    }
+}
 
-    let mut orderbook = Orderbook::new();
+/// Builds the feed for `args`; with a websocket token it also subscribes to the private
+/// channels.
+fn new_feed(args: &Args, token: Option<String>) -> Box<dyn ExchangeFeed> {
+    match args.exchange {
+        Exchange::Kraken => {
+            let feed =
+                KrakenFeed::new(&args.symbols, args.depth).with_clock(Arc::clone(&args.clock));
+            let feed = if args.rest_snapshot {
+                feed.with_rest_snapshots()
+            } else {
+                feed
+            };
+            let feed = if args.trades {
+                feed.with_trades()
+            } else {
+                feed
+            };
+            match token {
+                Some(token) => Box::new(feed.with_private_channels(token, &args.private_channels)),
+                None => Box::new(feed),
+            }
+        }
+        Exchange::Binance => {
+            Box::new(BinanceFeed::new(&args.symbols).with_clock(Arc::clone(&args.clock)))
+        }
+    }
+}
 
-    client.incoming_messages().for_each(|result| match result {
-        Ok(message) => match message {
-            OwnedMessage::Text(text) => {
-                let result1 = serde_json::from_str::<OrderbookMessage>(text.as_str());
-                match result1 {
-                    Ok(orderbook_message) => match orderbook_message.channel.as_deref() {
-                        Some("book") => {
-                            orderbook.evaluate(orderbook_message);
-                            orderbook.render();
-                        }
-                        _ => {}
-                    },
-                    Err(err) => {
-                        error!("Error while parsing message: {}", err);
-                    }
+fn new_book(args: &Args, symbol: &str) -> Orderbook {
+    let orderbook = Orderbook::new(symbol)
+        .with_clock(Arc::clone(&args.clock))
+        .with_precision(args.price_precision, args.qty_precision)
+        .with_max_depth(args.depth as usize)
+        .with_qty_epsilon(args.qty_epsilon);
+    let orderbook = match args.price_decimals {
+        Some(decimals) => orderbook.with_price_decimals(decimals),
+        None => orderbook,
+    };
+    let orderbook = match args.qty_decimals {
+        Some(decimals) => orderbook.with_qty_decimals(decimals),
+        None => orderbook,
+    };
+    let orderbook = match args.ema_alpha {
+        Some(alpha) => orderbook.with_ema_alpha(alpha),
+        None => orderbook,
+    };
+    let orderbook = match args.min_levels {
+        Some(min_levels) => orderbook.with_min_levels(min_levels),
+        None => orderbook,
+    };
+    let orderbook = match args.max_spread {
+        Some(max_spread) => orderbook.with_max_spread(max_spread),
+        None => orderbook,
+    };
+    let orderbook = match args.spread_window {
+        Some(size) => orderbook.with_spread_window(size),
+        None => orderbook,
+    };
+    let orderbook = match args.ohlc {
+        Some(interval) => orderbook.with_ohlc_interval(interval),
+        None => orderbook,
+    };
+    let tick_size = args
+        .tick_size
+        .or_else(|| args.tick_sizes.get(symbol).copied());
+    match tick_size {
+        Some(tick_size) => orderbook.with_tick_size(tick_size),
+        None => orderbook,
+    }
+}
+
+/// Lowest and highest mid price of each book, to carry over into the books replacing them;
+/// empty with --reset-session-range.
+fn session_ranges(
+    args: &Args,
+    books: &HashMap<String, Orderbook>,
+) -> HashMap<String, (Decimal, Decimal)> {
+    if args.reset_session_range {
+        return HashMap::new();
+    }
+    books
+        .iter()
+        .filter_map(|(symbol, orderbook)| {
+            let range = orderbook.session_low().zip(orderbook.session_high());
+            range.map(|range| (symbol.clone(), range))
+        })
+        .collect()
+}
+
+/// Creates an empty book for `symbol` that continues the session range in `ranges`.
+fn carried_book(
+    args: &Args,
+    symbol: &str,
+    ranges: &HashMap<String, (Decimal, Decimal)>,
+) -> Orderbook {
+    let orderbook = new_book(args, symbol);
+    match ranges.get(symbol) {
+        Some((low, high)) => orderbook.with_session_range(*low, *high),
+        None => orderbook,
+    }
+}
+
+/// Sinks for the configured output on stdout, unless quiet, the output files and the
+/// HTTP endpoint.
+fn new_sinks(args: &Args) -> io::Result<Vec<Box<dyn OutputSink>>> {
+    let mut sinks = Vec::new();
+    if !args.quiet && !args.dump_once {
+        sinks.push(output_sink(args.output, io::stdout().lock())?);
+    }
+    if let Some(path) = &args.json_file {
+        let writer = BufWriter::new(File::create(path)?);
+        sinks.push(Box::new(JsonSink::new(writer)));
+    }
+    if let Some(path) = &args.csv_file {
+        let writer = BufWriter::new(File::create(path)?);
+        sinks.push(Box::new(CsvSink::new(writer)?));
+    }
+    #[cfg(feature = "http")]
+    if let Some(addr) = &args.http_addr {
+        let endpoint = Arc::new(BookEndpoint::new());
+        hft_orderbook::serve_book_endpoint(addr, Arc::clone(&endpoint))
+            .map_err(io::Error::other)?;
+        info!("Serving the books on http://{}/book", addr);
+        sinks.push(Box::new(EndpointSink::new(endpoint)));
+    }
+    if args.on_bbo_change && !sinks.is_empty() {
+        return Ok(vec![Box::new(BboChangeSink::new(sinks))]);
+    }
+    Ok(sinks)
+}
+
+/// Creates an empty book for every configured symbol.
+fn new_books(args: &Args) -> HashMap<String, Orderbook> {
+    args.symbols
+        .iter()
+        .map(|symbol| (symbol.clone(), new_book(args, symbol)))
+        .collect()
+}
+
+/// Applies a single text message from the feed and hands the updated books to the sinks.
+#[allow(clippy::too_many_arguments)]
+fn handle_text(
+    text: &str,
+    feed: &mut impl ExchangeFeed,
+    args: &Args,
+    books: &mut HashMap<String, Orderbook>,
+    metrics: Option<&Metrics>,
+    session: Option<&SessionStats>,
+    ipc: Option<&IpcPublisher>,
+    sinks: &mut [Box<dyn OutputSink>],
+) -> Action {
+    handle_batch(&[text], feed, args, books, metrics, session, ipc, sinks)
+}
+
+/// Applies the book entries of text messages from the feed in order and hands each book
+/// they updated to the sinks once. Entries read before a trade or a rejection are applied
+/// and handed on first, so the output keeps the order of the feed. Of the checksums in each
+/// such run of entries only the last one of each book is checked, once all are applied.
+#[allow(clippy::too_many_arguments)]
+fn handle_batch(
+    texts: &[&str],
+    feed: &mut impl ExchangeFeed,
+    args: &Args,
+    books: &mut HashMap<String, Orderbook>,
+    metrics: Option<&Metrics>,
+    session: Option<&SessionStats>,
+    ipc: Option<&IpcPublisher>,
+    sinks: &mut [Box<dyn OutputSink>],
+) -> Action {
+    let mut updates = Vec::new();
+    for text in texts {
+        match feed.parse(text) {
+            Some(FeedEvent::Book(entries)) => updates.extend(entries),
+            Some(FeedEvent::Trades(trades)) => {
+                let pending = mem::take(&mut updates);
+                let action = apply_updates(pending, args, books, metrics, session, ipc, sinks);
+                if action != Action::Continue {
+                    return action;
+                }
+                let action = write_sinks(sinks, |sink| sink.on_trades(&trades));
+                if action == Action::Shutdown {
+                    return action;
                 }
             }
-            _ => {
-                error!("Unhandled message type");
+            Some(FeedEvent::Rejected(_)) => {
+                let pending = mem::take(&mut updates);
+                let action = apply_updates(pending, args, books, metrics, session, ipc, sinks);
+                if action == Action::Shutdown {
+                    return action;
+                }
+                return Action::Exit;
             }
-        },
-        Err(error) => {
-            error!("Error while receiving message: {}", error);
+            None => {}
         }
-    });
+    }
+    apply_updates(updates, args, books, metrics, session, ipc, sinks)
 }
 
-fn get_subscription() -> Subscription {
-    Subscription {
-        method: String::from("subscribe"),
-        params: SubscriptionParams {
-            channel: String::from("book"),
-            symbol: Vec::from([String::from("BTC/USD")]),
-            depth: 25
-        },
+/// Applies `updates` to the books and hands each book they updated to the sinks once.
+fn apply_updates(
+    mut updates: Vec<BookUpdate>,
+    args: &Args,
+    books: &mut HashMap<String, Orderbook>,
+    metrics: Option<&Metrics>,
+    session: Option<&SessionStats>,
+    ipc: Option<&IpcPublisher>,
+    sinks: &mut [Box<dyn OutputSink>],
+) -> Action {
+    if updates.is_empty() {
+        return Action::Continue;
+    }
+    keep_last_checksums(&mut updates);
+    let applied = route_entries(books, updates);
+    if applied.is_empty() {
+        return Action::Continue;
+    }
+    let mut seen = HashSet::new();
+    let updated: Vec<String> = applied
+        .iter()
+        .filter(|applied| seen.insert(&applied.symbol))
+        .map(|applied| applied.symbol.clone())
+        .collect();
+    if args.strict {
+        for orderbook in updated.iter().filter_map(|symbol| books.get(symbol)) {
+            if let Err(error) = orderbook.validate() {
+                error!("Invalid {} book: {}", orderbook.symbol(), error);
+            }
+        }
+    }
+    if let Some(metrics) = metrics {
+        metrics.observe(books, &applied);
+    }
+    if let Some(session) = session {
+        session.observe(&applied);
+    }
+    if let Some(ipc) = ipc {
+        for orderbook in updated.iter().filter_map(|symbol| books.get(symbol)) {
+            ipc.publish(&serde_json::to_string(&orderbook.to_snapshot()).unwrap());
+        }
+    }
+    if let Some(rate) = args.arbitrage_rate {
+        log_arbitrage(books, &args.symbols, rate, args.arbitrage_fee);
+    }
+    if write_sinks(sinks, |sink| sink.on_update(args, books, &updated)) == Action::Shutdown {
+        return Action::Shutdown;
+    }
+    let snapshotted = |symbol: &String| books.get(symbol).is_some_and(Orderbook::has_snapshot);
+    if args.dump_once && args.symbols.iter().all(snapshotted) {
+        if let Err(error) = dump_once(&mut io::stdout().lock(), books, &args.symbols) {
+            error!("Couldn't print the books. {}", error);
+        }
+        return Action::Shutdown;
+    }
+    if args.exit_on_wide_spread && updated.iter().any(|symbol| books[symbol].is_wide()) {
+        return Action::Exit;
+    }
+    if args.resync_on_crossed && updated.iter().any(|symbol| books[symbol].is_crossed()) {
+        return Action::Resubscribe;
+    }
+    if let Some(limit) = args.resync_after_checksum_failures {
+        let failing = updated
+            .iter()
+            .find(|symbol| books[*symbol].consecutive_checksum_failures() >= limit);
+        if let Some(symbol) = failing {
+            warn!(
+                "{} checksums in a row didn't match the {} book",
+                limit, symbol
+            );
+            return Action::Resubscribe;
+        }
+    }
+    Action::Continue
+}
+
+/// Hands the output to every sink with `write`; a closed output shuts down.
+fn write_sinks(
+    sinks: &mut [Box<dyn OutputSink>],
+    mut write: impl FnMut(&mut dyn OutputSink) -> io::Result<()>,
+) -> Action {
+    for sink in sinks.iter_mut() {
+        match write(sink.as_mut()) {
+            Err(error) if error.kind() == io::ErrorKind::BrokenPipe => {
+                info!("Output was closed, shutting down");
+                return Action::Shutdown;
+            }
+            Err(error) => error!("Couldn't write output. {}", error),
+            Ok(()) => {}
+        }
+    }
+    Action::Continue
+}
+
+/// Logs the arbitrage between the books of the first two `symbols`, if there is one.
+fn log_arbitrage(books: &HashMap<String, Orderbook>, symbols: &[String], rate: f64, fee: f64) {
+    let [a, b, ..] = symbols else {
+        return;
+    };
+    let (Some(book_a), Some(book_b)) = (books.get(a), books.get(b)) else {
+        return;
+    };
+    if let Some(arbitrage) = cross_spread(book_a, book_b, rate, fee) {
+        info!(
+            "Arbitrage: buy {} {} at {:.2}, sell on {} at {:.2} for {:.3}% after fees",
+            arbitrage.qty,
+            arbitrage.buy_symbol,
+            arbitrage.buy_price,
+            arbitrage.sell_symbol,
+            arbitrage.sell_price,
+            arbitrage.profit * 100.0
+        );
+    }
+}
+
+/// Views of `books` with their levels merged into price buckets of size `bucket`.
+fn bucketed(books: &HashMap<String, Orderbook>, bucket: f64) -> HashMap<String, Orderbook> {
+    books
+        .iter()
+        .map(|(symbol, orderbook)| (symbol.clone(), orderbook.bucketed_view(bucket)))
+        .collect()
+}
+
+/// Writes each book to `<symbol>-<unix millis>.json` in the working directory, named after
+/// the current time of `clock`.
+fn dump_books(books: &HashMap<String, Orderbook>, clock: &dyn Clock) {
+    let millis = clock
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    for (symbol, orderbook) in books {
+        let path = format!("{}-{}.json", symbol.replace('/', "-"), millis);
+        let written = File::create(&path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            orderbook.dump_to_writer(&mut writer)?;
+            writer.flush()
+        });
+        match written {
+            Ok(()) => info!("Dumped the {} book to {}", symbol, path),
+            Err(error) => error!("Couldn't dump the {} book to {}. {}", symbol, path, error),
+        }
+    }
+}
+
+/// Writes every level of the books of `symbols`, in their order, for --dump-once.
+fn dump_once(
+    writer: &mut impl Write,
+    books: &HashMap<String, Orderbook>,
+    symbols: &[String],
+) -> io::Result<()> {
+    for orderbook in symbols.iter().filter_map(|symbol| books.get(symbol)) {
+        orderbook.dump_to_writer(writer)?;
+    }
+    writer.flush()
+}
+
+/// Books configured by `args` holding the snapshots in the file at `path`, as written by
+/// [`dump_once`], along with the symbols of the loaded ones in the order of the file.
+fn load_books(path: &Path, args: &Args) -> io::Result<(HashMap<String, Orderbook>, Vec<String>)> {
+    let file = BufReader::new(File::open(path)?);
+    let mut books = new_books(args);
+    let mut loaded = Vec::new();
+    for snapshot in serde_json::Deserializer::from_reader(file).into_iter::<BookSnapshot>() {
+        let snapshot = snapshot?;
+        books
+            .entry(snapshot.symbol.clone())
+            .or_insert_with(|| new_book(args, &snapshot.symbol))
+            .load_snapshot(&snapshot);
+        loaded.push(snapshot.symbol);
+    }
+    Ok((books, loaded))
+}
+
+/// Rebuilds `books` from a recording instead of connecting to the exchange, counting the
+/// replayed messages in `stats`.
+fn replay(
+    path: &Path,
+    args: &Args,
+    books: &mut HashMap<String, Orderbook>,
+    stats: &mut ConnectionStats,
+    session: Option<&SessionStats>,
+    ipc: Option<&IpcPublisher>,
+    sinks: &mut [Box<dyn OutputSink>],
+) {
+    let file = match open_recording(path) {
+        Ok(file) => file,
+        Err(error) => {
+            error!("Couldn't open replay file {:?}. {}", path, error);
+            return;
+        }
+    };
+
+    let mut feed = new_feed(args, None);
+    let mut previous_received_at = None;
+    for line in file.lines() {
+        if SHUTDOWN.load(Ordering::SeqCst) {
+            break;
+        }
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                error!("Couldn't read replay file {:?}. {}", path, error);
+                break;
+            }
+        };
+        let Some(message) = parse_recorded_line(&line) else {
+            continue;
+        };
+
+        if args.step {
+            // end of input stops stepping and plays the rest right away
+            let _ = io::stdin().lock().read_line(&mut String::new());
+        } else if args.realtime || args.speed.is_some() {
+            if let (Some(previous), Some(current)) = (previous_received_at, message.received_at) {
+                thread::sleep(replay_delay(previous, current, args.speed.unwrap_or(1.0)));
+            }
+            previous_received_at = message.received_at;
+        }
+        stats.record_message();
+        if handle_text(
+            message.text,
+            &mut feed,
+            args,
+            books,
+            None,
+            session,
+            ipc,
+            sinks,
+        ) == Action::Shutdown
+        {
+            break;
+        }
+        if DUMP_REQUESTED.swap(false, Ordering::SeqCst) {
+            dump_books(books, &*args.clock);
+        }
+    }
+}
+
+/// How long to wait between messages recorded at `previous` and `current` unix millis when
+/// replaying at `speed` times the recorded pace.
+fn replay_delay(previous: u64, current: u64, speed: f64) -> Duration {
+    Duration::from_millis(current.saturating_sub(previous)).div_f64(speed)
+}
+
+/// Decodes a binary message as UTF-8, for venues sending JSON in binary frames. The error
+/// tells where decoding failed and whether the message ended in the middle of a character,
+/// as a message cut short at a fragment boundary would.
+fn binary_text(data: Bytes) -> Result<Utf8Bytes, String> {
+    let len = data.len();
+    Utf8Bytes::try_from(data).map_err(|error| match error.error_len() {
+        Some(_) => format!(
+            "Invalid UTF-8 at byte {} of {}. {}",
+            error.valid_up_to(),
+            len,
+            error
+        ),
+        None => format!(
+            "{} bytes end in the middle of a character at byte {}, the message may be truncated",
+            len,
+            error.valid_up_to()
+        ),
+    })
+}
+
+/// Whether a run started at `started` used up the `--duration` it was given by `now`; a run
+/// without a duration never expires.
+fn run_expired(started: Instant, now: Instant, duration: Option<Duration>) -> bool {
+    duration.is_some_and(|duration| now.saturating_duration_since(started) >= duration)
+}
+
+/// One line summing up a run: the messages received, the time taken, the `session` counts
+/// and the final spread and session mid range of each book, `-` for books without one.
+fn summary(
+    stats: &ConnectionStats,
+    session: &SessionCounts,
+    books: &HashMap<String, Orderbook>,
+    symbols: &[String],
+) -> String {
+    let books: Vec<String> = symbols
+        .iter()
+        .map(|symbol| {
+            let orderbook = books.get(symbol);
+            let spread = orderbook.and_then(Orderbook::spread);
+            let range = orderbook
+                .and_then(|orderbook| orderbook.session_low().zip(orderbook.session_high()));
+            let spread = spread.map_or(String::from("-"), |spread| spread.to_string());
+            let range = range.map_or(String::from("-"), |(low, high)| {
+                format!("{} - {}", low, high)
+            });
+            format!("{} spread {} session {}", symbol, spread, range)
+        })
+        .collect();
+    format!(
+        "Processed {} messages in {}: {} updates, {} snapshots, {} reconnects, {} checksum \
+         failures; {}",
+        stats.messages(),
+        humantime::format_duration(Duration::from_secs(stats.uptime().as_secs())),
+        session.updates,
+        session.snapshots,
+        session.reconnects,
+        session.checksum_failures,
+        books.join(", ")
+    )
+}
+
+/// Whether a connection whose last message arrived at `last_message` was silent for
+/// `timeout` by `now`; a zero timeout never expires.
+fn idle_timed_out(last_message: Instant, now: Instant, timeout: Duration) -> bool {
+    !timeout.is_zero() && now.saturating_duration_since(last_message) >= timeout
+}
+
+/// Fetches a websocket token on a blocking thread, leaving the runtime to the connection.
+async fn websockets_token(api_key: &str, api_secret: &str) -> Result<String, String> {
+    let (api_key, api_secret) = (api_key.to_string(), api_secret.to_string());
+    tokio::task::spawn_blocking(move || fetch_websockets_token(&api_key, &api_secret))
+        .await
+        .map_err(|error| error.to_string())?
+}
+
+/// Streams the books into `books`, which are replaced by fresh ones on subscribing, until
+/// the connection ends.
+/// Returns `false` when the subscription was rejected and reconnecting is pointless.
+#[allow(clippy::too_many_arguments)]
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    client: WebSocketStream<S>,
+    args: &Args,
+    books: &mut HashMap<String, Orderbook>,
+    mut recorder: Option<&mut Recorder<RecordingFile>>,
+    metrics: Option<&Metrics>,
+    session: Option<&SessionStats>,
+    ipc: Option<&IpcPublisher>,
+    sinks: &mut [Box<dyn OutputSink>],
+    stats: &mut ConnectionStats,
+) -> bool {
+    let token = match (&args.api_key, &args.api_secret) {
+        (Some(api_key), Some(api_secret)) => match websockets_token(api_key, api_secret).await {
+            Ok(token) => Some(token),
+            Err(error) => {
+                error!("Couldn't get a websocket token. {}", error);
+                return true;
+            }
+        },
+        _ => None,
+    };
+    let mut connection = Connection::new(client, new_feed(args, token));
+    if let Err(error) = connection.subscribe().await {
+        error!("Couldn't send subscription. {}", error);
+        return true;
+    }
+
+    let ranges = session_ranges(args, books);
+    *books = args
+        .symbols
+        .iter()
+        .map(|symbol| (symbol.clone(), carried_book(args, symbol, &ranges)))
+        .collect();
+
+    let ping_interval = Duration::from_secs(args.ping_interval);
+    let mut ping =
+        tokio::time::interval_at(tokio::time::Instant::now() + ping_interval, ping_interval);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut watchdog = tokio::time::interval(WATCHDOG_INTERVAL);
+    watchdog.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_message = args.clock.instant();
+    let status_row = status_row(args);
+    let mut status = tokio::time::interval(STATUS_INTERVAL);
+    status.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // the run started on `args.clock`, whose time may not be tokio's
+    let deadline = args.duration.map(|duration| {
+        let elapsed = args
+            .clock
+            .instant()
+            .saturating_duration_since(stats.started());
+        tokio::time::Instant::now() + duration.saturating_sub(elapsed)
+    });
+    let mut retry = true;
+    // messages waiting for the end of the batch window and when it ends
+    let mut batch = Vec::new();
+    let mut batch_due = None;
+    loop {
+        let mut flush = false;
+        tokio::select! {
+            message = connection.recv_message() => {
+                last_message = args.clock.instant();
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => Some(text),
+                    Some(Ok(Message::Binary(data))) => match binary_text(data) {
+                        Ok(text) => Some(text),
+                        Err(error) => {
+                            error!("Couldn't decode binary message. {}", error);
+                            None
+                        }
+                    },
+                    // pings are answered by the websocket stream itself
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => None,
+                    Some(Ok(Message::Close(frame))) => {
+                        // the stream answers the close frame and ends on the next read
+                        warn!("Server closed the connection. {:?}", frame);
+                        None
+                    }
+                    Some(Ok(Message::Frame(frame))) => {
+                        // fragments are reassembled into whole messages before they get here
+                        error!(
+                            "Unexpected raw {:?} frame of {} bytes, final: {}",
+                            frame.header().opcode,
+                            frame.len(),
+                            frame.header().is_final
+                        );
+                        None
+                    }
+                    Some(Err(error)) => {
+                        error!("Error while receiving message: {}", error);
+                        break;
+                    }
+                    None => break,
+                };
+                if let Some(text) = text {
+                    stats.record_message();
+                    if let Some(recorder) = recorder.as_mut() {
+                        if let Err(error) = recorder.record(&text) {
+                            error!("Couldn't record message. {}", error);
+                        }
+                    }
+                    batch.push(text);
+                    match args.batch_window {
+                        Some(window) => {
+                            batch_due.get_or_insert_with(|| tokio::time::Instant::now() + window);
+                        }
+                        None => flush = true,
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(batch_due.unwrap_or_else(tokio::time::Instant::now)),
+                if batch_due.is_some() => {
+                flush = true;
+            }
+            _ = ping.tick() => {
+                let pinged = connection.send_message(Message::Ping(Default::default())).await;
+                if let Err(error) = pinged {
+                    error!("Couldn't send ping. {}", error);
+                    break;
+                }
+            }
+            _ = watchdog.tick() => {
+                if idle_timed_out(last_message, args.clock.instant(), args.idle_timeout) {
+                    warn!("Nothing received for {:?}, reconnecting", args.idle_timeout);
+                    break;
+                }
+            }
+            _ = status.tick(), if status_row.is_some() => {
+                if let Some(row) = status_row {
+                    // a closed stdout is noticed by the sinks
+                    let _ = render_status(&mut io::stdout().lock(), stats, row);
+                }
+            }
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                if deadline.is_some() => {}
+            _ = SHUTDOWN_SIGNAL.notified() => {}
+        }
+
+        if flush {
+            batch_due = None;
+            let texts: Vec<&str> = batch.iter().map(Utf8Bytes::as_str).collect();
+            let feed = connection.feed_mut();
+            let action = handle_batch(&texts, feed, args, books, metrics, session, ipc, sinks);
+            batch.clear();
+            let checksums: Vec<bool> = books
+                .values()
+                .filter_map(Orderbook::last_checksum_matched)
+                .collect();
+            if !checksums.is_empty() {
+                stats.record_checksum(checksums.iter().all(|matched| *matched));
+            }
+            match action {
+                Action::Continue => {}
+                Action::Resubscribe => {
+                    warn!("Resubscribing to get a fresh snapshot");
+                    if let Some(metrics) = metrics {
+                        metrics.record_resubscribe();
+                    }
+                    let ranges = session_ranges(args, books);
+                    let resubscribed = connection
+                        .resubscribe(books, |symbol| carried_book(args, symbol, &ranges))
+                        .await;
+                    if let Err(error) = resubscribed {
+                        error!("Couldn't resubscribe. {}", error);
+                        break;
+                    }
+                }
+                Action::Exit => {
+                    if let Err(error) = connection.close(CLOSE_TIMEOUT).await {
+                        error!("Couldn't close the connection. {}", error);
+                    }
+                    retry = false;
+                    break;
+                }
+                Action::Shutdown => {
+                    SHUTDOWN.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+
+        if run_expired(stats.started(), args.clock.instant(), args.duration) {
+            info!("Ran for {:?}, shutting down", stats.uptime());
+            if let Err(error) = connection.unsubscribe().await {
+                error!("Couldn't unsubscribe. {}", error);
+            }
+            SHUTDOWN.store(true, Ordering::SeqCst);
+        }
+
+        // SIGUSR1 only raises a flag, so dumps wait for the next message or ping
+        if DUMP_REQUESTED.swap(false, Ordering::SeqCst) {
+            dump_books(books, &*args.clock);
+        }
+        if SHUTDOWN.load(Ordering::SeqCst) {
+            if let Err(error) = connection.close(CLOSE_TIMEOUT).await {
+                error!("Couldn't close the connection. {}", error);
+            }
+            break;
+        }
+    }
+
+    // the messages of an unfinished batch were received, so they still count
+    if !batch.is_empty() {
+        let texts: Vec<&str> = batch.iter().map(Utf8Bytes::as_str).collect();
+        let feed = connection.feed_mut();
+        handle_batch(&texts, feed, args, books, metrics, session, ipc, sinks);
+    }
+    if let Some(recorder) = recorder {
+        if let Err(error) = recorder.flush() {
+            error!("Couldn't flush recording. {}", error);
+        }
+    }
+    retry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hft_orderbook::{MockClock, PriceLevel, Side};
+    use mock_server::MockServer;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::time::SystemTime;
+
+    /// Writer whose output stays readable after it was moved into a sink.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A sink for `args.output` writing to the returned buffer.
+    fn buffered_sinks(args: &Args) -> (Vec<Box<dyn OutputSink>>, SharedBuffer) {
+        let buffer = SharedBuffer::default();
+        (
+            vec![output_sink(args.output, buffer.clone()).unwrap()],
+            buffer,
+        )
+    }
+
+    /// Book with a bid at 100 and an ask at 101, one of each.
+    const BTC_SNAPSHOT: &str = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0}],"asks":[{"price":101.0,"qty":1.0}]}]}"#;
+
+    /// Handles `texts` in order with new books for `args`, returning the books and what
+    /// the output sink wrote.
+    fn apply_texts(args: &Args, texts: &[&str]) -> (HashMap<String, Orderbook>, String) {
+        let mut feed = new_feed(args, None);
+        let mut books = new_books(args);
+        let (mut sinks, out) = buffered_sinks(args);
+        for text in texts {
+            handle_text(
+                text, &mut feed, args, &mut books, None, None, None, &mut sinks,
+            );
+        }
+        (books, out.contents())
+    }
+
+    #[test]
+    fn syslog_is_only_logged_to_when_asked_for() {
+        let args = Args::parse_from(["hft-orderbook", "--log-format", "json"]);
+        assert!(matches!(
+            log_target(&args),
+            LogTarget::Stderr(LogFormat::Json)
+        ));
+
+        let args = Args::parse_from(["hft-orderbook", "--syslog", "--syslog-facility", "local3"]);
+        let target = log_target(&args);
+        assert!(
+            matches!(
+                &target,
+                LogTarget::Syslog { facility: Facility::LOG_LOCAL3, tag } if tag == "hft-orderbook"
+            ),
+            "{:?}",
+            target
+        );
+
+        assert!(Args::try_parse_from(["hft-orderbook", "--syslog-tag", "feed"]).is_err());
+        assert!(
+            Args::try_parse_from(["hft-orderbook", "--syslog", "--syslog-facility", "nope"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn backoff_delay_doubles_up_to_cap() {
+        let delays: Vec<u64> = (0..8)
+            .map(|attempt| backoff_delay(attempt).as_secs())
+            .collect();
+
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30, 30]);
+        assert_eq!(backoff_delay(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn replay_delays_scale_with_the_speed() {
+        let delays: Vec<Duration> = [1.0, 2.0, 0.5, 4.0, 1000.0]
+            .into_iter()
+            .map(|speed| replay_delay(1_700_000_000_000, 1_700_000_000_200, speed))
+            .collect();
+
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(200),
+                Duration::from_millis(100),
+                Duration::from_millis(400),
+                Duration::from_millis(50),
+                Duration::from_micros(200),
+            ]
+        );
+        // messages recorded out of order are replayed right away
+        assert_eq!(replay_delay(200, 100, 2.0), Duration::ZERO);
+        assert!(
+            Args::try_parse_from(["hft-orderbook", "--replay", "a.log", "--speed", "0"]).is_err()
+        );
+        assert!(Args::try_parse_from([
+            "hft-orderbook",
+            "--replay",
+            "a.log",
+            "--step",
+            "--speed",
+            "2"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn binary_messages_are_decoded_as_utf8() {
+        let text = r#"{"channel":"heartbeat"}"#;
+        assert_eq!(
+            binary_text(Bytes::from_static(text.as_bytes())).unwrap(),
+            text
+        );
+
+        let invalid = binary_text(Bytes::from_static(b"{\"a\":\xff}")).unwrap_err();
+        assert!(
+            invalid.starts_with("Invalid UTF-8 at byte 5 of 7."),
+            "{}",
+            invalid
+        );
+
+        // the first two bytes of the three byte encoding of €
+        let truncated = binary_text(Bytes::from_static(b"price \xe2\x82")).unwrap_err();
+        assert_eq!(
+            truncated,
+            "8 bytes end in the middle of a character at byte 6, the message may be truncated"
+        );
+    }
+
+    #[test]
+    fn duration_expires_once_it_has_passed() {
+        let started = Instant::now();
+        let duration = Some(Duration::from_secs(10));
+
+        assert!(!run_expired(started, started, duration));
+        assert!(!run_expired(
+            started,
+            started + Duration::from_millis(9999),
+            duration
+        ));
+        assert!(run_expired(
+            started,
+            started + Duration::from_secs(10),
+            duration
+        ));
+        assert!(run_expired(
+            started,
+            started + Duration::from_secs(60),
+            duration
+        ));
+        assert!(!run_expired(
+            started,
+            started + Duration::from_secs(3600),
+            None
+        ));
+    }
+
+    #[test]
+    fn summary_reports_messages_and_final_spreads() {
+        let snapshot = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0}],"asks":[{"price":100.5,"qty":1.0}]}]}"#;
+        let args = Args::parse_from([
+            "hft-orderbook",
+            "--symbol",
+            "BTC/USD",
+            "--symbol",
+            "ETH/USD",
+        ]);
+        let mut books = new_books(&args);
+        handle_text(
+            snapshot,
+            &mut new_feed(&args, None),
+            &args,
+            &mut books,
+            None,
+            None,
+            None,
+            &mut [],
+        );
+        let mut stats = ConnectionStats::new();
+        stats.record_message();
+        let session = SessionCounts {
+            updates: 3,
+            snapshots: 1,
+            reconnects: 1,
+            checksum_failures: 2,
+        };
+
+        assert_eq!(
+            summary(&stats, &session, &books, &args.symbols),
+            "Processed 1 messages in 0s: 3 updates, 1 snapshots, 1 reconnects, 2 checksum \
+             failures; BTC/USD spread 0.5 session 100.25 - 100.25, ETH/USD spread - session -"
+        );
+    }
+
+    #[test]
+    fn idle_timeout_expires_after_silence() {
+        let last_message = Instant::now();
+        let timeout = Duration::from_secs(15);
+
+        assert!(!idle_timed_out(last_message, last_message, timeout));
+        assert!(!idle_timed_out(
+            last_message,
+            last_message + Duration::from_secs(14),
+            timeout
+        ));
+        assert!(idle_timed_out(
+            last_message,
+            last_message + timeout,
+            timeout
+        ));
+        assert!(!idle_timed_out(
+            last_message,
+            last_message + Duration::from_secs(3600),
+            Duration::ZERO
+        ));
+        // a message arriving after the check started doesn't count as silence
+        assert!(!idle_timed_out(
+            last_message + timeout,
+            last_message,
+            timeout
+        ));
+    }
+
+    #[test]
+    fn parse_depth_accepts_only_kraken_depths() {
+        assert_eq!(parse_depth("100"), Ok(100));
+        assert_eq!(parse_depth("1"), Ok(1));
+        assert!(parse_depth("50").is_err());
+        assert!(parse_depth("abc").is_err());
+    }
+
+    #[test]
+    fn kraken_symbols_are_normalized_before_subscribing() {
+        let mut args = Args::parse_from([
+            "hft-orderbook",
+            "--symbol",
+            "eth-usd",
+            "--symbol",
+            "sol/usd",
+        ]);
+        args.tick_sizes
+            .insert(String::from("eth_usd"), Decimal::ONE);
+        normalize_symbols(&mut args);
+
+        assert_eq!(args.symbols, vec!["ETH/USD", "SOL/USD"]);
+        assert_eq!(args.tick_sizes["ETH/USD"], Decimal::ONE);
+        assert_eq!(
+            new_feed(&args, None).subscribe_message(),
+            r#"{"method":"subscribe","params":{"channel":"book","symbol":["ETH/USD","SOL/USD"],"depth":25}}"#
+        );
+    }
+
+    #[test]
+    fn printed_subscription_follows_the_arguments() {
+        let args = Args::parse_from([
+            "hft-orderbook",
+            "--symbol",
+            "ETH/USD",
+            "--symbol",
+            "SOL/USD",
+            "--depth",
+            "100",
+            "--print-subscription",
+        ]);
+
+        assert_eq!(
+            new_feed(&args, None).subscribe_message(),
+            r#"{"method":"subscribe","params":{"channel":"book","symbol":["ETH/USD","SOL/USD"],"depth":100}}"#
+        );
+    }
+
+    #[test]
+    fn depth_one_follows_the_top_of_book_from_the_ticker() {
+        let ticker = |bid: &str, bid_qty: &str, ask: &str, ask_qty: &str| {
+            format!(
+                r#"{{"channel":"ticker","type":"update","data":[{{"symbol":"BTC/USD","bid":{},"bid_qty":{},"ask":{},"ask_qty":{},"last":100.0,"volume":10.0}}]}}"#,
+                bid, bid_qty, ask, ask_qty
+            )
+        };
+        for output in ["pretty", "ladder"] {
+            let args = Args::parse_from(["hft-orderbook", "--levels", "1", "--output", output]);
+            let mut feed = new_feed(&args, None);
+            let mut books = new_books(&args);
+            let (mut sinks, out) = buffered_sinks(&args);
+            assert_eq!(
+                feed.subscribe_message(),
+                r#"{"method":"subscribe","params":{"channel":"ticker","symbol":["BTC/USD"],"event_trigger":"bbo"}}"#
+            );
+            let mut top = |text: &str| {
+                handle_text(
+                    text, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+                );
+                let orderbook = &books["BTC/USD"];
+                assert!(orderbook.depth(Side::Bid) <= 1 && orderbook.depth(Side::Ask) <= 1);
+                let price = |level: Option<PriceLevel>| {
+                    level.map(|level| level.price.normalize().to_string())
+                };
+                (
+                    price(orderbook.best_bid()),
+                    price(orderbook.best_ask()),
+                    orderbook.is_crossed(),
+                )
+            };
+
+            let some = |price: &str| Some(String::from(price));
+            assert_eq!(
+                top(&ticker("100.0", "1.0", "100.5", "2.0")),
+                (some("100"), some("100.5"), false)
+            );
+            // the old best bid is gone, not left behind as a second level
+            assert_eq!(
+                top(&ticker("99.5", "3.0", "100.5", "1.0")),
+                (some("99.5"), some("100.5"), false)
+            );
+            assert_eq!(
+                top(&ticker("99.5", "0.0", "100.0", "1.0")),
+                (None, some("100"), false)
+            );
+            assert_eq!(
+                top(&ticker("100.5", "1.0", "100.0", "1.0")),
+                (some("100.5"), some("100"), true)
+            );
+            assert_eq!(books["BTC/USD"].last_checksum_matched(), None);
+            assert!(out.contents().contains("BTC/USD"), "{} output", output);
+        }
+    }
+
+    #[test]
+    fn notional_is_rendered_with_the_price_decimals() {
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":99.9,"qty":0.123}],"asks":[]}]}"#;
+        let args = Args::parse_from(["hft-orderbook", "--notional-levels", "2"]);
+        let (_, out) = apply_texts(&args, &[BTC_SNAPSHOT, update]);
+        assert!(out.contains(" notional bid 112.3 ask 101.0"), "{}", out);
+    }
+
+    #[test]
+    fn spread_window_is_rendered_with_one_more_decimal_than_prices() {
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.5,"qty":1.0}],"asks":[]}]}"#;
+        let args = Args::parse_from(["hft-orderbook", "--spread-window", "3"]);
+        let (_, out) = apply_texts(&args, &[BTC_SNAPSHOT, update, update]);
+        assert!(out.contains(" spread avg 0.67 max 1.00"), "{}", out);
+    }
+
+    #[test]
+    fn session_range_is_carried_over_unless_reset() {
+        let args = Args::parse_from(["hft-orderbook", "--session-range"]);
+        let (books, out) = apply_texts(&args, &[BTC_SNAPSHOT]);
+        assert!(out.contains(" session 100.50 - 100.50"));
+
+        let carried = carried_book(&args, "BTC/USD", &session_ranges(&args, &books));
+        assert_eq!(carried.session_high(), Some("100.5".parse().unwrap()));
+
+        let args = Args::parse_from(["hft-orderbook", "--reset-session-range"]);
+        let renewed = carried_book(&args, "BTC/USD", &session_ranges(&args, &books));
+        assert_eq!(renewed.session_high(), None);
+    }
+
+    #[test]
+    fn status_line_is_drawn_below_the_books() {
+        let args = Args::parse_from([
+            "hft-orderbook",
+            "--symbol",
+            "ETH/USD",
+            "--symbol",
+            "SOL/USD",
+            "--status",
+        ]);
+        assert_eq!(status_row(&args), Some(2));
+        let json = Args::parse_from(["hft-orderbook", "--output", "json", "--status"]);
+        assert_eq!(status_row(&json), None);
+
+        let mut out = Vec::new();
+        render_status(&mut out, &ConnectionStats::new(), 2).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\n\n\x1b[2Kstatus connecting messages 0 checksum - up 0s\r\x1b[2A"
+        );
+    }
+
+    #[test]
+    fn csv_rows_follow_the_header_columns() {
+        let snapshot = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.5}],"asks":[{"price":100.5,"qty":2.0}],"timestamp":"2023-10-06T17:35:55.440295Z"}]}"#;
+        let args = Args::parse_from(["hft-orderbook", "--output", "csv"]);
+        let mut books = new_books(&args);
+        assert_eq!(
+            csv_row(&books["BTC/USD"]),
+            vec!["", "BTC/USD", "", "", "", "", "", ""]
+        );
+
+        let (mut sinks, out) = buffered_sinks(&args);
+        handle_text(
+            snapshot,
+            &mut new_feed(&args, None),
+            &args,
+            &mut books,
+            None,
+            None,
+            None,
+            &mut sinks,
+        );
+
+        let row = csv_row(&books["BTC/USD"]);
+        assert_eq!(row.len(), CSV_HEADER.len());
+        assert_eq!(
+            row,
+            vec![
+                "1696613755440",
+                "BTC/USD",
+                "100",
+                "1.5",
+                "100.5",
+                "2",
+                "0.5",
+                "100.25"
+            ]
+        );
+        assert_eq!(
+            out.contents(),
+            format!(
+                "{}\n1696613755440,BTC/USD,100,1.5,100.5,2,0.5,100.25\n",
+                CSV_HEADER.join(",")
+            )
+        );
+    }
+
+    #[test]
+    fn repeated_checksum_failures_trigger_a_resubscribe() {
+        let bad_update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":99.0,"qty":1.0}],"asks":[],"checksum":1}]}"#;
+        let args = Args::parse_from([
+            "hft-orderbook",
+            "--quiet",
+            "--resync-after-checksum-failures",
+            "3",
+        ]);
+        let mut feed = new_feed(&args, None);
+        let mut books = new_books(&args);
+        let mut sinks = Vec::new();
+        let mut handle = |text: &str| {
+            handle_text(
+                text, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+            )
+        };
+
+        assert_eq!(handle(BTC_SNAPSHOT), Action::Continue);
+        assert_eq!(handle(bad_update), Action::Continue);
+        assert_eq!(handle(bad_update), Action::Continue);
+        assert_eq!(handle(bad_update), Action::Resubscribe);
+    }
+
+    #[test]
+    fn a_wide_spread_exits_when_asked_to() {
+        let snapshot = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0}],"asks":[{"price":104.0,"qty":1.0},{"price":110.0,"qty":1.0}]}]}"#;
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[],"asks":[{"price":104.0,"qty":0.0}]}]}"#;
+        for (exit, expected) in [(false, Action::Continue), (true, Action::Exit)] {
+            let mut flags = vec!["hft-orderbook", "--quiet", "--max-spread", "5.0"];
+            if exit {
+                flags.push("--exit-on-wide-spread");
+            }
+            let args = Args::parse_from(flags);
+            let mut feed = new_feed(&args, None);
+            let mut books = new_books(&args);
+            let mut sinks = Vec::new();
+            let mut handle = |text: &str| {
+                handle_text(
+                    text, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+                )
+            };
+
+            assert_eq!(handle(snapshot), Action::Continue);
+            assert_eq!(handle(update), expected);
+        }
+    }
+
+    #[test]
+    fn dump_once_prints_every_level_of_each_book() {
+        let snapshot = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0},{"price":99.5,"qty":2.0}],"asks":[{"price":101.0,"qty":1.5}]}]}"#;
+        let args = Args::parse_from([
+            "hft-orderbook",
+            "--symbol",
+            "BTC/USD",
+            "--symbol",
+            "ETH/USD",
+        ]);
+        let mut feed = new_feed(&args, None);
+        let mut books = new_books(&args);
+        handle_text(
+            snapshot,
+            &mut feed,
+            &args,
+            &mut books,
+            None,
+            None,
+            None,
+            &mut [],
+        );
+
+        let mut out = Vec::new();
+        dump_once(&mut out, &books, &args.symbols).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let dumped: Vec<serde_json::Value> = serde_json::Deserializer::from_str(&out)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(dumped.len(), 2);
+        assert_eq!(dumped[0]["symbol"], "BTC/USD");
+        assert_eq!(
+            dumped[0]["bids"],
+            serde_json::json!([["100", "1"], ["99.5", "2"]])
+        );
+        assert_eq!(dumped[0]["asks"], serde_json::json!([["101", "1.5"]]));
+        assert_eq!(dumped[1]["symbol"], "ETH/USD");
+        assert_eq!(dumped[1]["bids"], serde_json::json!([]));
+        assert!(out.contains("\n  \"bids\": [\n"));
+    }
+
+    /// Sink reporting which of the books each update changed it was handed, then waiting
+    /// until it's allowed to go on, like an output stuck on a slow reader. Trades are
+    /// reported by their quantities, without waiting.
+    struct BlockingSink {
+        entered: mpsc::Sender<Vec<String>>,
+        proceed: mpsc::Receiver<()>,
+    }
+
+    impl OutputSink for BlockingSink {
+        fn on_update(
+            &mut self,
+            _args: &Args,
+            books: &HashMap<String, Orderbook>,
+            updated: &[String],
+        ) -> io::Result<()> {
+            let handed = updated.iter().filter(|symbol| books.contains_key(*symbol));
+            self.entered.send(handed.cloned().collect()).unwrap();
+            self.proceed.recv().unwrap();
+            Ok(())
+        }
+
+        fn on_trades(&mut self, trades: &[Trade]) -> io::Result<()> {
+            let qtys = trades.iter().map(|trade| trade.qty.to_string()).collect();
+            self.entered.send(qtys).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_full_output_queue_skips_updates_without_blocking() {
+        let args = Args::parse_from([
+            "hft-orderbook",
+            "--symbol",
+            "BTC/USD",
+            "--symbol",
+            "ETH/USD",
+        ]);
+        let books = new_books(&args);
+        let (entered_sender, entered) = mpsc::channel();
+        let (proceed, proceed_receiver) = mpsc::channel();
+        let metrics = Arc::new(Metrics::new());
+        let mut queued = QueuedSink::spawn(args.clone(), 1, Some(Arc::clone(&metrics)), |_| {
+            Ok(vec![Box::new(BlockingSink {
+                entered: entered_sender,
+                proceed: proceed_receiver,
+            }) as Box<dyn OutputSink>])
+        })
+        .unwrap();
+        let mut update = |symbol: &str| {
+            queued
+                .on_update(&args, &books, &[String::from(symbol)])
+                .unwrap()
+        };
+
+        // the first update is being written, the second waits in the queue
+        update("BTC/USD");
+        assert_eq!(entered.recv().unwrap(), vec!["BTC/USD"]);
+        update("BTC/USD");
+        update("ETH/USD");
+        assert!(metrics
+            .render()
+            .contains("orderbook_dropped_outputs_total 0\n"));
+        // the held back update is merged into this one
+        update("BTC/USD");
+        assert!(metrics
+            .render()
+            .contains("orderbook_dropped_outputs_total 1\n"));
+
+        proceed.send(()).unwrap();
+        assert_eq!(entered.recv().unwrap(), vec!["BTC/USD"]);
+        // the skipped books go out with the next update
+        update("BTC/USD");
+        assert!(metrics
+            .render()
+            .contains("orderbook_dropped_outputs_total 2\n"));
+        proceed.send(()).unwrap();
+        proceed.send(()).unwrap();
+        drop(queued);
+        assert_eq!(entered.recv().unwrap(), vec!["ETH/USD", "BTC/USD"]);
+        assert!(entered.recv().is_err());
+    }
+
+    #[test]
+    fn trades_wait_for_a_full_output_queue() {
+        let args = Args::parse_from(["hft-orderbook"]);
+        let books = new_books(&args);
+        let (entered_sender, entered) = mpsc::channel();
+        let (proceed, proceed_receiver) = mpsc::channel();
+        let metrics = Arc::new(Metrics::new());
+        let mut queued = QueuedSink::spawn(args.clone(), 1, Some(Arc::clone(&metrics)), |_| {
+            Ok(vec![Box::new(BlockingSink {
+                entered: entered_sender,
+                proceed: proceed_receiver,
+            }) as Box<dyn OutputSink>])
+        })
+        .unwrap();
+        let trade = |qty: u32| Trade {
+            symbol: String::from("BTC/USD"),
+            side: TradeSide::Buy,
+            price: Decimal::from(100),
+            qty: Decimal::from(qty),
+            timestamp: None,
+        };
+
+        queued
+            .on_update(&args, &books, &[String::from("BTC/USD")])
+            .unwrap();
+        assert_eq!(entered.recv().unwrap(), vec!["BTC/USD"]);
+        queued.on_trades(&[trade(1)]).unwrap();
+        queued.on_trades(&[trade(2)]).unwrap();
+        queued.on_trades(&[trade(3)]).unwrap();
+        // waiting trades aren't skipped updates
+        assert!(metrics
+            .render()
+            .contains("orderbook_dropped_outputs_total 0\n"));
+
+        proceed.send(()).unwrap();
+        assert_eq!(entered.recv().unwrap(), vec!["1"]);
+        queued.on_trades(&[trade(4)]).unwrap();
+        drop(queued);
+        assert_eq!(entered.recv().unwrap(), vec!["2", "3", "4"]);
+        assert!(entered.recv().is_err());
+    }
+
+    /// Sink recording which books each update it was handed changed.
+    struct CapturingSink(Rc<RefCell<Vec<Vec<String>>>>);
+
+    impl OutputSink for CapturingSink {
+        fn on_update(
+            &mut self,
+            _args: &Args,
+            _books: &HashMap<String, Orderbook>,
+            updated: &[String],
+        ) -> io::Result<()> {
+            self.0.borrow_mut().push(updated.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn every_sink_receives_the_updates() {
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.5,"qty":1.0}],"asks":[]}]}"#;
+        let args = Args::parse_from(["hft-orderbook"]);
+        let mut feed = new_feed(&args, None);
+        let mut books = new_books(&args);
+        let (mut sinks, out) = buffered_sinks(&args);
+        let captured = Rc::default();
+        sinks.push(Box::new(CapturingSink(Rc::clone(&captured))));
+
+        for text in [BTC_SNAPSHOT, update] {
+            handle_text(
+                text, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+            );
+        }
+
+        assert_eq!(out.contents().matches("BTC/USD BID").count(), 2);
+        assert!(out.contents().contains("BID 100.5 "));
+        assert_eq!(
+            *captured.borrow(),
+            vec![vec![String::from("BTC/USD")], vec![String::from("BTC/USD")]]
+        );
+    }
+
+    #[test]
+    fn trades_are_written_between_book_updates() {
+        let trade = r#"{"channel":"trade","type":"update","data":[{"symbol":"BTC/USD","side":"buy","price":101.0,"qty":0.25,"ord_type":"market","trade_id":1,"timestamp":"2023-10-06T17:35:55.440295Z"}]}"#;
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[],"asks":[{"price":101.0,"qty":0.75}]}]}"#;
+        let args = Args::parse_from(["hft-orderbook", "--trades", "--output", "json"]);
+        let feed = new_feed(&args, None);
+        assert!(feed.session_messages()[0].contains(r#""channel":"trade""#));
+
+        let (_, out) = apply_texts(&args, &[BTC_SNAPSHOT, trade, update]);
+
+        let lines: Vec<String> = out.lines().map(String::from).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            r#"{"symbol":"BTC/USD","trade":{"price":"101","qty":"0.25","side":"buy","timestamp":"2023-10-06T17:35:55.440295Z"}}"#
+        );
+    }
+
+    #[test]
+    fn batched_trades_are_written_between_book_updates() {
+        let trade = r#"{"channel":"trade","type":"update","data":[{"symbol":"BTC/USD","side":"buy","price":101.0,"qty":0.25,"ord_type":"market","trade_id":1,"timestamp":"2023-10-06T17:35:55.440295Z"}]}"#;
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[],"asks":[{"price":101.0,"qty":0.75}]}]}"#;
+        let args = Args::parse_from(["hft-orderbook", "--trades", "--output", "json"]);
+        let mut books = new_books(&args);
+        let (mut sinks, out) = buffered_sinks(&args);
+
+        let action = handle_batch(
+            &[BTC_SNAPSHOT, trade, update],
+            &mut new_feed(&args, None),
+            &args,
+            &mut books,
+            None,
+            None,
+            None,
+            &mut sinks,
+        );
+
+        assert_eq!(action, Action::Continue);
+        let lines: Vec<String> = out.contents().lines().map(String::from).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(r#"["101","1"]"#), "{}", lines[0]);
+        assert!(lines[1].contains(r#""trade""#), "{}", lines[1]);
+        assert!(lines[2].contains(r#"["101","0.75"]"#), "{}", lines[2]);
+    }
+
+    #[test]
+    fn batches_apply_their_entries_before_a_rejection() {
+        let rejected = r#"{"error":"Currency pair not supported XYZ/USD","method":"subscribe","success":false}"#;
+        let args = Args::parse_from(["hft-orderbook", "--output", "json"]);
+        let mut books = new_books(&args);
+        let (mut sinks, out) = buffered_sinks(&args);
+
+        let action = handle_batch(
+            &[BTC_SNAPSHOT, rejected],
+            &mut new_feed(&args, None),
+            &args,
+            &mut books,
+            None,
+            None,
+            None,
+            &mut sinks,
+        );
+
+        assert_eq!(action, Action::Exit);
+        assert_eq!(books["BTC/USD"].spread(), Some(Decimal::ONE));
+        assert_eq!(out.contents().lines().count(), 1);
+    }
+
+    #[test]
+    fn quiet_mode_updates_the_book_without_output() {
+        for output in ["pretty", "json", "ladder"] {
+            let args = Args::parse_from(["hft-orderbook", "--quiet", "--output", output]);
+            let mut feed = new_feed(&args, None);
+            let mut books = new_books(&args);
+            let mut sinks = new_sinks(&args).unwrap();
+
+            handle_text(
+                BTC_SNAPSHOT,
+                &mut feed,
+                &args,
+                &mut books,
+                None,
+                None,
+                None,
+                &mut sinks,
+            );
+
+            assert_eq!(books["BTC/USD"].spread(), Some(Decimal::ONE));
+            assert!(sinks.is_empty(), "{} output was written", output);
+        }
+
+        let args = Args::parse_from(["hft-orderbook", "--output", "json"]);
+        let (_, out) = apply_texts(&args, &[BTC_SNAPSHOT]);
+        assert!(!out.is_empty());
+    }
+
+    #[test]
+    fn nothing_renders_before_the_first_snapshot() {
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0}],"asks":[{"price":99.0,"qty":1.0}]}]}"#;
+        let args = Args::parse_from(["hft-orderbook"]);
+        let mut feed = new_feed(&args, None);
+        let mut books = new_books(&args);
+        let (mut sinks, out) = buffered_sinks(&args);
+
+        handle_text(
+            update, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+        );
+        assert!(out.contents().is_empty());
+        assert!(books["BTC/USD"].top_of_book().is_none());
+
+        handle_text(
+            BTC_SNAPSHOT,
+            &mut feed,
+            &args,
+            &mut books,
+            None,
+            None,
+            None,
+            &mut sinks,
+        );
+        assert!(out.contents().contains("BTC/USD BID 100"));
+        assert_eq!(books["BTC/USD"].spread(), Some(Decimal::ONE));
+    }
+
+    #[test]
+    fn throttled_rendering_still_applies_every_update() {
+        let args = Args::parse_from(["hft-orderbook", "--render-interval", "1h"]);
+        let updates: Vec<String> = ["100.5", "100.6", "100.7"]
+            .iter()
+            .map(|price| {
+                format!(
+                    r#"{{"channel":"book","type":"update","data":[{{"symbol":"BTC/USD","bids":[{{"price":{},"qty":1.0}}],"asks":[]}}]}}"#,
+                    price
+                )
+            })
+            .collect();
+        let mut texts = vec![BTC_SNAPSHOT];
+        texts.extend(updates.iter().map(String::as_str));
+
+        let (books, rendered) = apply_texts(&args, &texts);
+
+        assert_eq!(books["BTC/USD"].depth(Side::Bid), 4);
+        assert_eq!(
+            books["BTC/USD"].best_bid().unwrap().price.to_string(),
+            "100.7"
+        );
+        assert_eq!(rendered.matches("BTC/USD BID").count(), 1);
+        assert!(rendered.contains("BID 100.0 "));
+    }
+
+    #[test]
+    fn rendering_is_throttled_by_the_clock() {
+        let update = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.5,"qty":1.0}],"asks":[]}]}"#;
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        let mut args = Args::parse_from(["hft-orderbook", "--render-interval", "1s"]);
+        args.clock = clock.clone();
+        let mut feed = new_feed(&args, None);
+        let mut books = new_books(&args);
+        let (mut sinks, out) = buffered_sinks(&args);
+        let mut handle = |text: &str| {
+            handle_text(
+                text, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+            );
+            out.contents().matches("BTC/USD BID").count()
+        };
+
+        assert_eq!(handle(BTC_SNAPSHOT), 1);
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(handle(update), 1);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(handle(update), 2);
+    }
+
+    #[test]
+    fn symbols_are_shown_in_the_chosen_format() {
+        let cases = [
+            ("BTC/USD", SymbolFormat::Slash, "BTC/USD"),
+            ("BTC/USD", SymbolFormat::Dash, "BTC-USD"),
+            ("BTC/USD", SymbolFormat::Compact, "BTCUSD"),
+            ("ETH-EUR", SymbolFormat::Slash, "ETH/EUR"),
+            ("BTCUSDT", SymbolFormat::Dash, "BTCUSDT"),
+        ];
+        for (symbol, format, shown) in cases {
+            assert_eq!(display_symbol(symbol, format), shown);
+        }
+
+        let args = Args::parse_from(["hft-orderbook", "--display-symbol-format", "dash"]);
+        let (_, out) = apply_texts(&args, &[BTC_SNAPSHOT]);
+        assert!(out.contains("BTC-USD BID 100"));
+        assert!(new_feed(&args, None)
+            .subscribe_message()
+            .contains(r#""BTC/USD""#));
+    }
+
+    #[test]
+    fn trend_follows_the_mid_between_renders() {
+        let mid = |mid: &str| Some(mid.parse::<Decimal>().unwrap());
+
+        assert_eq!(trend(None, mid("100.5")), ' ');
+        assert_eq!(trend(mid("100.5"), None), ' ');
+        assert_eq!(trend(mid("100.5"), mid("100.75")), '▲');
+        assert_eq!(trend(mid("100.5"), mid("100.25")), '▼');
+        assert_eq!(trend(mid("100.5"), mid("100.50")), '=');
+    }
+
+    #[test]
+    fn dumped_books_load_back_with_every_level() {
+        let snapshot = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":27566.9,"qty":2.145},{"price":27566.5,"qty":0.00125}],"asks":[{"price":27567.1,"qty":0.743},{"price":27570.2,"qty":1.5}]},{"symbol":"ETH/USD","bids":[{"price":1650.11,"qty":3.0}],"asks":[{"price":1650.2,"qty":0.5}]}]}"#;
+        let args = Args::parse_from([
+            "hft-orderbook",
+            "--symbol",
+            "BTC/USD",
+            "--symbol",
+            "ETH/USD",
+        ]);
+        let mut feed = new_feed(&args, None);
+        let mut books = new_books(&args);
+        handle_text(
+            snapshot,
+            &mut feed,
+            &args,
+            &mut books,
+            None,
+            None,
+            None,
+            &mut [],
+        );
+        let path = std::env::temp_dir().join("hft-orderbook-load-snapshot.json");
+        let mut file = File::create(&path).unwrap();
+        dump_once(&mut file, &books, &args.symbols).unwrap();
+
+        let (loaded, symbols) = load_books(&path, &args).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // the loaded books never seed a connection
+        assert!(Args::try_parse_from([
+            "hft-orderbook",
+            "--load-snapshot",
+            "books.json",
+            "--record",
+            "feed.log"
+        ])
+        .is_err());
+
+        assert_eq!(symbols, args.symbols);
+        for symbol in &args.symbols {
+            let (dumped, loaded) = (&books[symbol], &loaded[symbol]);
+            assert_eq!(
+                loaded.iter_bids().collect::<Vec<_>>(),
+                dumped.iter_bids().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                loaded.iter_asks().collect::<Vec<_>>(),
+                dumped.iter_asks().collect::<Vec<_>>()
+            );
+            assert_eq!(loaded.subscribed_depth(), dumped.subscribed_depth());
+        }
+    }
+
+    #[test]
+    fn batches_count_every_entry_like_single_messages() {
+        let messages = [
+            BTC_SNAPSHOT,
+            r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.2,"qty":0.5}],"asks":[]}]}"#,
+            r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[],"asks":[{"price":100.7,"qty":1.5}],"checksum":1}]}"#,
+        ];
+        let counted = |batched: bool| {
+            let args = Args::parse_from(["hft-orderbook", "--quiet"]);
+            let mut feed = new_feed(&args, None);
+            let mut books = new_books(&args);
+            let metrics = Metrics::new();
+            let session = SessionStats::new();
+            let mut sinks = Vec::new();
+            if batched {
+                handle_batch(
+                    &messages,
+                    &mut feed,
+                    &args,
+                    &mut books,
+                    Some(&metrics),
+                    Some(&session),
+                    None,
+                    &mut sinks,
+                );
+            } else {
+                for message in messages {
+                    handle_text(
+                        message,
+                        &mut feed,
+                        &args,
+                        &mut books,
+                        Some(&metrics),
+                        Some(&session),
+                        None,
+                        &mut sinks,
+                    );
+                }
+            }
+            (session.counts(), metrics.render())
+        };
+
+        let (counts, rendered) = counted(false);
+        assert_eq!(
+            counts,
+            SessionCounts {
+                updates: 2,
+                snapshots: 1,
+                reconnects: 0,
+                checksum_failures: 1,
+            }
+        );
+        assert!(rendered.contains("orderbook_updates_total 3\n"));
+        assert!(rendered.contains("orderbook_checksum_failures_total 1\n"));
+        assert_eq!(counted(true), (counts, rendered));
+    }
+
+    #[test]
+    fn batches_end_in_the_same_books_as_single_messages() {
+        let messages = [
+            r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0},{"price":99.5,"qty":2.0}],"asks":[{"price":100.5,"qty":1.0}]}]}"#,
+            r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.2,"qty":0.5}],"asks":[],"checksum":1}]}"#,
+            r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":99.5,"qty":0.0}],"asks":[{"price":100.7,"qty":1.5}]}]}"#,
+        ];
+        let args = Args::parse_from(["hft-orderbook", "--output", "json"]);
+        let mut single = new_books(&args);
+        let mut feed = new_feed(&args, None);
+        let (mut sinks, single_out) = buffered_sinks(&args);
+        for message in messages {
+            handle_text(
+                message,
+                &mut feed,
+                &args,
+                &mut single,
+                None,
+                None,
+                None,
+                &mut sinks,
+            );
+        }
+        assert_eq!(single["BTC/USD"].last_checksum_matched(), Some(false));
+        let checksum = single["BTC/USD"].checksum();
+        let last = messages[2].replace("]}]}", &format!(r#"],"checksum":{}}}]}}"#, checksum));
+
+        let mut batched = new_books(&args);
+        let mut feed = new_feed(&args, None);
+        let (mut sinks, batched_out) = buffered_sinks(&args);
+        let action = handle_batch(
+            &[messages[0], messages[1], &last],
+            &mut feed,
+            &args,
+            &mut batched,
+            None,
+            None,
+            None,
+            &mut sinks,
+        );
+
+        assert_eq!(action, Action::Continue);
+        assert_eq!(
+            batched["BTC/USD"].iter_bids().collect::<Vec<_>>(),
+            single["BTC/USD"].iter_bids().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            batched["BTC/USD"].iter_asks().collect::<Vec<_>>(),
+            single["BTC/USD"].iter_asks().collect::<Vec<_>>()
+        );
+        assert_eq!(batched["BTC/USD"].last_checksum_matched(), Some(true));
+        assert_eq!(single_out.contents().lines().count(), 3);
+        assert_eq!(batched_out.contents().lines().count(), 1);
+    }
+
+    #[test]
+    fn bbo_change_output_skips_updates_below_the_top() {
+        let snapshot = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0},{"price":99.0,"qty":1.0}],"asks":[{"price":101.0,"qty":1.0},{"price":102.0,"qty":1.0}]}]}"#;
+        let deep = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":99.0,"qty":3.0},{"price":98.0,"qty":1.0}],"asks":[{"price":102.0,"qty":0.0}]}]}"#;
+        let resized = r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":2.0}],"asks":[]}]}"#;
+        let args = Args::parse_from(["hft-orderbook", "--output", "json", "--on-bbo-change"]);
+        let mut feed = new_feed(&args, None);
+        let mut books = new_books(&args);
+        let (sinks, out) = buffered_sinks(&args);
+        let mut sinks: Vec<Box<dyn OutputSink>> = vec![Box::new(BboChangeSink::new(sinks))];
+
+        handle_text(
+            snapshot, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+        );
+        assert_eq!(out.contents().lines().count(), 1);
+
+        handle_text(
+            deep, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+        );
+        assert_eq!(books["BTC/USD"].depth(Side::Bid), 3);
+        assert_eq!(out.contents().lines().count(), 1);
+
+        handle_text(
+            resized, &mut feed, &args, &mut books, None, None, None, &mut sinks,
+        );
+        let contents = out.contents();
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.lines().last().unwrap().contains(r#"["100","2"]"#));
+    }
+
+    /// Writer standing in for stdout piped into a reader that has exited.
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn closed_output_shuts_down_instead_of_panicking() {
+        for output in ["pretty", "json", "ladder"] {
+            let args = Args::parse_from(["hft-orderbook", "--output", output]);
+            let mut feed = new_feed(&args, None);
+            let mut books = new_books(&args);
+
+            assert!(render(&mut ClosedPipe, &books, &args, &mut HashMap::new()).is_err());
+            let action = handle_text(
+                BTC_SNAPSHOT,
+                &mut feed,
+                &args,
+                &mut books,
+                None,
+                None,
+                None,
+                &mut [output_sink(args.output, ClosedPipe).unwrap()],
+            );
+
+            assert_eq!(action, Action::Shutdown, "{} output", output);
+            assert_eq!(books["BTC/USD"].spread(), Some(Decimal::ONE));
+        }
+    }
+
+    #[test]
+    fn color_never_keeps_the_output_plain() {
+        for output in ["pretty", "ladder"] {
+            let rendered = |color: &str| {
+                let mut args =
+                    Args::parse_from(["hft-orderbook", "--output", output, "--color", color]);
+                // the test's stdout isn't a terminal either
+                args.palette = Palette::new(args.color, false, None);
+                apply_texts(&args, &[BTC_SNAPSHOT]).1
+            };
+
+            assert!(!rendered("never").contains("\x1b[0m"), "{} output", output);
+            assert!(!rendered("auto").contains("\x1b[0m"), "{} output", output);
+            assert!(rendered("always").contains("\x1b[0m"), "{} output", output);
+        }
+
+        let mut args = Args::parse_from(["hft-orderbook", "--output", "json", "--color", "always"]);
+        args.palette = Palette::new(args.color, true, None);
+        let (_, out) = apply_texts(&args, &[BTC_SNAPSHOT]);
+        assert!(!out.contains('\x1b'));
+    }
+
+    /// held by the tests running a connection, which a raised shutdown ends early
+    static CONNECTIONS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    #[tokio::test]
+    async fn connection_builds_books_from_a_mock_exchange() {
+        let _connection = CONNECTIONS.lock().await;
+        let server = MockServer::start(vec![
+            String::from(
+                r#"{"method":"subscribe","result":{"channel":"book","depth":10,"snapshot":true,"symbol":"BTC/USD"},"success":true}"#,
+            ),
+            String::from(
+                r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0},{"price":99.5,"qty":2.0}],"asks":[{"price":101.0,"qty":1.5}]}]}"#,
+            ),
+            String::from(r#"{"channel":"heartbeat"}"#),
+            String::from(
+                r#"{"channel":"book","type":"update","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":0.0}],"asks":[{"price":100.5,"qty":0.5}]}]}"#,
+            ),
+        ]);
+        let args = Args::parse_from(["hft-orderbook", "--depth", "10", "--output", "json"]);
+        let mut books = HashMap::new();
+        let mut stats = ConnectionStats::new();
+
+        assert!(
+            handle_connection(
+                server.connect().await,
+                &args,
+                &mut books,
+                None,
+                None,
+                None,
+                None,
+                &mut Vec::new(),
+                &mut stats
+            )
+            .await
+        );
+
+        let (bid, ask) = books["BTC/USD"].top_of_book().unwrap();
+        assert_eq!(
+            (bid.price, bid.qty),
+            ("99.5".parse().unwrap(), "2".parse().unwrap())
+        );
+        assert_eq!(
+            (ask.price, ask.qty),
+            ("100.5".parse().unwrap(), "0.5".parse().unwrap())
+        );
+        assert_eq!(stats.messages(), 4);
+        assert_eq!(stats.last_checksum(), None);
+        assert_eq!(
+            server.received(),
+            vec![
+                r#"{"method":"subscribe","params":{"channel":"book","symbol":["BTC/USD"],"depth":10}}"#
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tls_mock_exchanges_are_reached_through_their_ca_or_without_verification() {
+        let _connection = CONNECTIONS.lock().await;
+        let ca_cert = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/localhost-cert.pem"
+        ));
+        for (ca_cert, insecure) in [(None, true), (Some(ca_cert), false)] {
+            let identity = native_tls::Identity::from_pkcs8(
+                include_bytes!("../testdata/localhost-cert.pem"),
+                include_bytes!("../testdata/localhost-key.pem"),
+            )
+            .unwrap();
+            let server = MockServer::start_tls(
+                vec![String::from(
+                    r#"{"channel":"book","type":"snapshot","data":[{"symbol":"BTC/USD","bids":[{"price":100.0,"qty":1.0}],"asks":[{"price":101.0,"qty":1.5}]}]}"#,
+                )],
+                identity,
+            );
+            let connector = tls::connector(ca_cert, insecure).unwrap();
+            assert!(connector.is_some());
+            let (client, _) = tokio_tungstenite::connect_async_tls_with_config(
+                server.url(),
+                None,
+                false,
+                connector,
+            )
+            .await
+            .unwrap();
+            let args = Args::parse_from(["hft-orderbook", "--quiet"]);
+            let mut books = HashMap::new();
+
+            handle_connection(
+                client,
+                &args,
+                &mut books,
+                None,
+                None,
+                None,
+                None,
+                &mut Vec::new(),
+                &mut ConnectionStats::new(),
+            )
+            .await;
+
+            assert_eq!(books["BTC/USD"].spread(), Some(Decimal::ONE));
+            assert_eq!(server.received().len(), 1);
+        }
+        assert!(tls::connector(None, false).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_subscription_ends_the_connection_for_good() {
+        let _connection = CONNECTIONS.lock().await;
+        let server = MockServer::start(vec![String::from(
+            r#"{"error":"Currency pair not supported XYZ/USD","method":"subscribe","success":false}"#,
+        )]);
+        let args = Args::parse_from(["hft-orderbook", "--symbol", "XYZ/USD", "--output", "json"]);
+        let mut books = HashMap::new();
+
+        assert!(
+            !handle_connection(
+                server.connect().await,
+                &args,
+                &mut books,
+                None,
+                None,
+                None,
+                None,
+                &mut Vec::new(),
+                &mut ConnectionStats::new()
+            )
+            .await
+        );
+        assert!(books["XYZ/USD"].top_of_book().is_none());
+        server.received();
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_the_connection_from_the_client() {
+        let _connection = CONNECTIONS.lock().await;
+        let server = MockServer::start_until_closed(vec![String::from(BTC_SNAPSHOT)]);
+        let args = Args::parse_from(["hft-orderbook", "--quiet"]);
+        let mut books = HashMap::new();
+
+        SHUTDOWN.store(true, Ordering::SeqCst);
+        handle_connection(
+            server.connect().await,
+            &args,
+            &mut books,
+            None,
+            None,
+            None,
+            None,
+            &mut Vec::new(),
+            &mut ConnectionStats::new(),
+        )
+        .await;
+        SHUTDOWN.store(false, Ordering::SeqCst);
+
+        assert_eq!(books["BTC/USD"].spread(), Some(Decimal::ONE));
+        assert!(server.closed_by_client());
+    }
+
+    #[tokio::test]
+    async fn closing_waits_for_the_server_to_answer() {
+        let server = MockServer::start_until_closed(Vec::new());
+        let args = Args::parse_from(["hft-orderbook"]);
+        let mut connection = Connection::new(server.connect().await, new_feed(&args, None));
+        connection.subscribe().await.unwrap();
+
+        connection.close(CLOSE_TIMEOUT).await.unwrap();
+
+        // the handshake is complete, so the stream ends
+        assert!(connection.recv_message().await.is_none());
+        assert!(server.closed_by_client());
     }
 }
@@ -0,0 +1,236 @@
+use crate::feed::TradeSide;
+use crate::orderbook::{Orderbook, Side};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// Hypothetical limit order, resting in a [`MatchingSim`] until filled or cancelled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimOrder {
+    pub id: u64,
+    pub side: TradeSide,
+    pub price: Decimal,
+    /// quantity still waiting to be filled
+    pub remaining: Decimal,
+}
+
+/// Part of a simulated order filled against one level of the book.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub order_id: u64,
+    pub side: TradeSide,
+    pub price: Decimal,
+    pub qty: Decimal,
+}
+
+/// In-memory matching of hypothetical limit orders against a live book, for trying out
+/// strategies without sending anything to the exchange. Orders take the liquidity of the
+/// book at their limit or better, best price first, and rest with whatever is left until
+/// the book moves through their price. The book itself is never changed, so liquidity
+/// taken by one call is available again to the next.
+#[derive(Debug, Default)]
+pub struct MatchingSim {
+    /// resting orders, oldest first
+    orders: Vec<SimOrder>,
+    next_id: u64,
+    /// base currency bought minus sold
+    position: Decimal,
+    /// quote currency received for sells minus paid for buys
+    cash: Decimal,
+}
+
+impl MatchingSim {
+    pub fn new() -> Self {
+        MatchingSim::default()
+    }
+
+    /// Matches a limit order against `book` and rests its unfilled quantity. Returns the
+    /// order's id along with the fills it got right away.
+    pub fn submit(
+        &mut self,
+        book: &Orderbook,
+        side: TradeSide,
+        price: Decimal,
+        qty: Decimal,
+    ) -> (u64, Vec<Fill>) {
+        self.next_id += 1;
+        let mut order = SimOrder {
+            id: self.next_id,
+            side,
+            price,
+            remaining: qty,
+        };
+        let fills = take_liquidity(&mut order, book, &mut HashMap::new());
+        self.settle(&fills);
+        if order.remaining > Decimal::ZERO {
+            self.orders.push(order);
+        }
+        (self.next_id, fills)
+    }
+
+    /// Fills the resting orders the book moved through, oldest first. Orders sharing a
+    /// level split its quantity in that order rather than each taking all of it.
+    pub fn match_resting(&mut self, book: &Orderbook) -> Vec<Fill> {
+        let mut taken = HashMap::new();
+        let mut fills = Vec::new();
+        for order in &mut self.orders {
+            fills.extend(take_liquidity(order, book, &mut taken));
+        }
+        self.orders.retain(|order| order.remaining > Decimal::ZERO);
+        self.settle(&fills);
+        fills
+    }
+
+    /// Removes a resting order, returning it if it was still resting.
+    pub fn cancel(&mut self, id: u64) -> Option<SimOrder> {
+        let index = self.orders.iter().position(|order| order.id == id)?;
+        Some(self.orders.remove(index))
+    }
+
+    /// Quantity of an order still resting, `None` once it's filled or cancelled.
+    pub fn remaining(&self, id: u64) -> Option<Decimal> {
+        self.orders
+            .iter()
+            .find(|order| order.id == id)
+            .map(|order| order.remaining)
+    }
+
+    /// Resting orders, oldest first.
+    pub fn resting(&self) -> &[SimOrder] {
+        &self.orders
+    }
+
+    pub fn position(&self) -> Decimal {
+        self.position
+    }
+
+    pub fn cash(&self) -> Decimal {
+        self.cash
+    }
+
+    fn settle(&mut self, fills: &[Fill]) {
+        for fill in fills {
+            let (qty, notional) = match fill.side {
+                TradeSide::Buy => (fill.qty, -fill.qty * fill.price),
+                TradeSide::Sell => (-fill.qty, fill.qty * fill.price),
+            };
+            self.position += qty;
+            self.cash += notional;
+        }
+    }
+}
+
+/// Fills `order` from the opposite side of `book` up to its limit, best price first,
+/// leaving out the quantity per price already `taken` by earlier orders and adding its own.
+fn take_liquidity(
+    order: &mut SimOrder,
+    book: &Orderbook,
+    taken: &mut HashMap<Decimal, Decimal>,
+) -> Vec<Fill> {
+    let side = match order.side {
+        TradeSide::Buy => Side::Ask,
+        TradeSide::Sell => Side::Bid,
+    };
+    let mut fills = Vec::new();
+    for level in book
+        .iter_side(side)
+        .take_while(|level| side.is_within(level.price, order.price))
+    {
+        if order.remaining <= Decimal::ZERO {
+            break;
+        }
+        let taken = taken.entry(level.price).or_default();
+        let qty = (level.qty - *taken).min(order.remaining);
+        if qty <= Decimal::ZERO {
+            continue;
+        }
+        *taken += qty;
+        order.remaining -= qty;
+        fills.push(Fill {
+            order_id: order.id,
+            side: order.side,
+            price: level.price,
+            qty,
+        });
+    }
+    fills
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::{BookUpdate, UpdateKind};
+    use crate::orderbook::PriceLevel;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    fn book(bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Orderbook {
+        let levels = |levels: &[(&str, &str)]| {
+            levels
+                .iter()
+                .map(|(price, qty)| PriceLevel {
+                    price: dec(price),
+                    qty: dec(qty),
+                })
+                .collect()
+        };
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&BookUpdate {
+            symbol: String::from("BTC/USD"),
+            kind: UpdateKind::Snapshot,
+            bids: levels(bids),
+            asks: levels(asks),
+            checksum: None,
+            timestamp: None,
+        });
+        orderbook
+    }
+
+    #[test]
+    fn marketable_orders_walk_the_book_up_to_their_limit() {
+        let orderbook = book(&[("99", "1")], &[("100", "1"), ("101", "2"), ("102", "5")]);
+        let mut sim = MatchingSim::new();
+
+        let (id, fills) = sim.submit(&orderbook, TradeSide::Buy, dec("101"), dec("4"));
+
+        let filled: Vec<(Decimal, Decimal)> =
+            fills.iter().map(|fill| (fill.price, fill.qty)).collect();
+        assert_eq!(filled, vec![(dec("100"), dec("1")), (dec("101"), dec("2"))]);
+        assert_eq!(sim.remaining(id), Some(dec("1")));
+        assert_eq!(sim.position(), dec("3"));
+        assert_eq!(sim.cash(), dec("-302"));
+
+        let (id, fills) = sim.submit(&orderbook, TradeSide::Sell, dec("98"), dec("0.5"));
+        assert_eq!(fills[0].price, dec("99"));
+        assert_eq!(sim.remaining(id), None);
+        assert_eq!(sim.position(), dec("2.5"));
+    }
+
+    #[test]
+    fn resting_orders_fill_oldest_first_once_the_book_moves() {
+        let mut sim = MatchingSim::new();
+        let quiet = book(&[("99", "1")], &[("100", "1")]);
+        let (first, fills) = sim.submit(&quiet, TradeSide::Sell, dec("99.5"), dec("2"));
+        assert!(fills.is_empty());
+        let (second, _) = sim.submit(&quiet, TradeSide::Sell, dec("99.5"), dec("2"));
+        assert!(sim.match_resting(&quiet).is_empty());
+        assert_eq!(sim.resting().len(), 2);
+
+        let moved = book(&[("99.6", "3"), ("99", "5")], &[("100", "1")]);
+        let fills = sim.match_resting(&moved);
+
+        let filled: Vec<(u64, Decimal)> =
+            fills.iter().map(|fill| (fill.order_id, fill.qty)).collect();
+        assert_eq!(filled, vec![(first, dec("2")), (second, dec("1"))]);
+        assert_eq!(sim.remaining(first), None);
+        assert_eq!(sim.remaining(second), Some(dec("1")));
+        assert_eq!(sim.position(), dec("-3"));
+
+        assert_eq!(
+            sim.cancel(second).map(|order| order.remaining),
+            Some(dec("1"))
+        );
+        assert!(sim.resting().is_empty());
+    }
+}
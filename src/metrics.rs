@@ -0,0 +1,208 @@
+use crate::orderbook::{AppliedEntry, Orderbook, Side};
+use rust_decimal::prelude::ToPrimitive;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// Book health counters and gauges, rendered in the Prometheus text format.
+/// Shared between the feed loop, which updates it, and the HTTP server reading it.
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    updates: u64,
+    checksum_failures: u64,
+    reconnects: u64,
+    resubscribes: u64,
+    dropped_outputs: u64,
+    books: BTreeMap<String, BookGauges>,
+}
+
+struct BookGauges {
+    spread: Option<f64>,
+    bid_depth: usize,
+    ask_depth: usize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Counts the `applied` entries and refreshes the gauges of their books.
+    pub fn observe(&self, books: &HashMap<String, Orderbook>, applied: &[AppliedEntry]) {
+        let mut state = self.state.lock().unwrap();
+        for applied in applied {
+            state.updates += 1;
+            if applied.checksum_failed {
+                state.checksum_failures += 1;
+            }
+            let Some(orderbook) = books.get(&applied.symbol) else {
+                continue;
+            };
+            let gauges = BookGauges {
+                spread: orderbook.spread().and_then(|spread| spread.to_f64()),
+                bid_depth: orderbook.depth(Side::Bid),
+                ask_depth: orderbook.depth(Side::Ask),
+            };
+            state.books.insert(orderbook.symbol().to_string(), gauges);
+        }
+    }
+
+    pub fn record_reconnect(&self) {
+        self.state.lock().unwrap().reconnects += 1;
+    }
+
+    pub fn record_resubscribe(&self) {
+        self.state.lock().unwrap().resubscribes += 1;
+    }
+
+    pub fn record_dropped_output(&self) {
+        self.state.lock().unwrap().dropped_outputs += 1;
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "orderbook_updates_total",
+            "Book entries applied, use rate() for updates per second",
+            state.updates,
+        );
+        counter(
+            &mut out,
+            "orderbook_checksum_failures_total",
+            "Book entries whose checksum didn't match the reconstructed book",
+            state.checksum_failures,
+        );
+        counter(
+            &mut out,
+            "orderbook_reconnects_total",
+            "Reconnections to the exchange after the first connection",
+            state.reconnects,
+        );
+        counter(
+            &mut out,
+            "orderbook_resubscribes_total",
+            "Resubscriptions to replace inconsistent books with fresh snapshots",
+            state.resubscribes,
+        );
+        counter(
+            &mut out,
+            "orderbook_dropped_outputs_total",
+            "Updates the output couldn't keep up with and merged into a later one",
+            state.dropped_outputs,
+        );
+
+        header(
+            &mut out,
+            "orderbook_spread",
+            "gauge",
+            "Best ask minus best bid",
+        );
+        for (symbol, gauges) in &state.books {
+            if let Some(spread) = gauges.spread {
+                writeln!(out, "orderbook_spread{{symbol=\"{}\"}} {}", symbol, spread).unwrap();
+            }
+        }
+        header(
+            &mut out,
+            "orderbook_depth",
+            "gauge",
+            "Price levels held per side",
+        );
+        for (symbol, gauges) in &state.books {
+            for (side, depth) in [("bid", gauges.bid_depth), ("ask", gauges.ask_depth)] {
+                writeln!(
+                    out,
+                    "orderbook_depth{{symbol=\"{}\",side=\"{}\"}} {}",
+                    symbol, side, depth
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+/// Serves `metrics` on `addr` from a background thread until the process exits.
+#[cfg(feature = "metrics")]
+pub fn serve_metrics(
+    addr: &str,
+    metrics: std::sync::Arc<Metrics>,
+) -> Result<std::thread::JoinHandle<()>, Box<dyn std::error::Error + Send + Sync>> {
+    let server = tiny_http::Server::http(addr)?;
+    let content_type =
+        tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+    Ok(std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = tiny_http::Response::from_string(metrics.render())
+                .with_header(content_type.clone());
+            if let Err(error) = request.respond(response) {
+                log::warn!("Couldn't answer metrics request. {}", error);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::{BookUpdate, UpdateKind};
+    use crate::orderbook::PriceLevel;
+
+    #[test]
+    fn render_reports_counters_and_book_gauges() {
+        let level = |price: &str, qty: &str| PriceLevel {
+            price: price.parse().unwrap(),
+            qty: qty.parse().unwrap(),
+        };
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&BookUpdate {
+            symbol: String::from("BTC/USD"),
+            kind: UpdateKind::Snapshot,
+            bids: vec![level("100.0", "1")],
+            asks: vec![level("100.5", "1"), level("101.0", "2")],
+            checksum: Some(0),
+            timestamp: None,
+        });
+        let books = HashMap::from([(String::from("BTC/USD"), orderbook)]);
+
+        let metrics = Metrics::new();
+        metrics.observe(
+            &books,
+            &[AppliedEntry {
+                symbol: String::from("BTC/USD"),
+                kind: UpdateKind::Snapshot,
+                checksum_failed: books["BTC/USD"].checksum_failed(),
+            }],
+        );
+        metrics.record_reconnect();
+        metrics.record_resubscribe();
+        metrics.record_dropped_output();
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("orderbook_updates_total 1\n"));
+        assert!(rendered.contains("orderbook_checksum_failures_total 1\n"));
+        assert!(rendered.contains("orderbook_reconnects_total 1\n"));
+        assert!(rendered.contains("orderbook_resubscribes_total 1\n"));
+        assert!(rendered.contains("orderbook_dropped_outputs_total 1\n"));
+        assert!(rendered.contains("orderbook_spread{symbol=\"BTC/USD\"} 0.5\n"));
+        assert!(rendered.contains("orderbook_depth{symbol=\"BTC/USD\",side=\"ask\"} 2\n"));
+    }
+}
@@ -0,0 +1,118 @@
+//! Local websocket server standing in for an exchange in tests of the connection loop.
+
+use native_tls::{Identity, TlsAcceptor};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{self, Message, WebSocket};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Serves a single connection: waits for the first text message, answers it with canned
+/// messages and closes the connection, or waits for the client to close it.
+pub struct MockServer {
+    url: String,
+    handle: JoinHandle<Served>,
+}
+
+/// What the client did on the connection.
+struct Served {
+    /// text messages the client sent
+    received: Vec<String>,
+    /// whether the client closed the connection before the server did
+    closed_by_client: bool,
+}
+
+impl MockServer {
+    pub fn start(messages: Vec<String>) -> Self {
+        MockServer::start_plain(messages, true)
+    }
+
+    /// Same as [`MockServer::start`], leaving the connection open after the canned messages
+    /// until the client closes it.
+    pub fn start_until_closed(messages: Vec<String>) -> Self {
+        MockServer::start_plain(messages, false)
+    }
+
+    fn start_plain(messages: Vec<String>, close: bool) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(tungstenite::accept(stream).unwrap(), messages, close)
+        });
+        MockServer { url, handle }
+    }
+
+    /// Same as [`MockServer::start`] over TLS, presenting the certificate of `identity` for
+    /// `localhost`.
+    pub fn start_tls(messages: Vec<String>, identity: Identity) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("wss://localhost:{}", listener.local_addr().unwrap().port());
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let stream = acceptor.accept(stream).unwrap();
+            serve(tungstenite::accept(stream).unwrap(), messages, true)
+        });
+        MockServer { url, handle }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Connects a client to the server.
+    pub async fn connect(&self) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+        tokio_tungstenite::connect_async(&self.url).await.unwrap().0
+    }
+
+    /// Waits for the connection to end and returns the text messages the client sent.
+    pub fn received(self) -> Vec<String> {
+        self.handle.join().unwrap().received
+    }
+
+    /// Waits for the connection to end and returns whether the client sent a close frame
+    /// before the server did.
+    pub fn closed_by_client(self) -> bool {
+        self.handle.join().unwrap().closed_by_client
+    }
+}
+
+/// Waits for the first text message, answers it with `messages` and, with `close`, closes
+/// the connection. Then reads until the connection ends, noting every text message the
+/// client sent and whether it closed the connection first.
+fn serve<S: Read + Write>(mut client: WebSocket<S>, messages: Vec<String>, close: bool) -> Served {
+    let mut served = Served {
+        received: Vec::new(),
+        closed_by_client: false,
+    };
+    while served.received.is_empty() {
+        match client.read().unwrap() {
+            Message::Text(text) => served.received.push(text.to_string()),
+            Message::Close(_) => {
+                // sends the answer to the close frame
+                let _ = client.flush();
+                served.closed_by_client = true;
+                return served;
+            }
+            _ => {}
+        }
+    }
+    for message in messages {
+        client.send(Message::text(message)).unwrap();
+    }
+    if close {
+        client.close(None).unwrap();
+    }
+    // whatever the client sends until the close handshake completes, answered on the next
+    // read if the client started it
+    while let Ok(message) = client.read() {
+        match message {
+            Message::Text(text) => served.received.push(text.to_string()),
+            Message::Close(_) if !close => served.closed_by_client = true,
+            _ => {}
+        }
+    }
+    served
+}
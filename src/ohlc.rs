@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Open, high, low and close of the price samples within one interval.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OhlcBar {
+    /// milliseconds since the unix epoch at which the bar's interval starts
+    pub start: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+}
+
+/// Aggregates price samples into bars of a fixed interval, aligned to the unix epoch so
+/// that 1s bars start on whole seconds.
+#[derive(Clone)]
+pub struct OhlcAggregator {
+    interval_millis: u64,
+    current: Option<OhlcBar>,
+}
+
+impl OhlcAggregator {
+    /// Builds bars spanning `interval`, which is rounded to whole milliseconds and at least
+    /// one of them.
+    pub fn new(interval: Duration) -> Self {
+        OhlcAggregator {
+            interval_millis: (interval.as_millis() as u64).max(1),
+            current: None,
+        }
+    }
+
+    /// Adds a sample taken at `timestamp` milliseconds since the unix epoch. Returns the
+    /// bar in progress once a sample falls into a later interval; samples older than the
+    /// bar in progress are counted towards it.
+    pub fn record(&mut self, timestamp: u64, price: f64) -> Option<OhlcBar> {
+        let start = timestamp - timestamp % self.interval_millis;
+        match &mut self.current {
+            Some(bar) if start <= bar.start => {
+                bar.high = bar.high.max(price);
+                bar.low = bar.low.min(price);
+                bar.close = price;
+                None
+            }
+            current => current.replace(OhlcBar {
+                start,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+            }),
+        }
+    }
+
+    /// The bar still collecting samples, `None` before the first one.
+    pub fn current(&self) -> Option<OhlcBar> {
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bars_complete_when_the_interval_rolls_over() {
+        let mut aggregator = OhlcAggregator::new(Duration::from_secs(1));
+        let completed: Vec<OhlcBar> = [
+            (1_000_200, 100.0),
+            (1_000_400, 102.5),
+            (1_000_600, 99.0),
+            (1_000_999, 101.0),
+            (1_001_000, 101.5),
+            (1_001_500, 100.5),
+            // nothing arrives during the next two seconds
+            (1_004_100, 98.0),
+        ]
+        .into_iter()
+        .filter_map(|(timestamp, price)| aggregator.record(timestamp, price))
+        .collect();
+
+        assert_eq!(
+            completed,
+            vec![
+                OhlcBar {
+                    start: 1_000_000,
+                    open: 100.0,
+                    high: 102.5,
+                    low: 99.0,
+                    close: 101.0,
+                },
+                OhlcBar {
+                    start: 1_001_000,
+                    open: 101.5,
+                    high: 101.5,
+                    low: 100.5,
+                    close: 100.5,
+                },
+            ]
+        );
+        assert_eq!(
+            aggregator.current(),
+            Some(OhlcBar {
+                start: 1_004_000,
+                open: 98.0,
+                high: 98.0,
+                low: 98.0,
+                close: 98.0,
+            })
+        );
+    }
+
+    #[test]
+    fn late_samples_count_towards_the_bar_in_progress() {
+        let mut aggregator = OhlcAggregator::new(Duration::from_millis(500));
+        assert_eq!(aggregator.current(), None);
+
+        assert_eq!(aggregator.record(1_250, 10.0), None);
+        assert_eq!(aggregator.record(900, 12.0), None);
+
+        let bar = aggregator.current().unwrap();
+        assert_eq!((bar.start, bar.high, bar.close), (1_000, 12.0, 12.0));
+    }
+}
@@ -0,0 +1,2814 @@
+use crate::clock::{Clock, SystemClock};
+use crate::feed::{BookUpdate, UpdateKind};
+use crate::latency::{LatencyStats, LatencyWindow};
+use crate::ohlc::{OhlcAggregator, OhlcBar};
+use crate::spread::SpreadWindow;
+use log::{debug, error, warn};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A single price level on either side of the book, serialized as a `[price, qty]` pair of
+/// decimal strings so that no digit is lost. Numbers are read as well.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PriceLevel {
+    pub price: Decimal,
+    pub qty: Decimal,
+}
+
+impl Serialize for PriceLevel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.price, self.qty).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PriceLevel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (price, qty) = <(Decimal, Decimal)>::deserialize(deserializer)?;
+        Ok(PriceLevel { price, qty })
+    }
+}
+
+/// One side of the book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+impl Side {
+    /// Whether `price` on this side is at `limit` or nearer the top of book: at or above it
+    /// for bids, at or below it for asks.
+    pub fn is_within(self, price: Decimal, limit: Decimal) -> bool {
+        match self {
+            Side::Bid => price >= limit,
+            Side::Ask => price <= limit,
+        }
+    }
+}
+
+/// Best bid and ask price at the time the top of book changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tick {
+    pub timestamp: SystemTime,
+    pub bid: Decimal,
+    pub ask: Decimal,
+}
+
+/// Point-in-time copy of a book, as emitted by the JSON output.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BookSnapshot {
+    pub symbol: String,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    /// milliseconds since the unix epoch when the snapshot was taken
+    pub ts: u64,
+}
+
+/// Price levels one update added, changed the quantity of, or removed on one side, each
+/// in book order. Removed levels carry the quantity they had before.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SideDiff {
+    pub added: Vec<PriceLevel>,
+    pub modified: Vec<PriceLevel>,
+    pub removed: Vec<PriceLevel>,
+}
+
+/// What [`Orderbook::apply`] changed on each side.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BookDiff {
+    pub bids: SideDiff,
+    pub asks: SideDiff,
+}
+
+impl BookDiff {
+    /// Whether the update left the book as it was.
+    pub fn is_empty(&self) -> bool {
+        *self == BookDiff::default()
+    }
+}
+
+/// Broken invariant found by [`Orderbook::validate`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum BookError {
+    /// the best bid is at or above the best ask
+    Crossed { bid: Decimal, ask: Decimal },
+    /// a level without a positive price
+    NonPositivePrice { side: Side, price: Decimal },
+    /// a level without a positive quantity, which should have been deleted
+    NonPositiveQty {
+        side: Side,
+        price: Decimal,
+        qty: Decimal,
+    },
+    /// a level that isn't further from the top of book than the one before it
+    OutOfOrder {
+        side: Side,
+        previous: Decimal,
+        price: Decimal,
+    },
+    /// more levels on a side than the book's maximum depth
+    TooDeep {
+        side: Side,
+        levels: usize,
+        max_depth: usize,
+    },
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BookError::Crossed { bid, ask } => {
+                write!(f, "best bid {} is at or above best ask {}", bid, ask)
+            }
+            BookError::NonPositivePrice { side, price } => {
+                write!(f, "{:?} level has the price {}", side, price)
+            }
+            BookError::NonPositiveQty { side, price, qty } => {
+                write!(f, "{:?} level at {} has the quantity {}", side, price, qty)
+            }
+            BookError::OutOfOrder {
+                side,
+                previous,
+                price,
+            } => write!(
+                f,
+                "{:?} level at {} follows the one at {}",
+                side, price, previous
+            ),
+            BookError::TooDeep {
+                side,
+                levels,
+                max_depth,
+            } => write!(
+                f,
+                "{} {:?} levels exceed the maximum depth of {}",
+                levels, side, max_depth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BookError {}
+
+/// Both sides of the book, each keyed so that iteration starts at the top of book:
+/// bids in descending and asks in ascending price order. A side holds one quantity per
+/// price, so an entry at a price already in the book replaces its quantity, and of
+/// several entries at one price in the same update the last one wins.
+#[derive(Clone)]
+pub struct Orderbook {
+    symbol: String,
+    bids: BTreeMap<Reverse<Decimal>, Decimal>,
+    asks: BTreeMap<Decimal, Decimal>,
+    price_precision: usize,
+    qty_precision: usize,
+    /// decimal places prices are shown with, the instrument's precision unless set
+    price_decimals: Option<usize>,
+    /// decimal places quantities are shown with, the instrument's precision unless set
+    qty_decimals: Option<usize>,
+    /// depth the book was subscribed at, the number of levels kept per side
+    subscribed_depth: Option<usize>,
+    tick_size: Option<Decimal>,
+    qty_epsilon: Decimal,
+    last_update: Option<SystemTime>,
+    checksum_failed: bool,
+    /// checksum mismatches since the last matching checksum
+    consecutive_checksum_failures: u32,
+    /// result of the last checksum comparison
+    last_checksum_matched: Option<bool>,
+    /// whether a snapshot was applied, updates are ignored before that
+    has_snapshot: bool,
+    /// fewest levels per side a healthy book has, `None` disables the check
+    min_levels: Option<usize>,
+    /// whether a side held fewer than `min_levels` after the last applied entry
+    thin: bool,
+    /// widest healthy spread, `None` disables the check
+    max_spread: Option<Decimal>,
+    /// whether the spread was above `max_spread` after the last applied entry
+    wide: bool,
+    latency: LatencyWindow,
+    /// recent spreads, `None` unless enabled
+    spread_window: Option<SpreadWindow>,
+    /// smoothing factor of the mid price EMA, `None` disables it
+    ema_alpha: Option<f64>,
+    /// current and previous EMA of the mid price
+    ema: Option<(f64, Option<f64>)>,
+    /// mid price bars, `None` unless enabled
+    ohlc: Option<OhlcAggregator>,
+    /// bar the last applied entry completed
+    completed_bar: Option<OhlcBar>,
+    /// most recent top of book changes, oldest first
+    ticks: VecDeque<Tick>,
+    tick_capacity: usize,
+    /// lowest and highest mid price since the session range was last reset
+    session_range: Option<(Decimal, Decimal)>,
+    on_change: ChangeCallbacks,
+    /// time the entries are received at and the book's age is measured against
+    clock: Arc<dyn Clock>,
+}
+
+/// Callback handed the levels an update changed, see [`Orderbook::on_change`].
+pub type ChangeCallback = Box<dyn FnMut(&BookDiff) + Send>;
+
+/// Callbacks registered with [`Orderbook::on_change`]. Copies of a book start without any,
+/// so a callback only ever hears about the book it was registered with.
+#[derive(Default)]
+struct ChangeCallbacks(Vec<ChangeCallback>);
+
+impl Clone for ChangeCallbacks {
+    fn clone(&self) -> Self {
+        ChangeCallbacks::default()
+    }
+}
+
+/// no quantity threshold by default: like Kraken, only a zero quantity deletes its level
+pub const DEFAULT_QTY_EPSILON: Decimal = Decimal::ZERO;
+
+/// number of recent updates the latency statistics are computed over
+const LATENCY_WINDOW: usize = 1000;
+
+/// number of top of book changes kept for [`Orderbook::recent_ticks`] by default
+pub const DEFAULT_TICK_CAPACITY: usize = 1000;
+
+/// number of levels per side covered by Kraken's book checksum
+const CHECKSUM_DEPTH: usize = 10;
+
+impl Orderbook {
+    pub fn new(symbol: &str) -> Self {
+        Orderbook {
+            symbol: String::from(symbol),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            price_precision: 1,
+            qty_precision: 8,
+            price_decimals: None,
+            qty_decimals: None,
+            subscribed_depth: None,
+            tick_size: None,
+            qty_epsilon: DEFAULT_QTY_EPSILON,
+            last_update: None,
+            checksum_failed: false,
+            consecutive_checksum_failures: 0,
+            last_checksum_matched: None,
+            has_snapshot: false,
+            min_levels: None,
+            thin: false,
+            max_spread: None,
+            wide: false,
+            latency: LatencyWindow::new(LATENCY_WINDOW),
+            spread_window: None,
+            ema_alpha: None,
+            ema: None,
+            ohlc: None,
+            completed_bar: None,
+            ticks: VecDeque::new(),
+            tick_capacity: DEFAULT_TICK_CAPACITY,
+            session_range: None,
+            on_change: ChangeCallbacks::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the decimal precision Kraken uses for this instrument's prices and quantities.
+    pub fn with_precision(mut self, price_precision: usize, qty_precision: usize) -> Self {
+        self.price_precision = price_precision;
+        self.qty_precision = qty_precision;
+        self
+    }
+
+    /// Shows prices with `decimals` decimal places instead of the instrument's precision.
+    pub fn with_price_decimals(mut self, decimals: usize) -> Self {
+        self.price_decimals = Some(decimals);
+        self
+    }
+
+    /// Shows quantities with `decimals` decimal places instead of the instrument's precision.
+    pub fn with_qty_decimals(mut self, decimals: usize) -> Self {
+        self.qty_decimals = Some(decimals);
+        self
+    }
+
+    /// Keeps at most `max_depth` levels per side, dropping the ones furthest from the top.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.subscribed_depth = Some(max_depth);
+        self
+    }
+
+    /// Snaps incoming prices to multiples of `tick_size` so that prices within half a tick
+    /// of each other address the same level. Without a tick size, or with a non-positive
+    /// one, prices match exactly.
+    pub fn with_tick_size(mut self, tick_size: Decimal) -> Self {
+        self.tick_size = Some(tick_size)
+            .filter(|tick_size| tick_size.is_sign_positive() && !tick_size.is_zero());
+        self
+    }
+
+    /// Quantities below `qty_epsilon` delete their level, on top of a zero quantity which
+    /// always does.
+    pub fn with_qty_epsilon(mut self, qty_epsilon: Decimal) -> Self {
+        self.qty_epsilon = qty_epsilon;
+        self
+    }
+
+    /// Warns once a side drops below `min_levels` levels, see [`Orderbook::is_thin`].
+    pub fn with_min_levels(mut self, min_levels: usize) -> Self {
+        self.min_levels = Some(min_levels);
+        self
+    }
+
+    /// Warns once the spread goes above `max_spread`, see [`Orderbook::is_wide`].
+    pub fn with_max_spread(mut self, max_spread: Decimal) -> Self {
+        self.max_spread = Some(max_spread);
+        self
+    }
+
+    /// Keeps the spreads of the last `size` applied entries, see [`Orderbook::spread_window`].
+    pub fn with_spread_window(mut self, size: usize) -> Self {
+        self.spread_window = Some(SpreadWindow::new(size));
+        self
+    }
+
+    /// Takes the time from `clock` instead of the system's, for the latency and age.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Tracks an exponential moving average of the mid price, updated with every applied
+    /// entry; `alpha` in (0, 1] weighs each new sample.
+    pub fn with_ema_alpha(mut self, alpha: f64) -> Self {
+        self.ema_alpha = Some(alpha);
+        self
+    }
+
+    /// Aggregates the mid price into bars spanning `interval`, see
+    /// [`Orderbook::completed_bar`].
+    pub fn with_ohlc_interval(mut self, interval: Duration) -> Self {
+        self.ohlc = Some(OhlcAggregator::new(interval));
+        self
+    }
+
+    /// Keeps at most `capacity` top of book changes, dropping the oldest.
+    pub fn with_tick_capacity(mut self, capacity: usize) -> Self {
+        self.tick_capacity = capacity;
+        self
+    }
+
+    /// Starts the session range from `low` and `high` instead of empty, e.g. to carry it over
+    /// from the book this one replaces.
+    pub fn with_session_range(mut self, low: Decimal, high: Decimal) -> Self {
+        self.session_range = Some((low, high));
+        self
+    }
+
+    /// Registers `callback` to be handed the levels every applied update changed, whether
+    /// through [`Orderbook::apply`] or [`Orderbook::evaluate`]. Updates changing nothing
+    /// aren't reported.
+    pub fn on_change(&mut self, callback: ChangeCallback) {
+        self.on_change.0.push(callback);
+    }
+
+    /// Evaluates `update` and reports the levels it changed, including the ones a snapshot
+    /// replaced and the ones pushed beyond the maximum depth.
+    pub fn apply(&mut self, update: BookUpdate) -> BookDiff {
+        let diff = self.evaluate_diffed(&update);
+        self.notify(&diff);
+        diff
+    }
+
+    fn notify(&mut self, diff: &BookDiff) {
+        if diff.is_empty() {
+            return;
+        }
+        for callback in &mut self.on_change.0 {
+            callback(diff);
+        }
+    }
+
+    /// [`Orderbook::evaluate`] reporting the levels it changed.
+    fn evaluate_diffed(&mut self, update: &BookUpdate) -> BookDiff {
+        let snapshot = update.kind == UpdateKind::Snapshot;
+        let bids = prior_levels(
+            &self.bids,
+            update
+                .bids
+                .iter()
+                .map(|level| Reverse(self.level_price(level.price))),
+            snapshot,
+        );
+        let asks = prior_levels(
+            &self.asks,
+            update
+                .asks
+                .iter()
+                .map(|level| self.level_price(level.price)),
+            snapshot,
+        );
+        self.evaluate_entries(update);
+        BookDiff {
+            bids: side_diff(bids, &self.bids, |Reverse(price)| price),
+            asks: side_diff(asks, &self.asks, |price| price),
+        }
+    }
+
+    /// Applies `update` to the book. Updates arriving before the first snapshot, e.g. when
+    /// racing a reconnect, would only build a partial book and are ignored. Snapshots
+    /// repeating the book as it is leave its levels alone, but still count as an update and
+    /// have their checksum checked.
+    pub fn evaluate(&mut self, update: &BookUpdate) {
+        // working out the changed levels is only worth it for someone to tell
+        if self.on_change.0.is_empty() {
+            self.evaluate_entries(update);
+        } else {
+            let diff = self.evaluate_diffed(update);
+            self.notify(&diff);
+        }
+    }
+
+    fn evaluate_entries(&mut self, update: &BookUpdate) {
+        match update.kind {
+            UpdateKind::Snapshot if self.is_duplicate_snapshot(update) => debug!(
+                "Keeping the levels of {} for a snapshot identical to the book",
+                self.symbol
+            ),
+            UpdateKind::Snapshot => self.handle_snapshot(update),
+            UpdateKind::Update if !self.has_snapshot => {
+                debug!("Ignoring update for {} before its snapshot", self.symbol);
+                return;
+            }
+            UpdateKind::Update => self.handle_update(update),
+        }
+        let received_at = self.clock.now();
+        // exchange clocks running ahead of the local one yield no sample
+        if let Some(latency) = update
+            .timestamp
+            .and_then(|timestamp| received_at.duration_since(timestamp).ok())
+        {
+            self.latency.record(latency);
+        }
+        self.last_update = Some(update.timestamp.unwrap_or(received_at));
+
+        let spread = self.spread();
+        if let (Some(window), Some(spread)) = (self.spread_window.as_mut(), spread) {
+            window.record(spread);
+        }
+        if let Some(mid) = self.mid_price() {
+            self.session_range = Some(match self.session_range {
+                Some((low, high)) => (low.min(mid), high.max(mid)),
+                None => (mid, mid),
+            });
+        }
+        let mid = self.mid_price().and_then(|mid| mid.to_f64());
+        if let (Some(alpha), Some(mid)) = (self.ema_alpha, mid) {
+            let previous = self.mid_ema();
+            self.ema = Some((ema_update(previous, mid, alpha), previous));
+        }
+        self.completed_bar = None;
+        if let (Some(ohlc), Some(mid)) = (self.ohlc.as_mut(), mid) {
+            let timestamp = self.last_update.map_or(0, |last_update| {
+                last_update
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_millis() as u64)
+            });
+            self.completed_bar = ohlc.record(timestamp, mid);
+        }
+
+        self.push_tick();
+
+        self.checksum_failed = false;
+        if let Some(expected) = update.checksum {
+            let actual = self.checksum();
+            self.last_checksum_matched = Some(actual == expected);
+            if actual == expected {
+                self.consecutive_checksum_failures = 0;
+            } else {
+                self.checksum_failed = true;
+                self.consecutive_checksum_failures += 1;
+                error!(
+                    symbol = update.symbol.as_str(),
+                    expected = expected,
+                    computed = actual;
+                    "Checksum mismatch for {}: expected {}, computed {}",
+                    update.symbol, expected, actual
+                );
+            }
+        }
+
+        if let Some((bid, ask)) = self.top_of_book().filter(|_| self.is_crossed()) {
+            warn!(
+                symbol = self.symbol.as_str();
+                "Crossed book for {}: best bid {} >= best ask {}",
+                self.symbol, bid.price, ask.price
+            );
+        }
+
+        let thin = self.min_levels.is_some_and(|min_levels| {
+            self.bid_levels() < min_levels || self.ask_levels() < min_levels
+        });
+        if thin && !self.thin {
+            warn!(
+                "Thin book for {}: {} bid and {} ask levels",
+                self.symbol,
+                self.bid_levels(),
+                self.ask_levels()
+            );
+        }
+        self.thin = thin;
+
+        let wide = self
+            .max_spread
+            .zip(self.spread())
+            .filter(|(max_spread, spread)| spread > max_spread);
+        if let Some((max_spread, spread)) = wide.filter(|_| !self.wide) {
+            warn!(
+                symbol = self.symbol.as_str();
+                "Wide spread for {}: {} above {}",
+                self.symbol, spread, max_spread
+            );
+        }
+        self.wide = wide.is_some();
+    }
+
+    /// Records the top of book if either of its prices moved since the last tick.
+    fn push_tick(&mut self) {
+        let (Some((bid, ask)), Some(timestamp)) = (self.top_of_book(), self.last_update) else {
+            return;
+        };
+        let moved = self
+            .ticks
+            .back()
+            .is_none_or(|last| last.bid != bid.price || last.ask != ask.price);
+        if !moved || self.tick_capacity == 0 {
+            return;
+        }
+        if self.ticks.len() == self.tick_capacity {
+            self.ticks.pop_front();
+        }
+        self.ticks.push_back(Tick {
+            timestamp,
+            bid: bid.price,
+            ask: ask.price,
+        });
+    }
+
+    /// The last `n` top of book changes, oldest first.
+    pub fn recent_ticks(&self, n: usize) -> Vec<Tick> {
+        let skipped = self.ticks.len().saturating_sub(n);
+        self.ticks.iter().skip(skipped).copied().collect()
+    }
+
+    /// Computes Kraken's CRC32 checksum over the top ten asks followed by the top ten bids.
+    pub fn checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        self.iter_asks()
+            .take(CHECKSUM_DEPTH)
+            .chain(self.iter_bids().take(CHECKSUM_DEPTH))
+            .for_each(|level| {
+                hasher.update(checksum_field(level.price, self.price_precision).as_bytes());
+                hasher.update(checksum_field(level.qty, self.qty_precision).as_bytes());
+            });
+        hasher.finalize()
+    }
+
+    /// Bids from the best, highest, one down.
+    pub fn iter_bids(&self) -> impl Iterator<Item = PriceLevel> + '_ {
+        self.bids.iter().map(|(Reverse(price), qty)| PriceLevel {
+            price: *price,
+            qty: *qty,
+        })
+    }
+
+    /// Asks from the best, lowest, one up.
+    pub fn iter_asks(&self) -> impl Iterator<Item = PriceLevel> + '_ {
+        self.asks.iter().map(|(price, qty)| PriceLevel {
+            price: *price,
+            qty: *qty,
+        })
+    }
+
+    /// Levels of `side` from the best one outwards, for walking either side with the same
+    /// code.
+    pub fn iter_side(&self, side: Side) -> Box<dyn Iterator<Item = PriceLevel> + '_> {
+        match side {
+            Side::Bid => Box::new(self.iter_bids()),
+            Side::Ask => Box::new(self.iter_asks()),
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /// Depth the book was subscribed at, `None` if it keeps every level. Only bounds the
+    /// levels kept; the checksum always covers up to ten levels per side.
+    pub fn subscribed_depth(&self) -> Option<usize> {
+        self.subscribed_depth
+    }
+
+    /// Exchange time of the last applied entry, or its local receive time without one.
+    pub fn last_update(&self) -> Option<SystemTime> {
+        self.last_update
+    }
+
+    /// Time elapsed since the last applied entry, `None` before the first one.
+    pub fn age(&self) -> Option<Duration> {
+        self.last_update.map(|last_update| {
+            self.clock
+                .now()
+                .duration_since(last_update)
+                .unwrap_or_default()
+        })
+    }
+
+    /// Delay between the exchange producing an update and this book applying it, over the
+    /// most recent timestamped updates.
+    pub fn latency_stats(&self) -> Option<LatencyStats> {
+        self.latency.stats()
+    }
+
+    /// Spreads of the most recent entries, `None` without [`Orderbook::with_spread_window`].
+    pub fn spread_window(&self) -> Option<&SpreadWindow> {
+        self.spread_window.as_ref()
+    }
+
+    /// Exponential moving average of the mid price, `None` until both sides are populated
+    /// or without [`Orderbook::with_ema_alpha`].
+    pub fn mid_ema(&self) -> Option<f64> {
+        self.ema.map(|(ema, _)| ema)
+    }
+
+    /// Direction of the last EMA change, `Equal` after the first sample.
+    pub fn mid_ema_trend(&self) -> Option<Ordering> {
+        self.ema.map(|(ema, previous)| {
+            previous
+                .and_then(|previous| ema.partial_cmp(&previous))
+                .unwrap_or(Ordering::Equal)
+        })
+    }
+
+    /// Mid price bar the last applied entry completed by starting the next interval, only
+    /// with [`Orderbook::with_ohlc_interval`].
+    pub fn completed_bar(&self) -> Option<OhlcBar> {
+        self.completed_bar
+    }
+
+    /// Highest mid price since startup or the last [`Orderbook::reset_session_range`],
+    /// `None` before the book had a mid price.
+    pub fn session_high(&self) -> Option<Decimal> {
+        self.session_range.map(|(_, high)| high)
+    }
+
+    /// Lowest mid price since startup or the last [`Orderbook::reset_session_range`].
+    pub fn session_low(&self) -> Option<Decimal> {
+        self.session_range.map(|(low, _)| low)
+    }
+
+    /// Forgets the session high and low, the next mid price starts a new range.
+    pub fn reset_session_range(&mut self) {
+        self.session_range = None;
+    }
+
+    /// Whether a snapshot was applied, so the book is complete and updates amend it.
+    pub fn has_snapshot(&self) -> bool {
+        self.has_snapshot
+    }
+
+    /// Whether the checksum sent with the last applied entry didn't match the book.
+    pub fn checksum_failed(&self) -> bool {
+        self.checksum_failed
+    }
+
+    /// Number of entries in a row whose checksum didn't match the book; entries without a
+    /// checksum leave it as it is.
+    pub fn consecutive_checksum_failures(&self) -> u32 {
+        self.consecutive_checksum_failures
+    }
+
+    /// Whether the checksum of the last entry that carried one matched the book, `None`
+    /// until an entry did.
+    pub fn last_checksum_matched(&self) -> Option<bool> {
+        self.last_checksum_matched
+    }
+
+    /// Number of price levels currently held on one side.
+    pub fn depth(&self, side: Side) -> usize {
+        match side {
+            Side::Bid => self.bids.len(),
+            Side::Ask => self.asks.len(),
+        }
+    }
+
+    /// Number of bid levels currently held.
+    pub fn bid_levels(&self) -> usize {
+        self.bids.len()
+    }
+
+    /// Number of ask levels currently held.
+    pub fn ask_levels(&self) -> usize {
+        self.asks.len()
+    }
+
+    /// Whether either side held fewer levels than [`Orderbook::with_min_levels`] requires
+    /// after the last applied entry.
+    pub fn is_thin(&self) -> bool {
+        self.thin
+    }
+
+    /// Whether the spread was above [`Orderbook::with_max_spread`] after the last applied
+    /// entry.
+    pub fn is_wide(&self) -> bool {
+        self.wide
+    }
+
+    /// Whether the book holds exactly the levels of `snapshot`, in the same order.
+    pub fn is_same_as(&self, snapshot: &BookSnapshot) -> bool {
+        self.has_levels(&snapshot.bids, &snapshot.asks)
+    }
+
+    /// Whether `update` is a snapshot of the book as it already is, e.g. a second one
+    /// racing a resubscribe, which would only clear and rebuild the same levels.
+    fn is_duplicate_snapshot(&self, update: &BookUpdate) -> bool {
+        update.kind == UpdateKind::Snapshot
+            && self.has_snapshot
+            && self.has_levels(&update.bids, &update.asks)
+    }
+
+    fn has_levels(&self, bids: &[PriceLevel], asks: &[PriceLevel]) -> bool {
+        self.iter_bids().eq(bids.iter().copied()) && self.iter_asks().eq(asks.iter().copied())
+    }
+
+    /// Copies every level currently held on both sides.
+    pub fn to_snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            symbol: self.symbol.clone(),
+            bids: self.iter_bids().collect(),
+            asks: self.iter_asks().collect(),
+            ts: self
+                .clock
+                .now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64),
+        }
+    }
+
+    /// Writes every level currently held on both sides as pretty-printed JSON.
+    pub fn dump_to_writer(&self, writer: &mut impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, &self.to_snapshot())?;
+        writeln!(writer)
+    }
+
+    /// Book holding the levels of `snapshot`, e.g. one written by
+    /// [`Orderbook::dump_to_writer`].
+    pub fn from_snapshot(snapshot: &BookSnapshot) -> Self {
+        let mut orderbook = Orderbook::new(&snapshot.symbol);
+        orderbook.load_snapshot(snapshot);
+        orderbook
+    }
+
+    /// Replaces the levels with the ones of `snapshot` as if the exchange had sent them,
+    /// dating the book to when the snapshot was taken.
+    pub fn load_snapshot(&mut self, snapshot: &BookSnapshot) {
+        self.evaluate(&BookUpdate {
+            symbol: self.symbol.clone(),
+            kind: UpdateKind::Snapshot,
+            bids: snapshot.bids.clone(),
+            asks: snapshot.asks.clone(),
+            checksum: None,
+            // a timestamp would count the time since the dump as latency
+            timestamp: None,
+        });
+        self.last_update = Some(UNIX_EPOCH + Duration::from_millis(snapshot.ts));
+    }
+
+    /// Returns the highest bid.
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        self.iter_bids().next()
+    }
+
+    /// Returns the lowest ask.
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        self.iter_asks().next()
+    }
+
+    /// Returns the highest bid and the lowest ask, if both sides are populated.
+    pub fn top_of_book(&self) -> Option<(PriceLevel, PriceLevel)> {
+        self.best_bid().zip(self.best_ask())
+    }
+
+    /// Best ask minus best bid; negative when the book is crossed.
+    pub fn spread(&self) -> Option<Decimal> {
+        self.top_of_book().map(|(bid, ask)| ask.price - bid.price)
+    }
+
+    /// Average of the best bid and best ask.
+    pub fn mid_price(&self) -> Option<Decimal> {
+        self.top_of_book()
+            .map(|(bid, ask)| ((bid.price + ask.price) / Decimal::TWO).normalize())
+    }
+
+    /// Mid price weighted by the quantities at the top of book, each price by the other
+    /// side's quantity, so it leans towards the side with less quantity, the one more likely
+    /// to be taken out next. `None` without both sides or without any quantity on them.
+    pub fn weighted_mid(&self) -> Option<f64> {
+        let (bid, ask) = self.top_of_book()?;
+        let total = bid.qty + ask.qty;
+        if total.is_zero() {
+            return None;
+        }
+        ((bid.price * ask.qty + ask.price * bid.qty) / total).to_f64()
+    }
+
+    /// Walks `side` from the top accumulating quantity until `size` is filled, returning the
+    /// volume-weighted average price and the filled quantity, which is less than `size` when
+    /// the side is too thin. Walking [`Side::Ask`] prices a market buy, [`Side::Bid`] a sell.
+    pub fn vwap_for_size(&self, side: Side, size: f64) -> Option<(f64, f64)> {
+        let mut filled = 0.0;
+        let mut notional = 0.0;
+        for level in self.iter_side(side) {
+            if filled >= size {
+                break;
+            }
+            let price = level.price.to_f64()?;
+            let qty = level.qty.to_f64()?.min(size - filled);
+            filled += qty;
+            notional += price * qty;
+        }
+        if filled > 0.0 {
+            Some((notional / filled, filled))
+        } else {
+            None
+        }
+    }
+
+    /// Quantity available at `limit_price` or better: the asks priced at or below it for a
+    /// buy walking [`Side::Ask`], the bids at or above it for a sell walking [`Side::Bid`].
+    /// Zero when no level is within the limit.
+    pub fn volume_to_price(&self, side: Side, limit_price: f64) -> f64 {
+        let Some(limit) = Decimal::from_f64(limit_price) else {
+            return 0.0;
+        };
+        let volume: Decimal = self
+            .iter_side(side)
+            .take_while(|level| side.is_within(level.price, limit))
+            .map(|level| level.qty)
+            .sum();
+        volume.to_f64().unwrap_or_default()
+    }
+
+    /// Total quantity of the top `levels` of `side`.
+    pub fn volume(&self, side: Side, levels: usize) -> Decimal {
+        self.iter_side(side)
+            .take(levels)
+            .map(|level| level.qty)
+            .sum()
+    }
+
+    /// Share of bid volume in the combined volume of the top `levels` on each side,
+    /// so 0.5 is balanced, 1.0 means only bids and 0.0 only asks. `None` for an empty book.
+    pub fn imbalance(&self, levels: usize) -> Option<f64> {
+        let bid_volume = self.volume(Side::Bid, levels);
+        let ask_volume = self.volume(Side::Ask, levels);
+        let total = bid_volume + ask_volume;
+        if total.is_zero() {
+            return None;
+        }
+        (bid_volume / total).to_f64()
+    }
+
+    /// Capital resting on the top `levels` of `side`, the sum of price times quantity; zero
+    /// for an empty side.
+    pub fn notional(&self, side: Side, levels: usize) -> Decimal {
+        self.iter_side(side)
+            .take(levels)
+            .map(|level| level.price * level.qty)
+            .sum()
+    }
+
+    /// [`Orderbook::notional`] of the bids.
+    pub fn bid_notional(&self, levels: usize) -> Decimal {
+        self.notional(Side::Bid, levels)
+    }
+
+    /// [`Orderbook::notional`] of the asks.
+    pub fn ask_notional(&self, levels: usize) -> Decimal {
+        self.notional(Side::Ask, levels)
+    }
+
+    /// Whether the best bid is at or above the best ask, which a consistent book never is.
+    pub fn is_crossed(&self) -> bool {
+        self.top_of_book()
+            .is_some_and(|(bid, ask)| bid.price >= ask.price)
+    }
+
+    /// Checks the invariants every consistent book holds, returning the first one broken:
+    /// positive prices and quantities, each side strictly moving away from the top of book,
+    /// no more levels than the maximum depth, and no crossing. Prices are decimals, which
+    /// can't be NaN.
+    pub fn validate(&self) -> Result<(), BookError> {
+        for side in [Side::Bid, Side::Ask] {
+            let mut previous: Option<Decimal> = None;
+            for level in self.iter_side(side) {
+                if level.price <= Decimal::ZERO {
+                    return Err(BookError::NonPositivePrice {
+                        side,
+                        price: level.price,
+                    });
+                }
+                if level.qty <= Decimal::ZERO {
+                    return Err(BookError::NonPositiveQty {
+                        side,
+                        price: level.price,
+                        qty: level.qty,
+                    });
+                }
+                if let Some(previous) =
+                    previous.filter(|previous| side.is_within(level.price, *previous))
+                {
+                    return Err(BookError::OutOfOrder {
+                        side,
+                        previous,
+                        price: level.price,
+                    });
+                }
+                previous = Some(level.price);
+            }
+            let levels = self.depth(side);
+            if let Some(max_depth) = self
+                .subscribed_depth
+                .filter(|max_depth| levels > *max_depth)
+            {
+                return Err(BookError::TooDeep {
+                    side,
+                    levels,
+                    max_depth,
+                });
+            }
+        }
+        match self.top_of_book() {
+            Some((bid, ask)) if bid.price >= ask.price => Err(BookError::Crossed {
+                bid: bid.price,
+                ask: ask.price,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Formats the top of book as a single line, or `None` while either side is empty.
+    /// With an EMA configured, the line ends with it and an arrow for its last move.
+    pub fn format_top_of_book(&self) -> Option<String> {
+        let price_decimals = self.price_decimals();
+        let qty_decimals = self.qty_decimals();
+        self.top_of_book().map(|(bid, ask)| {
+            let price = |value: Decimal| format_decimal(value, price_decimals);
+            let qty = |value: Decimal| format_decimal(value, qty_decimals);
+            // the mid of two prices needs one more decimal
+            let mid = (bid.price + ask.price) / Decimal::TWO;
+            let mut line = format!(
+                "BID {} {} <-> ASK {} {} spread {} mid {}",
+                price(bid.price),
+                qty(bid.qty),
+                price(ask.price),
+                qty(ask.qty),
+                price(ask.price - bid.price),
+                format_decimal(mid, price_decimals + 1)
+            );
+            if let Some((ema, trend)) = self.mid_ema().zip(self.mid_ema_trend()) {
+                let arrow = match trend {
+                    Ordering::Greater => '↑',
+                    Ordering::Less => '↓',
+                    Ordering::Equal => '→',
+                };
+                // one more decimal than the prices, the same the mid needs
+                line.push_str(&format!(" ema {:.*} {}", price_decimals + 1, ema, arrow));
+            }
+            line
+        })
+    }
+
+    /// Returns `(price, qty, cumulative_qty)` for the top `levels` of `side`, best first,
+    /// where `cumulative_qty` is the total quantity from the top of book to that level.
+    pub fn cumulative(&self, side: Side, levels: usize) -> Vec<(f64, f64, f64)> {
+        self.iter_side(side)
+            .take(levels)
+            // summed exactly, only the results are converted to floats
+            .scan(Decimal::ZERO, |total, level| {
+                *total += level.qty;
+                Some((
+                    level.price.to_f64().unwrap_or_default(),
+                    level.qty.to_f64().unwrap_or_default(),
+                    total.to_f64().unwrap_or_default(),
+                ))
+            })
+            .collect()
+    }
+
+    /// [`Orderbook::cumulative`] of the bids.
+    pub fn cumulative_bids(&self, levels: usize) -> Vec<(f64, f64, f64)> {
+        self.cumulative(Side::Bid, levels)
+    }
+
+    /// [`Orderbook::cumulative`] of the asks.
+    pub fn cumulative_asks(&self, levels: usize) -> Vec<(f64, f64, f64)> {
+        self.cumulative(Side::Ask, levels)
+    }
+
+    /// Draws the top `levels` of both sides as a depth ladder: asks from the furthest down to
+    /// the best one, a spread line, then bids from the best one down. Each bar is `width`
+    /// characters at most and proportional to the cumulative quantity at its level.
+    /// Sides with fewer levels are padded with blank rows, so the ladder always has
+    /// `2 * levels + 1` lines and redrawing it in place overwrites the previous one.
+    pub fn render_ladder(&self, levels: usize, width: usize) -> String {
+        let asks = self.cumulative_asks(levels);
+        let bids = self.cumulative_bids(levels);
+        let max_cumulative = [asks.last(), bids.last()]
+            .into_iter()
+            .flatten()
+            .map(|(_, _, cumulative)| *cumulative)
+            .fold(0.0, f64::max);
+
+        let mut rows = Vec::with_capacity(2 * levels + 1);
+        rows.extend((asks.len()..levels).map(|_| String::new()));
+        rows.extend(
+            asks.iter()
+                .rev()
+                .map(|row| self.ladder_row("ASK", *row, max_cumulative, width)),
+        );
+        rows.push(match self.spread() {
+            Some(spread) => format!("{:-^33}", format!(" spread {} ", spread)),
+            None => format!("{:-^33}", " no spread "),
+        });
+        rows.extend(
+            bids.iter()
+                .map(|row| self.ladder_row("BID", *row, max_cumulative, width)),
+        );
+        rows.extend((bids.len()..levels).map(|_| String::new()));
+        rows.join("\n")
+    }
+
+    fn ladder_row(
+        &self,
+        label: &str,
+        (price, qty, cumulative): (f64, f64, f64),
+        max_cumulative: f64,
+        width: usize,
+    ) -> String {
+        let bar = if max_cumulative > 0.0 {
+            (cumulative / max_cumulative * width as f64).round() as usize
+        } else {
+            0
+        };
+        format!(
+            "{} {:>12} {:>16} {}",
+            label,
+            format!("{:.*}", self.price_decimals(), price),
+            format!("{:.*}", self.qty_decimals(), qty),
+            "#".repeat(bar)
+        )
+    }
+
+    /// Decimal places prices are shown with.
+    pub fn price_decimals(&self) -> usize {
+        self.price_decimals.unwrap_or(self.price_precision)
+    }
+
+    /// Decimal places quantities are shown with.
+    pub fn qty_decimals(&self) -> usize {
+        self.qty_decimals.unwrap_or(self.qty_precision)
+    }
+
+    /// Copy of the book with its levels merged into price buckets for a coarse view: each
+    /// price is rounded to the nearest multiple of `bucket`, halfway prices away from zero,
+    /// and the quantities within a bucket are summed. A bucket size that isn't positive
+    /// leaves the levels as they are. The book itself keeps its full resolution.
+    pub fn bucketed_view(&self, bucket: f64) -> Orderbook {
+        let bucket = Decimal::from_f64(bucket).filter(|bucket| *bucket > Decimal::ZERO);
+        let bucket_price = |price: Decimal| match bucket {
+            Some(bucket) => {
+                (price / bucket).round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+                    * bucket
+            }
+            None => price,
+        };
+        let mut view =
+            Orderbook::new(&self.symbol).with_precision(self.price_precision, self.qty_precision);
+        view.price_decimals = self.price_decimals;
+        view.qty_decimals = self.qty_decimals;
+        view.last_update = self.last_update;
+        view.has_snapshot = self.has_snapshot;
+        for (Reverse(price), qty) in &self.bids {
+            *view.bids.entry(Reverse(bucket_price(*price))).or_default() += qty;
+        }
+        for (price, qty) in &self.asks {
+            *view.asks.entry(bucket_price(*price)).or_default() += qty;
+        }
+        view
+    }
+
+    pub fn handle_snapshot(&mut self, update: &BookUpdate) {
+        self.bids.clear();
+        self.asks.clear();
+        self.has_snapshot = true;
+        self.handle_update(update);
+    }
+
+    pub fn handle_update(&mut self, update: &BookUpdate) {
+        update.bids.iter().for_each(|bid| {
+            if !self.is_valid(Side::Bid, bid) {
+                return;
+            }
+            let price = self.level_price(bid.price);
+            if self.deletes_level(bid.qty) {
+                let old = self.bids.remove(&Reverse(price));
+                self.log_level_change(Side::Bid, price, old, None);
+            } else {
+                let old = self.bids.insert(Reverse(price), bid.qty);
+                self.log_level_change(Side::Bid, price, old, Some(bid.qty));
+            }
+        });
+
+        update.asks.iter().for_each(|ask| {
+            if !self.is_valid(Side::Ask, ask) {
+                return;
+            }
+            let price = self.level_price(ask.price);
+            if self.deletes_level(ask.qty) {
+                let old = self.asks.remove(&price);
+                self.log_level_change(Side::Ask, price, old, None);
+            } else {
+                let old = self.asks.insert(price, ask.qty);
+                self.log_level_change(Side::Ask, price, old, Some(ask.qty));
+            }
+        });
+
+        self.truncate();
+    }
+
+    /// Logs a level going from the `old` to the `new` quantity at debug level, `None` being
+    /// a missing level. Deletes of levels that weren't there aren't logged.
+    fn log_level_change(
+        &self,
+        side: Side,
+        price: Decimal,
+        old: Option<Decimal>,
+        new: Option<Decimal>,
+    ) {
+        let action = match (old, new) {
+            (None, Some(_)) => "insert",
+            (Some(_), Some(_)) => "update",
+            (Some(_), None) => "delete",
+            (None, None) => return,
+        };
+        debug!(
+            "level {} symbol={} side={:?} price={} old_qty={} new_qty={}",
+            action,
+            self.symbol,
+            side,
+            price,
+            old.unwrap_or_default(),
+            new.unwrap_or_default()
+        );
+    }
+
+    /// Whether an incoming quantity removes its level rather than setting it.
+    fn deletes_level(&self, qty: Decimal) -> bool {
+        qty.is_zero() || qty < self.qty_epsilon
+    }
+
+    /// Whether an incoming level can be applied: its price must be positive and its quantity
+    /// non-negative. Decimals can't hold NaN or infinity, so those already fail to parse.
+    fn is_valid(&self, side: Side, level: &PriceLevel) -> bool {
+        let valid = level.price > Decimal::ZERO && !level.qty.is_sign_negative();
+        if !valid {
+            warn!(
+                "Skipping malformed {:?} level for {}: price {} qty {}",
+                side, self.symbol, level.price, level.qty
+            );
+        }
+        valid
+    }
+
+    /// The price of the level an incoming price belongs to.
+    fn level_price(&self, price: Decimal) -> Decimal {
+        match self.tick_size {
+            Some(tick_size) => (price / tick_size).round() * tick_size,
+            None => price,
+        }
+    }
+
+    fn truncate(&mut self) {
+        if let Some(max_depth) = self.subscribed_depth {
+            while self.bids.len() > max_depth {
+                self.bids.pop_last();
+            }
+            while self.asks.len() > max_depth {
+                self.asks.pop_last();
+            }
+        }
+    }
+}
+
+/// Quantities before an update of every level it can change: the `touched` ones, the ones
+/// at the bottom of the side it could push beyond the maximum depth, and with `replace`
+/// every level.
+fn prior_levels<K: Ord + Copy>(
+    levels: &BTreeMap<K, Decimal>,
+    touched: impl ExactSizeIterator<Item = K>,
+    replace: bool,
+) -> BTreeMap<K, Option<Decimal>> {
+    let displaced = if replace { levels.len() } else { touched.len() };
+    let mut prior: BTreeMap<K, Option<Decimal>> = levels
+        .iter()
+        .rev()
+        .take(displaced)
+        .map(|(key, qty)| (*key, Some(*qty)))
+        .collect();
+    for key in touched {
+        prior.insert(key, levels.get(&key).copied());
+    }
+    prior
+}
+
+/// Compares the quantities from [`prior_levels`] with the side after the update.
+fn side_diff<K: Ord + Copy>(
+    prior: BTreeMap<K, Option<Decimal>>,
+    levels: &BTreeMap<K, Decimal>,
+    price: impl Fn(K) -> Decimal,
+) -> SideDiff {
+    let mut diff = SideDiff::default();
+    for (key, before) in prior {
+        let level = |qty| PriceLevel {
+            price: price(key),
+            qty,
+        };
+        match (before, levels.get(&key).copied()) {
+            (None, Some(after)) => diff.added.push(level(after)),
+            (Some(before), Some(after)) if before != after => diff.modified.push(level(after)),
+            (Some(before), None) => diff.removed.push(level(before)),
+            _ => {}
+        }
+    }
+    diff
+}
+
+/// Exponential moving average after `sample`: `alpha * sample + (1 - alpha) * prev`,
+/// or `sample` itself when there is no previous value.
+pub fn ema_update(prev: Option<f64>, sample: f64, alpha: f64) -> f64 {
+    match prev {
+        Some(prev) => alpha * sample + (1.0 - alpha) * prev,
+        None => sample,
+    }
+}
+
+/// Formats `value` with exactly `decimals` decimal places, rounding halfway values away
+/// from zero.
+pub fn format_decimal(value: Decimal, decimals: usize) -> String {
+    let rounded = value.round_dp_with_strategy(
+        u32::try_from(decimals).unwrap_or(u32::MAX),
+        RoundingStrategy::MidpointAwayFromZero,
+    );
+    format!("{:.*}", decimals, rounded)
+}
+
+/// Formats a value with the given precision, dropping the decimal point and leading zeros.
+fn checksum_field(value: Decimal, precision: usize) -> String {
+    format!("{:.*}", precision, value)
+        .replace('.', "")
+        .trim_start_matches('0')
+        .to_string()
+}
+
+/// Dispatches every entry of a book message to the order book of its symbol and
+/// returns the symbols whose book was touched, leaving out books still waiting for their
+/// snapshot.
+pub fn route(books: &mut HashMap<String, Orderbook>, updates: Vec<BookUpdate>) -> Vec<String> {
+    route_entries(books, updates)
+        .into_iter()
+        .map(|applied| applied.symbol)
+        .collect()
+}
+
+/// An entry [`route_entries`] applied to its book.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AppliedEntry {
+    pub symbol: String,
+    pub kind: UpdateKind,
+    /// whether the entry's checksum didn't match the book it left
+    pub checksum_failed: bool,
+}
+
+/// [`route`] reporting each entry that touched its book, so that entries can be counted
+/// one by one even when several of them are applied to the same book at once.
+pub fn route_entries(
+    books: &mut HashMap<String, Orderbook>,
+    updates: Vec<BookUpdate>,
+) -> Vec<AppliedEntry> {
+    let mut applied = Vec::new();
+    updates
+        .iter()
+        .for_each(|update| match books.get_mut(&update.symbol) {
+            Some(orderbook) => {
+                let duplicate = orderbook.is_duplicate_snapshot(update);
+                orderbook.evaluate(update);
+                if orderbook.has_snapshot && !duplicate {
+                    applied.push(AppliedEntry {
+                        symbol: orderbook.symbol.clone(),
+                        kind: update.kind,
+                        checksum_failed: orderbook.checksum_failed,
+                    });
+                }
+            }
+            None => warn!(
+                "Received book entry for unsubscribed symbol {}",
+                update.symbol
+            ),
+        });
+    applied
+}
+
+/// Leaves the checksum only on the last entry of each symbol, for applying the entries of
+/// several messages as one batch: an exchange checksum holds for the book right after its
+/// own entry, so only the last one can be checked once the whole batch is applied.
+pub fn keep_last_checksums(updates: &mut [BookUpdate]) {
+    let mut seen = HashSet::new();
+    for update in updates.iter_mut().rev() {
+        if !seen.insert(update.symbol.clone()) {
+            update.checksum = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::kraken::parse_timestamp;
+    use crate::test_log::logged;
+    use crate::test_support::{assert_book_eq, snapshot_diff};
+
+    /// Converts the way the feed parser does, so `0.1` becomes exactly `0.1`.
+    fn decimal(value: f64) -> Decimal {
+        value.to_string().parse().unwrap()
+    }
+
+    fn entry(price: f64, qty: f64) -> PriceLevel {
+        PriceLevel {
+            price: decimal(price),
+            qty: decimal(qty),
+        }
+    }
+
+    fn book_update(
+        kind: UpdateKind,
+        symbol: &str,
+        bids: Vec<PriceLevel>,
+        asks: Vec<PriceLevel>,
+    ) -> BookUpdate {
+        BookUpdate {
+            symbol: String::from(symbol),
+            kind,
+            bids,
+            asks,
+            checksum: None,
+            timestamp: None,
+        }
+    }
+
+    fn snapshot(symbol: &str, bids: Vec<PriceLevel>, asks: Vec<PriceLevel>) -> BookUpdate {
+        book_update(UpdateKind::Snapshot, symbol, bids, asks)
+    }
+
+    fn update(symbol: &str, bids: Vec<PriceLevel>, asks: Vec<PriceLevel>) -> BookUpdate {
+        book_update(UpdateKind::Update, symbol, bids, asks)
+    }
+
+    #[test]
+    fn top_of_book_is_highest_bid_and_lowest_ask() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(102.0, 2.0), entry(101.0, 3.0)],
+            vec![entry(105.0, 4.0), entry(103.0, 5.0), entry(104.0, 6.0)],
+        ));
+
+        let (bid, ask) = orderbook.top_of_book().unwrap();
+        assert_eq!(bid.price, decimal(102.0));
+        assert_eq!(bid.qty, decimal(2.0));
+        assert_eq!(ask.price, decimal(103.0));
+        assert_eq!(ask.qty, decimal(5.0));
+    }
+
+    #[test]
+    fn top_of_book_requires_both_sides() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot("BTC/USD", vec![entry(100.0, 1.0)], vec![]));
+
+        assert!(orderbook.top_of_book().is_none());
+    }
+
+    #[test]
+    fn format_top_of_book_shows_both_prices() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(43210.1, 0.5), entry(43209.0, 1.0)],
+            vec![entry(43211.4, 0.25), entry(43212.0, 2.0)],
+        ));
+
+        assert_eq!(
+            orderbook.format_top_of_book().unwrap(),
+            "BID 43210.1 0.50000000 <-> ASK 43211.4 0.25000000 spread 1.3 mid 43210.75"
+        );
+
+        let orderbook = orderbook.with_price_decimals(0).with_qty_decimals(3);
+        assert_eq!(
+            orderbook.format_top_of_book().unwrap(),
+            "BID 43210 0.500 <-> ASK 43211 0.250 spread 1 mid 43210.8"
+        );
+    }
+
+    #[test]
+    fn format_decimal_pads_and_rounds_to_the_decimals() {
+        assert_eq!(format_decimal(decimal(100.0), 2), "100.00");
+        assert_eq!(format_decimal(decimal(0.12345), 3), "0.123");
+        assert_eq!(format_decimal(decimal(0.1235), 3), "0.124");
+        assert_eq!(format_decimal(decimal(-2.5), 0), "-3");
+        assert_eq!(format_decimal(decimal(43210.75), 8), "43210.75000000");
+    }
+
+    fn expected(bids: Vec<PriceLevel>, asks: Vec<PriceLevel>) -> BookSnapshot {
+        BookSnapshot {
+            symbol: String::from("BTC/USD"),
+            bids,
+            asks,
+            ts: 0,
+        }
+    }
+
+    #[test]
+    fn updates_leave_the_expected_book() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_max_depth(3);
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 2.0), entry(98.0, 3.0)],
+            vec![entry(101.0, 1.0), entry(102.0, 2.0)],
+        ));
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(99.0, 0.0), entry(100.5, 0.5), entry(97.0, 1.0)],
+            vec![entry(101.0, 4.0), entry(103.0, 1.0), entry(104.0, 1.0)],
+        ));
+
+        assert_book_eq(
+            &orderbook,
+            &expected(
+                vec![entry(100.5, 0.5), entry(100.0, 1.0), entry(98.0, 3.0)],
+                vec![entry(101.0, 4.0), entry(102.0, 2.0), entry(103.0, 1.0)],
+            ),
+        );
+    }
+
+    #[test]
+    fn snapshot_diff_lists_each_mismatching_level() {
+        let actual = expected(
+            vec![entry(100.0, 1.0), entry(99.0, 2.0)],
+            vec![entry(101.0, 1.0)],
+        );
+        assert_eq!(
+            snapshot_diff(&actual, &actual.clone()),
+            Vec::<String>::new()
+        );
+
+        let other = expected(
+            vec![entry(100.0, 1.0), entry(99.0, 1.5), entry(98.0, 1.0)],
+            vec![],
+        );
+        assert_ne!(actual, other);
+        assert_eq!(
+            snapshot_diff(&actual, &other),
+            vec![
+                "bid level 2: expected 99 x 1.5, got 99 x 2",
+                "bid level 3: missing 98 x 1",
+                "ask level 1: unexpected 101 x 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn format_top_of_book_is_none_for_empty_book() {
+        assert!(Orderbook::new("BTC/USD").format_top_of_book().is_none());
+    }
+
+    #[test]
+    fn ema_starts_at_the_first_sample() {
+        assert_eq!(ema_update(None, 100.0, 0.1), 100.0);
+    }
+
+    #[test]
+    fn ema_moves_alpha_of_the_way_towards_each_sample() {
+        assert!((ema_update(Some(100.0), 110.0, 0.1) - 101.0).abs() < 1e-9);
+
+        // a constant input is a fixed point, and the EMA converges to a new level
+        assert_eq!(ema_update(Some(100.0), 100.0, 0.1), 100.0);
+        let converged = (0..200).fold(100.0, |ema, _| ema_update(Some(ema), 110.0, 0.1));
+        assert!((converged - 110.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn top_of_book_shows_the_mid_ema_and_its_direction() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_ema_alpha(0.5);
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0)],
+            vec![entry(102.0, 1.0)],
+        ));
+        assert!(orderbook
+            .format_top_of_book()
+            .unwrap()
+            .ends_with("mid 101.00 ema 101.00 →"));
+
+        orderbook.evaluate(&update("BTC/USD", vec![entry(104.0, 1.0)], vec![]));
+        assert_eq!(orderbook.mid_ema(), Some(102.0));
+        assert!(orderbook
+            .format_top_of_book()
+            .unwrap()
+            .ends_with("ema 102.00 ↑"));
+
+        orderbook.evaluate(&update("BTC/USD", vec![entry(104.0, 0.0)], vec![]));
+        assert_eq!(orderbook.mid_ema_trend(), Some(Ordering::Less));
+        assert_eq!(Orderbook::new("BTC/USD").mid_ema(), None);
+    }
+
+    #[test]
+    fn recent_ticks_keep_the_latest_top_of_book_changes() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_tick_capacity(3);
+        assert!(orderbook.recent_ticks(10).is_empty());
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0)],
+            vec![entry(101.0, 1.0)],
+        ));
+        // below the top, so the top of book doesn't change
+        orderbook.evaluate(&update("BTC/USD", vec![entry(99.0, 1.0)], vec![]));
+        for bid in [100.1, 100.2, 100.3, 100.4] {
+            orderbook.evaluate(&update("BTC/USD", vec![entry(bid, 1.0)], vec![]));
+            assert!(orderbook.recent_ticks(10).len() <= 3);
+        }
+
+        let bids: Vec<Decimal> = orderbook
+            .recent_ticks(10)
+            .iter()
+            .map(|tick| tick.bid)
+            .collect();
+        assert_eq!(bids, vec![decimal(100.2), decimal(100.3), decimal(100.4)]);
+        let ticks = orderbook.recent_ticks(2);
+        assert_eq!(
+            (ticks[0].bid, ticks[1].bid),
+            (decimal(100.3), decimal(100.4))
+        );
+        assert!(ticks[0].timestamp <= ticks[1].timestamp);
+        assert_eq!(ticks[1].ask, decimal(101.0));
+    }
+
+    #[test]
+    fn session_range_tracks_the_extreme_mids() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert_eq!(orderbook.session_high(), None);
+        let mut mid = |bid: f64, ask: f64| {
+            orderbook.evaluate(&snapshot(
+                "BTC/USD",
+                vec![entry(bid, 1.0)],
+                vec![entry(ask, 1.0)],
+            ));
+            (orderbook.session_low(), orderbook.session_high())
+        };
+
+        assert_eq!(
+            mid(100.0, 101.0),
+            (Some(decimal(100.5)), Some(decimal(100.5)))
+        );
+        assert_eq!(
+            mid(101.0, 102.0),
+            (Some(decimal(100.5)), Some(decimal(101.5)))
+        );
+        assert_eq!(mid(98.0, 99.0), (Some(decimal(98.5)), Some(decimal(101.5))));
+        assert_eq!(
+            mid(100.0, 100.2),
+            (Some(decimal(98.5)), Some(decimal(101.5)))
+        );
+
+        orderbook.reset_session_range();
+        assert_eq!(orderbook.session_low(), None);
+        orderbook.evaluate(&update("BTC/USD", vec![entry(100.1, 1.0)], vec![]));
+        assert_eq!(orderbook.session_low(), Some(decimal(100.15)));
+        assert_eq!(orderbook.session_high(), Some(decimal(100.15)));
+
+        let carried = Orderbook::new("BTC/USD").with_session_range(decimal(90.0), decimal(110.0));
+        assert_eq!(carried.session_low(), Some(decimal(90.0)));
+        assert_eq!(carried.session_high(), Some(decimal(110.0)));
+    }
+
+    #[test]
+    fn mid_price_bars_complete_on_the_next_interval() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_ohlc_interval(Duration::from_secs(1));
+        let mut timestamped = |timestamp: &str, bid: f64, ask: f64| {
+            let mut update = snapshot("BTC/USD", vec![entry(bid, 1.0)], vec![entry(ask, 1.0)]);
+            update.timestamp = parse_timestamp(timestamp);
+            orderbook.evaluate(&update);
+            orderbook.completed_bar()
+        };
+
+        assert_eq!(timestamped("2024-01-01T00:00:00.100Z", 100.0, 102.0), None);
+        assert_eq!(timestamped("2024-01-01T00:00:00.900Z", 98.0, 100.0), None);
+        let bar = timestamped("2024-01-01T00:00:01.000Z", 104.0, 106.0).unwrap();
+
+        assert_eq!(
+            (bar.open, bar.high, bar.low, bar.close),
+            (101.0, 101.0, 99.0, 99.0)
+        );
+        assert_eq!(bar.start, 1_704_067_200_000);
+        assert_eq!(orderbook.completed_bar(), Some(bar));
+    }
+
+    #[test]
+    fn cumulative_quantity_runs_from_the_top_of_book() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 2.5), entry(98.0, 0.5)],
+            vec![entry(101.0, 0.2), entry(102.0, 0.3), entry(103.0, 4.0)],
+        ));
+
+        assert_eq!(
+            orderbook.cumulative_bids(3),
+            vec![(100.0, 1.0, 1.0), (99.0, 2.5, 3.5), (98.0, 0.5, 4.0)]
+        );
+        // exact accumulation: 0.2 + 0.3 is 0.5, not 0.5000000000000001
+        assert_eq!(
+            orderbook.cumulative_asks(3),
+            vec![(101.0, 0.2, 0.2), (102.0, 0.3, 0.5), (103.0, 4.0, 4.5)]
+        );
+        assert_eq!(orderbook.cumulative_asks(1).len(), 1);
+    }
+
+    #[test]
+    fn level_helpers_walk_either_side_from_the_top() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 2.0), entry(98.0, 4.0)],
+            vec![entry(101.0, 0.5), entry(102.0, 1.5), entry(103.0, 3.0)],
+        ));
+
+        for (side, best, second, limit) in [
+            (Side::Bid, 100.0, 99.0, 99.0),
+            (Side::Ask, 101.0, 102.0, 102.0),
+        ] {
+            let levels: Vec<PriceLevel> = orderbook.iter_side(side).collect();
+            assert_eq!(levels[0].price, decimal(best), "{:?}", side);
+            assert_eq!(levels[1].price, decimal(second), "{:?}", side);
+            assert_eq!(
+                orderbook.volume(side, 2),
+                levels[0].qty + levels[1].qty,
+                "{:?}",
+                side
+            );
+            assert_eq!(
+                orderbook.notional(side, 1),
+                levels[0].price * levels[0].qty,
+                "{:?}",
+                side
+            );
+            assert_eq!(orderbook.cumulative(side, 3)[0].0, best, "{:?}", side);
+            assert_eq!(
+                orderbook.volume_to_price(side, limit),
+                orderbook.volume(side, 2).to_f64().unwrap(),
+                "{:?}",
+                side
+            );
+            assert!(side.is_within(decimal(best), decimal(second)));
+            assert!(!side.is_within(decimal(second), decimal(best)));
+        }
+        assert_eq!(orderbook.volume(Side::Bid, 10), decimal(7.0));
+        assert_eq!(
+            orderbook.cumulative(Side::Ask, 3),
+            orderbook.cumulative_asks(3)
+        );
+    }
+
+    #[test]
+    fn render_ladder_stacks_asks_above_bids() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_precision(1, 2);
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.5, 3.0)],
+            vec![entry(100.5, 2.0)],
+        ));
+
+        let ladder = orderbook.render_ladder(2, 8);
+        let lines: Vec<&str> = ladder.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "",
+                "ASK        100.5             2.00 ####",
+                "---------- spread 0.5 -----------",
+                "BID        100.0             1.00 ##",
+                "BID         99.5             3.00 ########",
+            ]
+        );
+    }
+
+    #[test]
+    fn spread_and_mid_price() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert_eq!(orderbook.spread(), None);
+        assert_eq!(orderbook.mid_price(), None);
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(43210.0, 1.0)],
+            vec![entry(43211.3, 1.0)],
+        ));
+        assert_eq!(orderbook.spread(), Some(decimal(1.3)));
+        assert_eq!(orderbook.mid_price(), Some(decimal(43210.65)));
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.5, 1.0)],
+            vec![entry(100.0, 1.0)],
+        ));
+        assert_eq!(orderbook.spread(), Some(decimal(-0.5)));
+        assert_eq!(orderbook.mid_price(), Some(decimal(100.25)));
+    }
+
+    #[test]
+    fn weighted_mid_leans_away_from_the_larger_side() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert_eq!(orderbook.weighted_mid(), None);
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 2.0)],
+            vec![entry(101.0, 2.0)],
+        ));
+        assert_eq!(orderbook.weighted_mid(), Some(100.5));
+        assert_eq!(orderbook.mid_price(), Some(decimal(100.5)));
+
+        orderbook.evaluate(&update("BTC/USD", vec![entry(100.0, 3.0)], vec![]));
+        assert_eq!(orderbook.weighted_mid(), Some(100.6));
+        assert!(orderbook.weighted_mid() > orderbook.mid_price().and_then(|mid| mid.to_f64()));
+
+        orderbook.evaluate(&update("BTC/USD", vec![entry(100.0, 1.0)], vec![]));
+        let weighted_mid = orderbook.weighted_mid().unwrap();
+        assert!((weighted_mid - (100.0 + 1.0 / 3.0)).abs() < 1e-9);
+        assert!(weighted_mid < 100.5);
+
+        orderbook.evaluate(&update("BTC/USD", vec![], vec![entry(101.0, 0.0)]));
+        assert_eq!(orderbook.weighted_mid(), None);
+    }
+
+    #[test]
+    fn vwap_for_size_walks_the_book() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert_eq!(orderbook.vwap_for_size(Side::Ask, 1.0), None);
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(99.0, 1.0), entry(98.0, 1.0)],
+            vec![entry(100.0, 1.0), entry(102.0, 2.0)],
+        ));
+
+        assert_eq!(orderbook.vwap_for_size(Side::Ask, 0.5), Some((100.0, 0.5)));
+        assert_eq!(orderbook.vwap_for_size(Side::Ask, 2.0), Some((101.0, 2.0)));
+        assert_eq!(
+            orderbook.vwap_for_size(Side::Ask, 3.0),
+            Some((304.0 / 3.0, 3.0))
+        );
+        assert_eq!(
+            orderbook.vwap_for_size(Side::Ask, 10.0),
+            Some((304.0 / 3.0, 3.0))
+        );
+        assert_eq!(orderbook.vwap_for_size(Side::Bid, 2.0), Some((98.5, 2.0)));
+        assert_eq!(orderbook.vwap_for_size(Side::Bid, 0.0), None);
+    }
+
+    #[test]
+    fn volume_to_price_includes_the_limit_level() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert_eq!(orderbook.volume_to_price(Side::Ask, 100.0), 0.0);
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(99.0, 1.0), entry(98.0, 2.5)],
+            vec![entry(100.0, 1.0), entry(101.5, 0.5), entry(102.0, 2.0)],
+        ));
+
+        assert_eq!(orderbook.volume_to_price(Side::Ask, 99.9), 0.0);
+        assert_eq!(orderbook.volume_to_price(Side::Ask, 100.0), 1.0);
+        assert_eq!(orderbook.volume_to_price(Side::Ask, 101.5), 1.5);
+        assert_eq!(orderbook.volume_to_price(Side::Ask, 1000.0), 3.5);
+        assert_eq!(orderbook.volume_to_price(Side::Bid, 99.5), 0.0);
+        assert_eq!(orderbook.volume_to_price(Side::Bid, 98.0), 3.5);
+        assert_eq!(orderbook.volume_to_price(Side::Bid, 0.0), 3.5);
+        assert_eq!(orderbook.volume_to_price(Side::Ask, f64::NAN), 0.0);
+    }
+
+    #[test]
+    fn imbalance_over_top_levels() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert_eq!(orderbook.imbalance(5), None);
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 3.0), entry(99.0, 1.0), entry(98.0, 100.0)],
+            vec![entry(101.0, 1.0), entry(102.0, 3.0), entry(103.0, 100.0)],
+        ));
+        assert_eq!(orderbook.imbalance(2), Some(0.5));
+        assert_eq!(orderbook.imbalance(1), Some(0.75));
+
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![],
+            vec![entry(101.0, 0.0), entry(102.0, 0.0), entry(103.0, 0.0)],
+        ));
+        assert_eq!(orderbook.imbalance(2), Some(1.0));
+    }
+
+    #[test]
+    fn notional_sums_price_times_quantity_over_top_levels() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert_eq!(orderbook.bid_notional(5), Decimal::ZERO);
+        assert_eq!(orderbook.ask_notional(5), Decimal::ZERO);
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.5), entry(99.5, 2.0), entry(98.0, 10.0)],
+            vec![entry(101.0, 0.1)],
+        ));
+
+        assert_eq!(orderbook.bid_notional(2), decimal(349.0));
+        assert_eq!(orderbook.bid_notional(10), decimal(1329.0));
+        assert_eq!(orderbook.ask_notional(2), decimal(10.1));
+    }
+
+    #[test]
+    fn age_tracks_the_last_entry_timestamp() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert_eq!(orderbook.age(), None);
+
+        let mut timestamped = snapshot("BTC/USD", vec![entry(100.0, 1.0)], vec![]);
+        timestamped.timestamp = parse_timestamp("2023-10-06T17:35:55.440295Z");
+        orderbook.evaluate(&timestamped);
+
+        let expected = parse_timestamp("2023-10-06T17:35:55.440295Z").unwrap();
+        assert_eq!(orderbook.last_update(), Some(expected));
+        assert!(orderbook.age().unwrap() > Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn dumped_books_load_back_every_digit() {
+        let level = |price: &str, qty: &str| PriceLevel {
+            price: price.parse().unwrap(),
+            qty: qty.parse().unwrap(),
+        };
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![level("12345678.123456789", "0.000000001")],
+            vec![level("12345678.123456790", "98765.432109876543")],
+        ));
+        let mut dumped = Vec::new();
+        orderbook.dump_to_writer(&mut dumped).unwrap();
+
+        let loaded = Orderbook::from_snapshot(&serde_json::from_slice(&dumped).unwrap());
+
+        assert_eq!(
+            loaded.iter_bids().collect::<Vec<_>>(),
+            vec![level("12345678.123456789", "0.000000001")]
+        );
+        assert_eq!(
+            loaded.iter_asks().collect::<Vec<_>>(),
+            vec![level("12345678.123456790", "98765.432109876543")]
+        );
+    }
+
+    #[test]
+    fn dumped_books_load_back_level_for_level() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![
+                entry(27566.9, 2.145),
+                entry(27566.5, 0.00125),
+                entry(27560.0, 13.0),
+            ],
+            vec![entry(27567.1, 0.743), entry(27570.25, 1.5)],
+        ));
+        let mut dumped = Vec::new();
+        orderbook.dump_to_writer(&mut dumped).unwrap();
+
+        let snapshot: BookSnapshot = serde_json::from_slice(&dumped).unwrap();
+        let loaded = Orderbook::from_snapshot(&snapshot);
+
+        assert_eq!(loaded.symbol(), "BTC/USD");
+        assert!(loaded.has_snapshot());
+        assert_eq!(
+            loaded.iter_bids().collect::<Vec<_>>(),
+            orderbook.iter_bids().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            loaded.iter_asks().collect::<Vec<_>>(),
+            orderbook.iter_asks().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.checksum(), orderbook.checksum());
+        assert_eq!(
+            loaded.last_update(),
+            Some(UNIX_EPOCH + Duration::from_millis(snapshot.ts))
+        );
+        assert_eq!(loaded.latency_stats(), None);
+    }
+
+    #[test]
+    fn age_and_latency_follow_the_clock() {
+        let received = parse_timestamp("2023-10-06T17:35:55.440295Z").unwrap();
+        let clock = Arc::new(MockClock::new(received));
+        let mut orderbook = Orderbook::new("BTC/USD").with_clock(clock.clone());
+
+        let mut delayed = snapshot("BTC/USD", vec![entry(100.0, 1.0)], vec![]);
+        delayed.timestamp = Some(received - Duration::from_millis(250));
+        orderbook.evaluate(&delayed);
+        clock.advance(Duration::from_secs(3));
+
+        assert_eq!(orderbook.age(), Some(Duration::from_millis(3250)));
+        assert_eq!(
+            orderbook.latency_stats().unwrap().max,
+            Duration::from_millis(250)
+        );
+
+        orderbook.evaluate(&update("BTC/USD", vec![entry(100.0, 2.0)], vec![]));
+        assert_eq!(orderbook.last_update(), Some(clock.now()));
+        assert_eq!(orderbook.age(), Some(Duration::ZERO));
+        assert_eq!(orderbook.to_snapshot().ts, 1696613758440);
+    }
+
+    #[test]
+    fn spread_window_follows_the_applied_entries() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_spread_window(2);
+        assert!(Orderbook::new("BTC/USD").spread_window().is_none());
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0)],
+            vec![entry(103.0, 1.0)],
+        ));
+        orderbook.evaluate(&update("BTC/USD", vec![entry(102.0, 1.0)], vec![]));
+        orderbook.evaluate(&update("BTC/USD", vec![entry(102.5, 1.0)], vec![]));
+
+        let window = orderbook.spread_window().unwrap();
+        assert_eq!(window.mean(), Some(decimal(0.75)));
+        assert_eq!(window.max(), Some(decimal(1.0)));
+    }
+
+    #[test]
+    fn latency_is_measured_from_the_exchange_timestamp() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot("BTC/USD", vec![entry(100.0, 1.0)], vec![]));
+        assert_eq!(orderbook.latency_stats(), None);
+
+        let mut delayed = update("BTC/USD", vec![entry(100.0, 2.0)], vec![]);
+        delayed.timestamp = Some(SystemTime::now() - Duration::from_secs(2));
+        orderbook.evaluate(&delayed);
+
+        let stats = orderbook.latency_stats().unwrap();
+        assert!(stats.min >= Duration::from_secs(2));
+        assert!(stats.max < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn level_counts_follow_inserts_and_deletions() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_min_levels(2);
+        assert_eq!((orderbook.bid_levels(), orderbook.ask_levels()), (0, 0));
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 1.0)],
+            vec![entry(101.0, 1.0), entry(102.0, 1.0), entry(103.0, 1.0)],
+        ));
+        assert_eq!((orderbook.bid_levels(), orderbook.ask_levels()), (2, 3));
+        assert!(!orderbook.is_thin());
+
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(98.0, 1.0), entry(100.0, 2.0)],
+            vec![entry(101.0, 0.0), entry(102.0, 0.0)],
+        ));
+        assert_eq!((orderbook.bid_levels(), orderbook.ask_levels()), (3, 1));
+        assert!(orderbook.is_thin());
+
+        orderbook.evaluate(&update("BTC/USD", vec![], vec![entry(104.0, 1.0)]));
+        assert_eq!(orderbook.ask_levels(), 2);
+        assert!(!orderbook.is_thin());
+    }
+
+    #[test]
+    fn wide_spreads_are_reported_once_per_crossing() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_max_spread(decimal(5.0));
+        let warnings = logged(log::Level::Warn, || {
+            orderbook.evaluate(&snapshot(
+                "BTC/USD",
+                vec![entry(100.0, 1.0)],
+                vec![entry(104.0, 1.0), entry(110.0, 1.0)],
+            ));
+            assert!(!orderbook.is_wide());
+            orderbook.evaluate(&update("BTC/USD", vec![], vec![entry(104.0, 0.0)]));
+            assert!(orderbook.is_wide());
+            orderbook.evaluate(&update("BTC/USD", vec![entry(99.0, 1.0)], vec![]));
+            orderbook.evaluate(&update("BTC/USD", vec![], vec![entry(112.0, 1.0)]));
+            assert!(orderbook.is_wide());
+            orderbook.evaluate(&update("BTC/USD", vec![entry(106.0, 1.0)], vec![]));
+            assert!(!orderbook.is_wide());
+            orderbook.evaluate(&update("BTC/USD", vec![entry(106.0, 0.0)], vec![]));
+        });
+
+        assert_eq!(
+            warnings,
+            vec![
+                "Wide spread for BTC/USD: 10 above 5",
+                "Wide spread for BTC/USD: 10 above 5",
+            ]
+        );
+        assert!(orderbook.is_wide());
+    }
+
+    #[test]
+    fn bucketed_view_sums_quantities_per_rounded_price() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(104.9, 1.0), entry(95.0, 2.0), entry(94.9, 4.0)],
+            vec![entry(105.0, 1.0), entry(114.99, 0.5), entry(115.0, 3.0)],
+        ));
+
+        let view = orderbook.bucketed_view(10.0);
+
+        // halfway prices round away from zero: 95 and 105 go up, 94.9 and 114.99 don't
+        assert_eq!(
+            view.iter_bids().collect::<Vec<_>>(),
+            vec![entry(100.0, 3.0), entry(90.0, 4.0)]
+        );
+        assert_eq!(
+            view.iter_asks().collect::<Vec<_>>(),
+            vec![entry(110.0, 1.5), entry(120.0, 3.0)]
+        );
+        assert_eq!(orderbook.bid_levels(), 3);
+        assert_eq!(orderbook.best_bid().unwrap().price, decimal(104.9));
+
+        let fine = orderbook.bucketed_view(0.01);
+        assert_eq!(fine.ask_levels(), 3);
+        assert_eq!(fine.best_ask().unwrap().price, decimal(105.0));
+        assert_eq!(orderbook.bucketed_view(0.0).bid_levels(), 3);
+    }
+
+    #[test]
+    fn crossed_and_locked_books_are_detected() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        assert!(!orderbook.is_crossed());
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0)],
+            vec![entry(101.0, 1.0)],
+        ));
+        assert!(!orderbook.is_crossed());
+
+        orderbook.evaluate(&update("BTC/USD", vec![entry(101.0, 1.0)], vec![]));
+        assert!(orderbook.is_crossed());
+
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(101.0, 0.0)],
+            vec![entry(99.0, 1.0)],
+        ));
+        assert!(orderbook.is_crossed());
+    }
+
+    #[test]
+    fn updates_before_the_first_snapshot_are_ignored() {
+        let mut books = HashMap::from([(String::from("BTC/USD"), Orderbook::new("BTC/USD"))]);
+        let updated = route(
+            &mut books,
+            vec![update(
+                "BTC/USD",
+                vec![entry(100.0, 1.0)],
+                vec![entry(99.0, 1.0)],
+            )],
+        );
+
+        assert!(updated.is_empty());
+        assert!(!books["BTC/USD"].has_snapshot());
+        assert_eq!(books["BTC/USD"].depth(Side::Bid), 0);
+        assert_eq!(books["BTC/USD"].last_update(), None);
+
+        let updated = route(
+            &mut books,
+            vec![
+                snapshot("BTC/USD", vec![entry(100.0, 1.0)], vec![entry(101.0, 1.0)]),
+                update("BTC/USD", vec![entry(99.5, 2.0)], vec![]),
+            ],
+        );
+
+        assert_eq!(updated, vec!["BTC/USD", "BTC/USD"]);
+        assert_eq!(books["BTC/USD"].depth(Side::Bid), 2);
+        assert!(!books["BTC/USD"].is_crossed());
+    }
+
+    #[test]
+    fn route_dispatches_entries_by_symbol() {
+        let mut books = HashMap::from([
+            (String::from("BTC/USD"), Orderbook::new("BTC/USD")),
+            (String::from("ETH/USD"), Orderbook::new("ETH/USD")),
+        ]);
+        let updated = route(
+            &mut books,
+            vec![
+                snapshot(
+                    "BTC/USD",
+                    vec![entry(43000.0, 1.0)],
+                    vec![entry(43001.0, 1.0)],
+                ),
+                snapshot(
+                    "ETH/USD",
+                    vec![entry(2300.0, 2.0)],
+                    vec![entry(2301.0, 2.0)],
+                ),
+                snapshot("SOL/USD", vec![entry(150.0, 3.0)], vec![entry(151.0, 3.0)]),
+            ],
+        );
+
+        let (btc_bid, _) = books["BTC/USD"].top_of_book().unwrap();
+        let (eth_bid, _) = books["ETH/USD"].top_of_book().unwrap();
+        assert_eq!(btc_bid.price, decimal(43000.0));
+        assert_eq!(eth_bid.price, decimal(2300.0));
+        assert_eq!(books.len(), 2);
+        assert_eq!(updated, vec!["BTC/USD", "ETH/USD"]);
+    }
+
+    #[test]
+    fn best_bid_and_ask_follow_side_ordering() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(102.0, 2.0)],
+            vec![entry(104.0, 3.0), entry(103.0, 4.0)],
+        ));
+
+        assert_eq!(
+            orderbook.best_bid(),
+            Some(PriceLevel {
+                price: decimal(102.0),
+                qty: decimal(2.0)
+            })
+        );
+        assert_eq!(
+            orderbook.best_ask(),
+            Some(PriceLevel {
+                price: decimal(103.0),
+                qty: decimal(4.0)
+            })
+        );
+        assert_eq!(Orderbook::new("BTC/USD").best_bid(), None);
+    }
+
+    #[test]
+    fn deleting_a_level_keeps_its_neighbours() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 2.0), entry(98.0, 3.0)],
+            vec![entry(101.0, 1.0), entry(102.0, 2.0), entry(103.0, 3.0)],
+        ));
+
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(99.0, 0.0)],
+            vec![entry(102.0, 0.0)],
+        ));
+
+        let bids: Vec<Decimal> = orderbook.iter_bids().map(|level| level.price).collect();
+        let asks: Vec<Decimal> = orderbook.iter_asks().map(|level| level.price).collect();
+        assert_eq!(bids, vec![decimal(100.0), decimal(98.0)]);
+        assert_eq!(asks, vec![decimal(101.0), decimal(103.0)]);
+    }
+
+    #[test]
+    fn levels_iterate_from_the_top_of_book_whatever_the_arrival_order() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(98.0, 3.0), entry(100.0, 1.0), entry(99.0, 2.0)],
+            vec![entry(103.0, 3.0), entry(101.0, 1.0), entry(102.0, 2.0)],
+        ));
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(100.5, 4.0)],
+            vec![entry(100.75, 5.0)],
+        ));
+
+        assert_eq!(orderbook.iter_bids().next(), Some(entry(100.5, 4.0)));
+        assert_eq!(orderbook.iter_asks().next(), Some(entry(100.75, 5.0)));
+        assert_eq!(orderbook.iter_bids().next(), orderbook.best_bid());
+        assert_eq!(orderbook.iter_asks().next(), orderbook.best_ask());
+        let bids: Vec<Decimal> = orderbook.iter_bids().map(|level| level.price).collect();
+        let asks: Vec<Decimal> = orderbook.iter_asks().map(|level| level.price).collect();
+        assert!(bids.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(asks.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            orderbook.iter_side(Side::Ask).next(),
+            Some(entry(100.75, 5.0))
+        );
+        assert_eq!(Orderbook::new("ETH/USD").iter_bids().next(), None);
+    }
+
+    #[test]
+    fn duplicate_snapshots_leave_the_book_alone() {
+        let bids = vec![entry(100.0, 1.0), entry(99.0, 2.0)];
+        let asks = vec![entry(101.0, 1.0)];
+        let mut books = HashMap::from([(String::from("BTC/USD"), Orderbook::new("BTC/USD"))]);
+        assert_eq!(
+            route(
+                &mut books,
+                vec![snapshot("BTC/USD", bids.clone(), asks.clone())]
+            ),
+            vec!["BTC/USD"]
+        );
+        let orderbook = &books["BTC/USD"];
+        let dumped = orderbook.to_snapshot();
+        assert!(orderbook.is_same_as(&dumped));
+
+        let mut repeated = snapshot("BTC/USD", bids, asks.clone());
+        repeated.timestamp = Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        repeated.checksum = Some(books["BTC/USD"].checksum().wrapping_add(1));
+        let kept = logged(log::Level::Debug, || {
+            let updated = route(&mut books, vec![repeated.clone()]);
+            assert!(updated.is_empty());
+        });
+
+        assert_eq!(
+            kept,
+            vec!["Keeping the levels of BTC/USD for a snapshot identical to the book"]
+        );
+        assert_eq!(books["BTC/USD"].last_update(), repeated.timestamp);
+        assert!(books["BTC/USD"].checksum_failed());
+        assert!(books["BTC/USD"].is_same_as(&dumped));
+
+        let changed = snapshot("BTC/USD", vec![entry(100.0, 1.0)], asks);
+        assert_eq!(route(&mut books, vec![changed]), vec!["BTC/USD"]);
+        assert!(!books["BTC/USD"].is_same_as(&dumped));
+        assert_eq!(books["BTC/USD"].bid_levels(), 1);
+    }
+
+    #[test]
+    fn validate_reports_each_broken_invariant() {
+        let healthy = || {
+            let mut orderbook = Orderbook::new("BTC/USD").with_max_depth(3);
+            orderbook.evaluate(&snapshot(
+                "BTC/USD",
+                vec![entry(100.0, 1.0), entry(99.0, 2.0)],
+                vec![entry(101.0, 1.0), entry(102.0, 2.0)],
+            ));
+            orderbook
+        };
+        assert_eq!(healthy().validate(), Ok(()));
+        assert_eq!(Orderbook::new("BTC/USD").validate(), Ok(()));
+
+        let mut crossed = healthy();
+        crossed.evaluate(&update("BTC/USD", vec![entry(101.5, 1.0)], vec![]));
+        assert_eq!(
+            crossed.validate(),
+            Err(BookError::Crossed {
+                bid: decimal(101.5),
+                ask: decimal(101.0),
+            })
+        );
+
+        let mut empty_level = healthy();
+        empty_level.asks.insert(decimal(103.0), Decimal::ZERO);
+        assert_eq!(
+            empty_level.validate(),
+            Err(BookError::NonPositiveQty {
+                side: Side::Ask,
+                price: decimal(103.0),
+                qty: Decimal::ZERO,
+            })
+        );
+
+        let mut negative_price = healthy();
+        negative_price
+            .bids
+            .insert(Reverse(decimal(-1.0)), decimal(1.0));
+        assert_eq!(
+            negative_price.validate(),
+            Err(BookError::NonPositivePrice {
+                side: Side::Bid,
+                price: decimal(-1.0),
+            })
+        );
+
+        let mut too_deep = healthy();
+        for price in [98.0, 97.0] {
+            too_deep.bids.insert(Reverse(decimal(price)), decimal(1.0));
+        }
+        let error = too_deep.validate().unwrap_err();
+        assert_eq!(
+            error,
+            BookError::TooDeep {
+                side: Side::Bid,
+                levels: 4,
+                max_depth: 3,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "4 Bid levels exceed the maximum depth of 3"
+        );
+    }
+
+    #[test]
+    fn change_callbacks_hear_about_every_changing_update() {
+        let diffs = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut orderbook = Orderbook::new("BTC/USD");
+        let seen = std::sync::Arc::clone(&diffs);
+        orderbook.on_change(Box::new(move |diff: &BookDiff| {
+            seen.lock().unwrap().push(diff.clone())
+        }));
+
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0)],
+            vec![entry(101.0, 1.0)],
+        ));
+        let applied = orderbook.apply(update("BTC/USD", vec![entry(100.0, 2.0)], vec![]));
+        // neither a repeated quantity nor a copy of the book reports anything
+        orderbook.evaluate(&update("BTC/USD", vec![entry(100.0, 2.0)], vec![]));
+        orderbook
+            .clone()
+            .evaluate(&update("BTC/USD", vec![], vec![entry(101.0, 0.0)]));
+
+        let diffs = diffs.lock().unwrap();
+        assert_eq!(diffs.len(), 2);
+        assert_eq!(diffs[0].bids.added, vec![entry(100.0, 1.0)]);
+        assert_eq!(diffs[0].asks.added, vec![entry(101.0, 1.0)]);
+        assert_eq!(diffs[1], applied);
+        assert_eq!(
+            applied.bids,
+            SideDiff {
+                added: vec![],
+                modified: vec![entry(100.0, 2.0)],
+                removed: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn apply_reports_inserted_changed_and_deleted_levels() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        let diff = orderbook.apply(snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 2.0)],
+            vec![entry(101.0, 1.0)],
+        ));
+        assert_eq!(diff.bids.added, vec![entry(100.0, 1.0), entry(99.0, 2.0)]);
+        assert_eq!(diff.asks.added, vec![entry(101.0, 1.0)]);
+
+        let diff = orderbook.apply(update(
+            "BTC/USD",
+            vec![entry(99.5, 3.0), entry(99.0, 0.0)],
+            vec![entry(101.0, 0.5)],
+        ));
+        assert_eq!(
+            diff,
+            BookDiff {
+                bids: SideDiff {
+                    added: vec![entry(99.5, 3.0)],
+                    modified: vec![],
+                    removed: vec![entry(99.0, 2.0)],
+                },
+                asks: SideDiff {
+                    added: vec![],
+                    modified: vec![entry(101.0, 0.5)],
+                    removed: vec![],
+                },
+            }
+        );
+
+        // deleting a missing level or repeating a quantity changes nothing
+        let diff = orderbook.apply(update(
+            "BTC/USD",
+            vec![entry(98.0, 0.0)],
+            vec![entry(101.0, 0.5)],
+        ));
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn apply_reports_levels_a_snapshot_or_max_depth_removes() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_max_depth(2);
+        orderbook.apply(snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 2.0)],
+            vec![entry(101.0, 1.0)],
+        ));
+
+        let diff = orderbook.apply(update("BTC/USD", vec![entry(100.5, 1.0)], vec![]));
+        assert_eq!(diff.bids.added, vec![entry(100.5, 1.0)]);
+        assert_eq!(diff.bids.removed, vec![entry(99.0, 2.0)]);
+
+        let diff = orderbook.apply(snapshot("BTC/USD", vec![entry(100.5, 2.0)], vec![]));
+        assert_eq!(diff.bids.modified, vec![entry(100.5, 2.0)]);
+        assert_eq!(diff.bids.removed, vec![entry(100.0, 1.0)]);
+        assert_eq!(diff.asks.removed, vec![entry(101.0, 1.0)]);
+    }
+
+    #[test]
+    fn book_is_pruned_to_max_depth() {
+        let bids: Vec<PriceLevel> = (0..50).map(|i| entry(1000.0 - i as f64, 1.0)).collect();
+        let asks: Vec<PriceLevel> = (0..50).map(|i| entry(1001.0 + i as f64, 1.0)).collect();
+        let mut orderbook = Orderbook::new("BTC/USD").with_max_depth(25);
+        orderbook.evaluate(&snapshot("BTC/USD", bids, asks));
+
+        assert_eq!(orderbook.iter_bids().count(), 25);
+        assert_eq!(orderbook.iter_asks().count(), 25);
+        assert_eq!(orderbook.iter_bids().last().unwrap().price, decimal(976.0));
+        assert_eq!(orderbook.iter_asks().last().unwrap().price, decimal(1025.0));
+
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(1000.5, 1.0)],
+            vec![entry(1000.9, 1.0)],
+        ));
+
+        assert_eq!(orderbook.iter_bids().count(), 25);
+        assert_eq!(orderbook.iter_asks().count(), 25);
+        assert_eq!(orderbook.best_bid().unwrap().price, decimal(1000.5));
+        assert_eq!(orderbook.best_ask().unwrap().price, decimal(1000.9));
+    }
+
+    #[test]
+    fn checksums_cover_ten_levels_whatever_the_subscribed_depth() {
+        let bids: Vec<PriceLevel> = (0..12).map(|i| entry(1000.0 - i as f64, 1.0)).collect();
+        let asks: Vec<PriceLevel> = (0..12).map(|i| entry(1001.0 + i as f64, 1.0)).collect();
+        let top = |levels: &[PriceLevel], n: usize| levels[..n].to_vec();
+
+        let mut shallow = Orderbook::new("BTC/USD").with_max_depth(5);
+        shallow.evaluate(&snapshot("BTC/USD", bids.clone(), asks.clone()));
+        let mut five = Orderbook::new("BTC/USD");
+        five.evaluate(&snapshot("BTC/USD", top(&bids, 5), top(&asks, 5)));
+        assert_eq!(shallow.subscribed_depth(), Some(5));
+        assert_eq!(shallow.iter_bids().count(), 5);
+        assert_eq!(shallow.checksum(), five.checksum());
+
+        let mut deep = Orderbook::new("BTC/USD").with_max_depth(25);
+        deep.evaluate(&snapshot("BTC/USD", bids.clone(), asks.clone()));
+        let mut ten = Orderbook::new("BTC/USD");
+        ten.evaluate(&snapshot("BTC/USD", top(&bids, 10), top(&asks, 10)));
+        assert_eq!(deep.iter_bids().count(), 12);
+        assert_eq!(deep.checksum(), ten.checksum());
+        assert_ne!(deep.checksum(), shallow.checksum());
+        assert_eq!(Orderbook::new("BTC/USD").subscribed_depth(), None);
+    }
+
+    #[test]
+    fn tick_size_matches_prices_within_half_a_tick() {
+        let initial = snapshot("BTC/USD", vec![entry(100.0, 1.0)], vec![entry(101.0, 1.0)]);
+        let nearby = update(
+            "BTC/USD",
+            vec![entry(100.00000002, 0.0)],
+            vec![entry(100.99999998, 3.0)],
+        );
+
+        let mut fine = Orderbook::new("BTC/USD");
+        fine.evaluate(&initial);
+        fine.evaluate(&nearby);
+        assert_eq!(fine.iter_bids().count(), 1);
+        assert_eq!(fine.iter_asks().count(), 2);
+
+        let mut coarse = Orderbook::new("BTC/USD").with_tick_size(decimal(0.5));
+        coarse.evaluate(&initial);
+        coarse.evaluate(&nearby);
+        assert_eq!(coarse.iter_bids().count(), 0);
+        assert_eq!(
+            coarse.iter_asks().collect::<Vec<_>>(),
+            vec![PriceLevel {
+                price: decimal(101.0),
+                qty: decimal(3.0)
+            }]
+        );
+    }
+
+    #[test]
+    fn qty_epsilon_controls_deletion_threshold() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_qty_epsilon(decimal(0.001));
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 1.0)],
+            vec![],
+        ));
+        orderbook.evaluate(&update("BTC/USD", vec![entry(100.0, 0.0005)], vec![]));
+
+        assert_eq!(orderbook.best_bid().unwrap().price, decimal(99.0));
+    }
+
+    #[test]
+    fn only_a_zero_quantity_deletes_by_default() {
+        let tiny: Decimal = "0.00000001".parse().unwrap();
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 1.0)],
+            vec![entry(101.0, 1.0), entry(102.0, 1.0)],
+        ));
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![PriceLevel {
+                price: decimal(100.0),
+                qty: tiny,
+            }],
+            vec![PriceLevel {
+                price: decimal(101.0),
+                qty: "0.00000000".parse().unwrap(),
+            }],
+        ));
+
+        assert_eq!(
+            orderbook.best_bid(),
+            Some(PriceLevel {
+                price: decimal(100.0),
+                qty: tiny
+            })
+        );
+        assert_eq!(orderbook.best_ask().unwrap().price, decimal(102.0));
+
+        orderbook.evaluate(&update("BTC/USD", vec![entry(100.0, 0.0)], vec![]));
+        assert_eq!(orderbook.best_bid().unwrap().price, decimal(99.0));
+    }
+
+    #[test]
+    fn malformed_levels_are_skipped() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(-5.0, 1.0)],
+            vec![entry(101.0, 1.0), entry(0.0, 2.0)],
+        ));
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(100.0, -1.0), entry(100.5, -2.0)],
+            vec![entry(101.0, -0.5)],
+        ));
+
+        assert_eq!(
+            orderbook.iter_bids().collect::<Vec<_>>(),
+            vec![entry(100.0, 1.0)]
+        );
+        assert_eq!(
+            orderbook.iter_asks().collect::<Vec<_>>(),
+            vec![entry(101.0, 1.0)]
+        );
+        assert!(!orderbook.is_crossed());
+    }
+
+    #[test]
+    fn entries_at_a_held_price_replace_its_quantity() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(100.0, 1.0), entry(99.0, 1.0)],
+            vec![entry(101.0, 1.0)],
+        ));
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(100.0, 3.0)],
+            vec![entry(101.0, 2.0), entry(101.0, 0.5)],
+        ));
+
+        assert_eq!(
+            orderbook.iter_bids().collect::<Vec<_>>(),
+            vec![entry(100.0, 3.0), entry(99.0, 1.0)]
+        );
+        assert_eq!(
+            orderbook.iter_asks().collect::<Vec<_>>(),
+            vec![entry(101.0, 0.5)]
+        );
+    }
+
+    #[test]
+    fn levels_are_matched_by_exact_decimal_price() {
+        assert_ne!(0.1 + 0.2, 0.3);
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![],
+            vec![entry(0.3, 1.0), entry(0.30000001, 2.0)],
+        ));
+
+        let deletion = PriceLevel {
+            price: decimal(0.1) + decimal(0.2),
+            qty: Decimal::ZERO,
+        };
+        orderbook.evaluate(&update("BTC/USD", vec![], vec![deletion]));
+
+        assert_eq!(
+            orderbook.best_ask(),
+            Some(PriceLevel {
+                price: decimal(0.30000001),
+                qty: decimal(2.0)
+            })
+        );
+    }
+
+    #[test]
+    fn snapshot_serializes_levels_as_decimal_string_pairs() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        let precise = PriceLevel {
+            price: "43209.123456789012345".parse().unwrap(),
+            qty: "1.000000000000000001".parse().unwrap(),
+        };
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(43210.1, 0.5), precise],
+            vec![entry(43211.4, 0.25)],
+        ));
+
+        let json = serde_json::to_value(orderbook.to_snapshot()).unwrap();
+
+        assert_eq!(json["symbol"], "BTC/USD");
+        assert_eq!(
+            json["bids"],
+            serde_json::json!([
+                ["43210.1", "0.5"],
+                ["43209.123456789012345", "1.000000000000000001"]
+            ])
+        );
+        assert_eq!(json["asks"], serde_json::json!([["43211.4", "0.25"]]));
+        assert!(json["ts"].as_u64().unwrap() > 0);
+        let numbers: PriceLevel = serde_json::from_str("[43211.4, 0.25]").unwrap();
+        assert_eq!(numbers, entry(43211.4, 0.25));
+    }
+
+    #[test]
+    fn dump_contains_every_level() {
+        let bids: Vec<PriceLevel> = (0..30).map(|i| entry(100.0 - i as f64, 1.0)).collect();
+        let asks: Vec<PriceLevel> = (0..30).map(|i| entry(101.0 + i as f64, 1.0)).collect();
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot("BTC/USD", bids, asks));
+
+        let mut dump = Vec::new();
+        orderbook.dump_to_writer(&mut dump).unwrap();
+        let dumped: BookSnapshot = serde_json::from_slice(&dump).unwrap();
+
+        assert_eq!(dumped.symbol, "BTC/USD");
+        assert_eq!(dumped.bids, orderbook.iter_bids().collect::<Vec<_>>());
+        assert_eq!(dumped.asks.len(), 30);
+        assert_eq!(dumped.asks.last(), Some(&entry(130.0, 1.0)));
+    }
+
+    #[test]
+    fn checksum_matches_kraken_format() {
+        let mut orderbook = Orderbook::new("BTC/USD").with_precision(1, 8);
+        orderbook.evaluate(&snapshot(
+            "BTC/USD",
+            vec![entry(45283.5, 0.1), entry(45281.0, 2.0)],
+            vec![entry(45285.2, 0.001), entry(45286.4, 1.5)],
+        ));
+
+        assert_eq!(orderbook.checksum(), 3630198917);
+    }
+
+    #[test]
+    fn level_changes_are_logged_at_debug_level() {
+        let mut orderbook = Orderbook::new("BTC/USD");
+        let logged = logged(log::Level::Debug, || {
+            orderbook.evaluate(&snapshot(
+                "BTC/USD",
+                vec![entry(100.0, 1.0), entry(99.0, 2.0)],
+                vec![entry(101.0, 1.0)],
+            ));
+            orderbook.evaluate(&update(
+                "BTC/USD",
+                vec![entry(100.0, 0.5), entry(99.0, 0.0), entry(98.0, 0.0)],
+                vec![entry(102.0, 3.0)],
+            ));
+        });
+
+        assert_eq!(logged.len(), 6, "{:?}", logged);
+        assert_eq!(
+            logged[3],
+            "level update symbol=BTC/USD side=Bid price=100 old_qty=1 new_qty=0.5"
+        );
+        assert_eq!(
+            logged[4],
+            "level delete symbol=BTC/USD side=Bid price=99 old_qty=2 new_qty=0"
+        );
+        assert_eq!(
+            logged[5],
+            "level insert symbol=BTC/USD side=Ask price=102 old_qty=0 new_qty=3"
+        );
+    }
+
+    #[test]
+    fn consecutive_checksum_failures_reset_on_a_match() {
+        fn checked(orderbook: &mut Orderbook, checksum: Option<u32>) -> u32 {
+            let mut entry = update("BTC/USD", vec![entry(45283.5, 0.1)], vec![]);
+            entry.checksum = checksum;
+            orderbook.evaluate(&entry);
+            orderbook.consecutive_checksum_failures()
+        }
+        let mut orderbook = Orderbook::new("BTC/USD").with_precision(1, 8);
+
+        assert_eq!(
+            checked(&mut orderbook, Some(0)),
+            0,
+            "ignored before the snapshot"
+        );
+        orderbook.evaluate(&snapshot("BTC/USD", vec![], vec![]));
+        let failures: Vec<u32> = [Some(0), Some(1), None, Some(2)]
+            .into_iter()
+            .map(|checksum| checked(&mut orderbook, checksum))
+            .collect();
+        assert_eq!(failures, vec![1, 2, 2, 3]);
+        assert_eq!(orderbook.last_checksum_matched(), Some(false));
+        let matching = orderbook.checksum();
+        assert_eq!(checked(&mut orderbook, Some(matching)), 0);
+        assert_eq!(orderbook.last_checksum_matched(), Some(true));
+    }
+
+    #[test]
+    fn batched_entries_end_in_the_same_book_with_the_last_checksum_checked() {
+        let mut entries = vec![
+            snapshot(
+                "BTC/USD",
+                vec![entry(100.0, 1.0), entry(99.5, 2.0)],
+                vec![entry(100.5, 1.0), entry(101.0, 3.0)],
+            ),
+            update("BTC/USD", vec![entry(100.2, 0.5)], vec![entry(100.5, 0.0)]),
+            snapshot(
+                "ETH/USD",
+                vec![entry(1650.0, 4.0)],
+                vec![entry(1651.0, 2.0)],
+            ),
+            update("BTC/USD", vec![entry(99.5, 0.0)], vec![entry(100.7, 1.5)]),
+            update("ETH/USD", vec![entry(1650.5, 1.0)], vec![]),
+        ];
+        let mut reference = HashMap::from([
+            (String::from("BTC/USD"), Orderbook::new("BTC/USD")),
+            (String::from("ETH/USD"), Orderbook::new("ETH/USD")),
+        ]);
+        let mut one_by_one = reference.clone();
+        for entry in &mut entries {
+            route(&mut reference, vec![entry.clone()]);
+            entry.checksum = Some(reference[&entry.symbol].checksum());
+        }
+        for entry in &entries {
+            route(&mut one_by_one, vec![entry.clone()]);
+        }
+        // checksums in the middle of a batch are never checked, even a wrong one
+        entries[1].checksum = Some(0);
+        let mut batched = HashMap::from([
+            (String::from("BTC/USD"), Orderbook::new("BTC/USD")),
+            (String::from("ETH/USD"), Orderbook::new("ETH/USD")),
+        ]);
+
+        keep_last_checksums(&mut entries);
+        route(&mut batched, entries.clone());
+
+        assert_eq!(entries[1].checksum, None);
+        assert!(entries[3].checksum.is_some() && entries[4].checksum.is_some());
+        for (symbol, orderbook) in &batched {
+            assert_book_eq(orderbook, &one_by_one[symbol].to_snapshot());
+            assert_eq!(orderbook.last_checksum_matched(), Some(true), "{}", symbol);
+            assert_eq!(orderbook.consecutive_checksum_failures(), 0);
+        }
+    }
+
+    #[test]
+    fn checksum_only_covers_top_ten_levels() {
+        let bids: Vec<PriceLevel> = (0..12).map(|i| entry(100.0 - i as f64, 1.0)).collect();
+        let asks: Vec<PriceLevel> = (0..12).map(|i| entry(101.0 + i as f64, 1.0)).collect();
+        let mut orderbook = Orderbook::new("BTC/USD");
+        orderbook.evaluate(&snapshot("BTC/USD", bids, asks));
+        let checksum = orderbook.checksum();
+
+        orderbook.evaluate(&update(
+            "BTC/USD",
+            vec![entry(80.0, 3.0)],
+            vec![entry(120.0, 3.0)],
+        ));
+
+        assert_eq!(orderbook.checksum(), checksum);
+    }
+}
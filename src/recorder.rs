@@ -0,0 +1,145 @@
+use crate::clock::{Clock, SystemClock};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// how long recorded messages may sit in the buffer before being written out
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Appends raw feed messages as `<receive unix millis> <message>` lines.
+pub struct Recorder<W: Write> {
+    writer: W,
+    last_flush: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+/// File a [`Recorder`] appends to, gzip compressed or not.
+pub enum RecordingFile {
+    Plain(BufWriter<File>),
+    /// each run appends a gzip member of its own, which gzip readers concatenate
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Write for RecordingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RecordingFile::Plain(writer) => writer.write(buf),
+            RecordingFile::Gzip(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RecordingFile::Plain(writer) => writer.flush(),
+            RecordingFile::Gzip(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Recorder<RecordingFile> {
+    /// Opens `path` for appending, creating it if needed. The messages are gzip compressed
+    /// with `gzip` or when the path ends in `.gz`.
+    pub fn create(path: &Path, gzip: bool) -> io::Result<Self> {
+        let file = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+        let file = if gzip || path.extension().is_some_and(|extension| extension == "gz") {
+            RecordingFile::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            RecordingFile::Plain(file)
+        };
+        Ok(Recorder::new(file))
+    }
+
+    /// Writes out what is buffered and, for gzip, the end of the stream, without which the
+    /// file can't be fully read back. Nothing can be recorded afterwards.
+    pub fn finish(self) -> io::Result<()> {
+        match self.writer {
+            RecordingFile::Plain(mut writer) => writer.flush(),
+            RecordingFile::Gzip(writer) => writer.finish()?.flush(),
+        }
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(writer: W) -> Self {
+        Recorder {
+            writer,
+            last_flush: Instant::now(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Takes the receive times and the flush interval from `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.last_flush = clock.instant();
+        self.clock = clock;
+        self
+    }
+
+    pub fn record(&mut self, message: &str) -> io::Result<()> {
+        let millis = self
+            .clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis());
+        writeln!(self.writer, "{} {}", millis, message)?;
+        if self.clock.instant() - self.last_flush >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = self.clock.instant();
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn records_one_timestamped_line_per_message() {
+        let mut recorder = Recorder::new(Vec::new());
+        recorder.record(r#"{"channel":"heartbeat"}"#).unwrap();
+        recorder.record(r#"{"channel":"book"}"#).unwrap();
+
+        let output = String::from_utf8(recorder.into_inner()).unwrap();
+        let lines: Vec<(&str, &str)> = output
+            .lines()
+            .map(|line| line.split_once(' ').unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].0.parse::<u64>().is_ok());
+        assert_eq!(lines[0].1, r#"{"channel":"heartbeat"}"#);
+        assert_eq!(lines[1].1, r#"{"channel":"book"}"#);
+    }
+
+    #[test]
+    fn receive_times_come_from_the_clock() {
+        let clock = Arc::new(MockClock::new(
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+        ));
+        let mut recorder = Recorder::new(Vec::new()).with_clock(clock.clone());
+        recorder.record(r#"{"channel":"heartbeat"}"#).unwrap();
+        clock.advance(Duration::from_millis(5));
+        recorder.record(r#"{"channel":"book"}"#).unwrap();
+
+        let output = String::from_utf8(recorder.into_inner()).unwrap();
+        let times: Vec<&str> = output
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(times, ["1700000000123", "1700000000128"]);
+    }
+}
@@ -0,0 +1,152 @@
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// first two bytes of every gzip member
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Opens a recording for reading line by line, decompressing it if it was written with
+/// gzip whatever its file name.
+pub fn open_recording(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if file.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+/// One line of a recording written by [`crate::Recorder`].
+#[derive(Debug, PartialEq)]
+pub struct RecordedMessage<'a> {
+    /// receive time in milliseconds since the unix epoch, if the line carries one
+    pub received_at: Option<u64>,
+    pub text: &'a str,
+}
+
+/// Splits a recorded line into its optional timestamp prefix and the raw message.
+/// Blank lines yield `None`; lines starting with `{` are taken as bare messages.
+pub fn parse_recorded_line(line: &str) -> Option<RecordedMessage<'_>> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    if line.starts_with('{') {
+        return Some(RecordedMessage {
+            received_at: None,
+            text: line,
+        });
+    }
+    match line.split_once(' ') {
+        Some((timestamp, text)) => Some(RecordedMessage {
+            received_at: timestamp.parse().ok(),
+            text: text.trim_start(),
+        }),
+        None => Some(RecordedMessage {
+            received_at: None,
+            text: line,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::{ExchangeFeed, FeedEvent};
+    use crate::kraken::KrakenFeed;
+    use crate::orderbook::{route, Orderbook};
+    use crate::recorder::Recorder;
+    use std::collections::HashMap;
+
+    /// Kraken BTC/USD session at depth 10 recorded with `--record`: a snapshot followed by
+    /// updates that insert, change and delete levels and push levels beyond the depth.
+    const KRAKEN_SESSION: &str = include_str!("../testdata/kraken_btc_usd_session.log");
+
+    #[test]
+    fn parses_timestamped_and_bare_lines() {
+        assert_eq!(
+            parse_recorded_line(r#"1700000000123 {"channel":"book"}"#),
+            Some(RecordedMessage {
+                received_at: Some(1700000000123),
+                text: r#"{"channel":"book"}"#,
+            })
+        );
+        assert_eq!(
+            parse_recorded_line(r#"{"channel":"book"}"#),
+            Some(RecordedMessage {
+                received_at: None,
+                text: r#"{"channel":"book"}"#,
+            })
+        );
+        assert_eq!(parse_recorded_line("   "), None);
+    }
+
+    #[test]
+    fn gzip_recordings_replay_like_plain_ones() {
+        let messages = [r#"{"channel":"heartbeat"}"#, r#"{"channel":"book"}"#];
+        for (name, gzip) in [
+            ("plain.log", false),
+            ("flag.log", true),
+            ("extension.log.gz", false),
+        ] {
+            let path = std::env::temp_dir().join(format!("hft-orderbook-recording-{}", name));
+            let _ = std::fs::remove_file(&path);
+            // two runs appending to the same file
+            for message in messages {
+                let mut recorder = Recorder::create(&path, gzip).unwrap();
+                recorder.record(message).unwrap();
+                recorder.finish().unwrap();
+            }
+
+            let compressed = std::fs::read(&path).unwrap().starts_with(&GZIP_MAGIC);
+            let lines: Vec<String> = open_recording(&path)
+                .unwrap()
+                .lines()
+                .map(Result::unwrap)
+                .collect();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(compressed, name != "plain.log", "{}", name);
+            let texts: Vec<&str> = lines
+                .iter()
+                .map(|line| parse_recorded_line(line).unwrap().text)
+                .collect();
+            assert_eq!(texts, messages, "{}", name);
+        }
+    }
+
+    #[test]
+    fn recorded_kraken_session_matches_every_checksum() {
+        let symbols = vec![String::from("BTC/USD")];
+        let mut feed = KrakenFeed::new(&symbols, 10);
+        let mut books = HashMap::from([(
+            String::from("BTC/USD"),
+            Orderbook::new("BTC/USD")
+                .with_precision(1, 8)
+                .with_max_depth(10),
+        )]);
+
+        let mut checked = 0;
+        for (number, line) in KRAKEN_SESSION.lines().enumerate() {
+            let message = parse_recorded_line(line).unwrap();
+            let Some(FeedEvent::Book(updates)) = feed.parse(message.text) else {
+                continue;
+            };
+            for update in updates {
+                let expected = update
+                    .checksum
+                    .expect("Kraken sends a checksum with every entry");
+                route(&mut books, vec![update]);
+                assert_eq!(
+                    books["BTC/USD"].checksum(),
+                    expected,
+                    "checksum after line {}",
+                    number + 1
+                );
+                checked += 1;
+            }
+        }
+        assert_eq!(checked, 37);
+    }
+}
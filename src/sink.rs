@@ -0,0 +1,468 @@
+use crate::{
+    bucketed, new_book, print_bars, print_csv_rows, print_snapshots, print_trades, render,
+    render_ladders, write_sinks, Action, Args, OutputMode, CSV_HEADER,
+};
+use hft_orderbook::{BookSnapshot, Clock, Metrics, Orderbook, PriceLevel, Trade};
+use log::warn;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Destination of the books, handed every message that changed some of them.
+pub trait OutputSink {
+    /// Writes the sink's view of `books` after the books named in `updated` changed.
+    fn on_update(
+        &mut self,
+        args: &Args,
+        books: &HashMap<String, Orderbook>,
+        updated: &[String],
+    ) -> io::Result<()>;
+
+    /// Writes trades executed since the last update, if the sink shows trades at all.
+    fn on_trades(&mut self, _trades: &[Trade]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Builds the sink writing `output` to `writer`.
+pub fn output_sink<W: Write + 'static>(
+    output: OutputMode,
+    writer: W,
+) -> io::Result<Box<dyn OutputSink>> {
+    Ok(match output {
+        OutputMode::Pretty => Box::new(PrettySink::new(writer)),
+        OutputMode::Json => Box::new(JsonSink::new(writer)),
+        OutputMode::Ladder => Box::new(LadderSink::new(writer)),
+        OutputMode::Csv => Box::new(CsvSink::new(writer)?),
+    })
+}
+
+/// Remembers when the books were last redrawn by an in-place output.
+#[derive(Default)]
+struct Throttle {
+    last_render: Option<Instant>,
+}
+
+impl Throttle {
+    /// Whether `interval` passed on `clock` since the last redraw, counting this as one if
+    /// so.
+    fn render_due(&mut self, clock: &dyn Clock, interval: Duration) -> bool {
+        let now = clock.instant();
+        if self
+            .last_render
+            .is_some_and(|last_render| now - last_render < interval)
+        {
+            return false;
+        }
+        self.last_render = Some(now);
+        true
+    }
+}
+
+/// Top of book per symbol, redrawn in place at most once per `--render-interval`.
+pub struct PrettySink<W: Write> {
+    writer: W,
+    throttle: Throttle,
+    /// mid of each symbol at the last redraw, to show which way it moved since
+    mids: HashMap<String, Decimal>,
+}
+
+impl<W: Write> PrettySink<W> {
+    pub fn new(writer: W) -> Self {
+        PrettySink {
+            writer,
+            throttle: Throttle::default(),
+            mids: HashMap::new(),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for PrettySink<W> {
+    fn on_update(
+        &mut self,
+        args: &Args,
+        books: &HashMap<String, Orderbook>,
+        updated: &[String],
+    ) -> io::Result<()> {
+        print_bars(&mut self.writer, updated, books, OutputMode::Pretty)?;
+        if self.throttle.render_due(&*args.clock, args.render_interval) {
+            render(&mut self.writer, books, args, &mut self.mids)?;
+        }
+        Ok(())
+    }
+
+    fn on_trades(&mut self, trades: &[Trade]) -> io::Result<()> {
+        print_trades(&mut self.writer, trades, OutputMode::Pretty)
+    }
+}
+
+/// Depth ladder per symbol, redrawn in place at most once per `--render-interval`.
+pub struct LadderSink<W: Write> {
+    writer: W,
+    throttle: Throttle,
+}
+
+impl<W: Write> LadderSink<W> {
+    pub fn new(writer: W) -> Self {
+        LadderSink {
+            writer,
+            throttle: Throttle::default(),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for LadderSink<W> {
+    fn on_update(
+        &mut self,
+        args: &Args,
+        books: &HashMap<String, Orderbook>,
+        updated: &[String],
+    ) -> io::Result<()> {
+        print_bars(&mut self.writer, updated, books, OutputMode::Ladder)?;
+        if !self.throttle.render_due(&*args.clock, args.render_interval) {
+            return Ok(());
+        }
+        let bucketed_books;
+        let shown = match args.bucket {
+            Some(bucket) => {
+                bucketed_books = bucketed(books, bucket);
+                &bucketed_books
+            }
+            None => books,
+        };
+        render_ladders(
+            &mut self.writer,
+            &args.symbols,
+            shown,
+            args.ladder_levels,
+            &args.palette,
+            args.display_symbol_format,
+        )
+    }
+
+    fn on_trades(&mut self, trades: &[Trade]) -> io::Result<()> {
+        print_trades(&mut self.writer, trades, OutputMode::Ladder)
+    }
+}
+
+/// One JSON snapshot per updated book and line, written for every update.
+pub struct JsonSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonSink { writer }
+    }
+}
+
+impl<W: Write> OutputSink for JsonSink<W> {
+    fn on_update(
+        &mut self,
+        args: &Args,
+        books: &HashMap<String, Orderbook>,
+        updated: &[String],
+    ) -> io::Result<()> {
+        print_bars(&mut self.writer, updated, books, OutputMode::Json)?;
+        match args.bucket {
+            Some(bucket) => print_snapshots(&mut self.writer, updated, &bucketed(books, bucket)),
+            None => print_snapshots(&mut self.writer, updated, books),
+        }
+    }
+
+    fn on_trades(&mut self, trades: &[Trade]) -> io::Result<()> {
+        print_trades(&mut self.writer, trades, OutputMode::Json)
+    }
+}
+
+/// One row of top of book values per updated book, below a header row.
+pub struct CsvSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Starts the output with the header row.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{}", CSV_HEADER.join(","))?;
+        Ok(CsvSink { writer })
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn on_update(
+        &mut self,
+        _args: &Args,
+        books: &HashMap<String, Orderbook>,
+        updated: &[String],
+    ) -> io::Result<()> {
+        print_csv_rows(&mut self.writer, updated, books)?;
+        self.writer.flush()
+    }
+}
+
+/// Keeps the top of book served by `--http-addr` up to date.
+#[cfg(feature = "http")]
+pub struct EndpointSink {
+    endpoint: std::sync::Arc<hft_orderbook::BookEndpoint>,
+}
+
+#[cfg(feature = "http")]
+impl EndpointSink {
+    pub fn new(endpoint: std::sync::Arc<hft_orderbook::BookEndpoint>) -> Self {
+        EndpointSink { endpoint }
+    }
+}
+
+#[cfg(feature = "http")]
+impl OutputSink for EndpointSink {
+    fn on_update(
+        &mut self,
+        _args: &Args,
+        books: &HashMap<String, Orderbook>,
+        updated: &[String],
+    ) -> io::Result<()> {
+        self.endpoint.observe(books, updated);
+        Ok(())
+    }
+}
+
+/// Hands `sinks` only the books whose best bid or ask, price or quantity, changed since
+/// they were last handed on; trades all go through.
+pub struct BboChangeSink {
+    sinks: Vec<Box<dyn OutputSink>>,
+    /// best bid and ask of each book as last handed on
+    last: HashMap<String, (Option<PriceLevel>, Option<PriceLevel>)>,
+}
+
+impl BboChangeSink {
+    pub fn new(sinks: Vec<Box<dyn OutputSink>>) -> Self {
+        BboChangeSink {
+            sinks,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Whether `orderbook`'s top differs from the one last handed on, remembering it if so.
+    fn top_changed(&mut self, orderbook: &Orderbook) -> bool {
+        let top = (orderbook.best_bid(), orderbook.best_ask());
+        if self.last.get(orderbook.symbol()) == Some(&top) {
+            return false;
+        }
+        self.last.insert(orderbook.symbol().to_string(), top);
+        true
+    }
+}
+
+impl OutputSink for BboChangeSink {
+    fn on_update(
+        &mut self,
+        args: &Args,
+        books: &HashMap<String, Orderbook>,
+        updated: &[String],
+    ) -> io::Result<()> {
+        let changed: Vec<String> = updated
+            .iter()
+            .filter(|symbol| {
+                books
+                    .get(*symbol)
+                    .is_some_and(|orderbook| self.top_changed(orderbook))
+            })
+            .cloned()
+            .collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        match write_sinks(&mut self.sinks, |sink| {
+            sink.on_update(args, books, &changed)
+        }) {
+            Action::Shutdown => Err(io::ErrorKind::BrokenPipe.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn on_trades(&mut self, trades: &[Trade]) -> io::Result<()> {
+        match write_sinks(&mut self.sinks, |sink| sink.on_trades(trades)) {
+            Action::Shutdown => Err(io::ErrorKind::BrokenPipe.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What the feed loop hands to the output thread of a [`QueuedSink`].
+enum Queued {
+    /// the levels of each updated book, in the order they were updated
+    Update(Vec<BookSnapshot>),
+    Trades(Vec<Trade>),
+}
+
+/// Runs sinks on a thread of their own, handing them snapshots of the updated books through
+/// a queue of bounded size so that a slow output never holds up reading the feed. The
+/// thread keeps books of its own rebuilt from the snapshots, so their windows, ranges and
+/// bars follow the states that reached it. Updates arriving while the queue is full are
+/// held back and merged into the next one, so the output skips states rather than falling
+/// behind. Trades can't be skipped like that, so they wait and go out ahead of the next
+/// message that fits.
+pub struct QueuedSink {
+    sender: Option<SyncSender<Queued>>,
+    worker: Option<JoinHandle<()>>,
+    metrics: Option<Arc<Metrics>>,
+    /// snapshots of the update that didn't fit in the queue
+    pending: Vec<BookSnapshot>,
+    /// trades that didn't fit in the queue, oldest first
+    pending_trades: Vec<Trade>,
+    /// updates merged into a later one instead of going out on their own
+    coalesced: u64,
+}
+
+impl QueuedSink {
+    /// Starts the output thread, which builds its sinks with `build` and then waits for
+    /// updates, up to `capacity` of them queued at a time.
+    pub fn spawn(
+        args: Args,
+        capacity: usize,
+        metrics: Option<Arc<Metrics>>,
+        build: impl FnOnce(&Args) -> io::Result<Vec<Box<dyn OutputSink>>> + Send + 'static,
+    ) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let (built, ready) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            // sinks writing to stdout hold its lock, so they are built where they write
+            let mut sinks = match build(&args) {
+                Ok(sinks) => {
+                    let _ = built.send(Ok(()));
+                    sinks
+                }
+                Err(error) => {
+                    let _ = built.send(Err(error));
+                    return;
+                }
+            };
+            let mut books = HashMap::new();
+            for queued in receiver {
+                let action = match queued {
+                    Queued::Update(snapshots) => {
+                        let mut updated = Vec::with_capacity(snapshots.len());
+                        for snapshot in snapshots {
+                            books
+                                .entry(snapshot.symbol.clone())
+                                .or_insert_with(|| new_book(&args, &snapshot.symbol))
+                                .load_snapshot(&snapshot);
+                            updated.push(snapshot.symbol);
+                        }
+                        write_sinks(&mut sinks, |sink| sink.on_update(&args, &books, &updated))
+                    }
+                    Queued::Trades(trades) => {
+                        write_sinks(&mut sinks, |sink| sink.on_trades(&trades))
+                    }
+                };
+                if action == Action::Shutdown {
+                    break;
+                }
+            }
+        });
+        ready
+            .recv()
+            .map_err(|_| io::Error::other("the output thread exited while starting"))??;
+        Ok(QueuedSink {
+            sender: Some(sender),
+            worker: Some(worker),
+            metrics,
+            pending: Vec::new(),
+            pending_trades: Vec::new(),
+            coalesced: 0,
+        })
+    }
+
+    /// Queues `queued`, handing it back if the queue is full. The output thread having
+    /// stopped, which it does once stdout is closed, is reported as a broken pipe.
+    fn send(&mut self, queued: Queued) -> io::Result<Option<Queued>> {
+        let Some(sender) = &self.sender else {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        };
+        match sender.try_send(queued) {
+            Ok(()) => Ok(None),
+            Err(TrySendError::Full(queued)) => Ok(Some(queued)),
+            Err(TrySendError::Disconnected(_)) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// Queues the trades that didn't fit before, keeping them if they still don't.
+    fn send_pending_trades(&mut self) -> io::Result<()> {
+        if self.pending_trades.is_empty() {
+            return Ok(());
+        }
+        let trades = mem::take(&mut self.pending_trades);
+        if let Some(Queued::Trades(trades)) = self.send(Queued::Trades(trades))? {
+            self.pending_trades = trades;
+        }
+        Ok(())
+    }
+
+    /// Counts the held back update as merged into the next one.
+    fn record_coalesced(&mut self) {
+        if self.coalesced == 0 {
+            warn!("Output can't keep up with the feed, skipping updates");
+        }
+        self.coalesced += 1;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_dropped_output();
+        }
+    }
+}
+
+impl OutputSink for QueuedSink {
+    fn on_update(
+        &mut self,
+        _args: &Args,
+        books: &HashMap<String, Orderbook>,
+        updated: &[String],
+    ) -> io::Result<()> {
+        self.send_pending_trades()?;
+        let mut snapshots = mem::take(&mut self.pending);
+        if !snapshots.is_empty() {
+            self.record_coalesced();
+        }
+        for orderbook in updated.iter().filter_map(|symbol| books.get(symbol)) {
+            let snapshot = orderbook.to_snapshot();
+            match snapshots
+                .iter_mut()
+                .find(|pending| pending.symbol == snapshot.symbol)
+            {
+                Some(pending) => *pending = snapshot,
+                None => snapshots.push(snapshot),
+            }
+        }
+        if let Some(Queued::Update(snapshots)) = self.send(Queued::Update(snapshots))? {
+            self.pending = snapshots;
+        }
+        Ok(())
+    }
+
+    fn on_trades(&mut self, trades: &[Trade]) -> io::Result<()> {
+        self.pending_trades.extend_from_slice(trades);
+        self.send_pending_trades()
+    }
+}
+
+impl Drop for QueuedSink {
+    /// Lets the output thread write out what is queued, and what is still pending, before
+    /// returning.
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            if !self.pending_trades.is_empty() {
+                let _ = sender.send(Queued::Trades(mem::take(&mut self.pending_trades)));
+            }
+            if !self.pending.is_empty() {
+                let _ = sender.send(Queued::Update(mem::take(&mut self.pending)));
+            }
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! REST snapshots fetched on a thread of their own, so that a feed waiting for one keeps
+//! parsing the websocket's messages, and retried with a growing delay after failures.
+
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// delay before retrying a failed fetch, doubled on each failure in a row
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// upper bound for the delay between fetches
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The fetch of one book's snapshot: at most one is in flight at a time.
+pub(crate) struct SnapshotFetch<T> {
+    /// result of the fetch in flight, if there is one
+    in_flight: Option<Receiver<Result<T, String>>>,
+    /// earliest time of the next fetch after a failure
+    retry_at: Option<Instant>,
+    backoff: Duration,
+}
+
+impl<T> Default for SnapshotFetch<T> {
+    fn default() -> Self {
+        SnapshotFetch {
+            in_flight: None,
+            retry_at: None,
+            backoff: INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl<T: Send + 'static> SnapshotFetch<T> {
+    /// Runs `fetch` on a thread of its own, unless a fetch is in flight already or the
+    /// delay after the last failure hasn't passed at `now`.
+    pub(crate) fn start(
+        &mut self,
+        now: Instant,
+        fetch: impl FnOnce() -> Result<T, String> + Send + 'static,
+    ) {
+        if self.in_flight.is_some() || self.retry_at.is_some_and(|retry_at| now < retry_at) {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // the feed may have been reset or dropped in the meantime
+            let _ = sender.send(fetch());
+        });
+        self.in_flight = Some(receiver);
+    }
+
+    /// Whether a fetch is in flight.
+    #[cfg(test)]
+    pub(crate) fn is_in_flight(&self) -> bool {
+        self.in_flight.is_some()
+    }
+
+    /// Result of the fetch in flight once it's done.
+    pub(crate) fn poll(&mut self) -> Option<Result<T, String>> {
+        let result = match self.in_flight.as_ref()?.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(String::from("the fetch panicked")),
+        };
+        self.in_flight = None;
+        Some(result)
+    }
+
+    /// Holds off the next fetch after a failed one, or one whose snapshot was of no use,
+    /// for twice as long as after the previous failure.
+    pub(crate) fn failed(&mut self, now: Instant) {
+        self.retry_at = Some(now + self.backoff);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    /// Lets the next fetch start right away.
+    pub(crate) fn succeeded(&mut self) {
+        self.retry_at = None;
+        self.backoff = INITIAL_BACKOFF;
+    }
+
+    /// Blocks until the fetch in flight is done, so that the next [`SnapshotFetch::poll`]
+    /// returns its result.
+    #[cfg(test)]
+    pub(crate) fn wait(&mut self) {
+        if let Some(receiver) = self.in_flight.take() {
+            let result = receiver
+                .recv()
+                .unwrap_or_else(|_| Err(String::from("the fetch panicked")));
+            let (sender, receiver) = mpsc::channel();
+            sender.send(result).unwrap();
+            self.in_flight = Some(receiver);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_hold_off_the_next_fetch_for_longer_each_time() {
+        let mut fetch = SnapshotFetch::default();
+        let now = Instant::now();
+
+        fetch.start(now, || Err::<u32, _>(String::from("timed out")));
+        // one fetch at a time
+        fetch.start(now, || Ok(1));
+        fetch.wait();
+        assert_eq!(fetch.poll(), Some(Err(String::from("timed out"))));
+        assert_eq!(fetch.poll(), None);
+
+        fetch.failed(now);
+        fetch.start(now + Duration::from_millis(999), || Ok(2));
+        assert!(!fetch.is_in_flight());
+        fetch.start(now + INITIAL_BACKOFF, || Err(String::from("timed out")));
+        fetch.wait();
+        assert!(fetch.poll().unwrap().is_err());
+
+        let now = now + INITIAL_BACKOFF;
+        fetch.failed(now);
+        fetch.start(now + INITIAL_BACKOFF, || Ok(3));
+        assert!(!fetch.is_in_flight());
+        fetch.start(now + 2 * INITIAL_BACKOFF, || Ok(3));
+        fetch.wait();
+        assert_eq!(fetch.poll(), Some(Ok(3)));
+
+        fetch.succeeded();
+        fetch.start(now + 2 * INITIAL_BACKOFF, || Ok(4));
+        fetch.wait();
+        assert_eq!(fetch.poll(), Some(Ok(4)));
+    }
+}
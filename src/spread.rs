@@ -0,0 +1,106 @@
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+/// The spreads of the most recent `capacity` updates, for the typical width of a market
+/// rather than its current one; older spreads are dropped as new ones arrive.
+#[derive(Clone, Debug)]
+pub struct SpreadWindow {
+    samples: VecDeque<Decimal>,
+    capacity: usize,
+    /// sum of `samples`, kept up to date so the mean doesn't walk the window
+    total: Decimal,
+}
+
+impl SpreadWindow {
+    pub fn new(capacity: usize) -> Self {
+        SpreadWindow {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            total: Decimal::ZERO,
+        }
+    }
+
+    pub fn record(&mut self, spread: Decimal) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            if let Some(oldest) = self.samples.pop_front() {
+                self.total -= oldest;
+            }
+        }
+        self.samples.push_back(spread);
+        self.total += spread;
+    }
+
+    /// Number of spreads in the window, at most its capacity.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Average spread over the window, `None` while it's empty.
+    pub fn mean(&self) -> Option<Decimal> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some((self.total / Decimal::from(self.samples.len())).normalize())
+    }
+
+    /// Widest spread in the window, `None` while it's empty.
+    pub fn max(&self) -> Option<Decimal> {
+        self.samples.iter().copied().max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(value: &str) -> Decimal {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn statistics_cover_every_spread_while_filling_up() {
+        let mut window = SpreadWindow::new(3);
+        assert_eq!((window.mean(), window.max()), (None, None));
+
+        window.record(dec("0.5"));
+        assert_eq!(
+            (window.mean(), window.max()),
+            (Some(dec("0.5")), Some(dec("0.5")))
+        );
+        window.record(dec("1.5"));
+        window.record(dec("0.1"));
+
+        assert_eq!(window.len(), 3);
+        assert_eq!(window.mean(), Some(dec("0.7")));
+        assert_eq!(window.max(), Some(dec("1.5")));
+    }
+
+    #[test]
+    fn old_spreads_roll_out_of_a_full_window() {
+        let mut window = SpreadWindow::new(2);
+        for spread in ["4", "1", "2"] {
+            window.record(dec(spread));
+        }
+
+        // 4 was pushed out by the two spreads after it
+        assert_eq!(window.len(), 2);
+        assert_eq!(window.mean(), Some(dec("1.5")));
+        assert_eq!(window.max(), Some(dec("2")));
+
+        window.record(dec("0.5"));
+        window.record(dec("0.5"));
+        assert_eq!(window.mean(), Some(dec("0.5")));
+        assert_eq!(window.max(), Some(dec("0.5")));
+
+        let mut empty = SpreadWindow::new(0);
+        empty.record(dec("1"));
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::clock::{Clock, SystemClock};
+use crate::feed::UpdateKind;
+use crate::orderbook::AppliedEntry;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where the feed loop stands with its websocket connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// Counters of the feed loop shown by the status line, kept across reconnections.
+#[derive(Debug)]
+pub struct ConnectionStats {
+    state: ConnectionState,
+    messages: u64,
+    /// whether the last checksum the exchange sent matched the book, `None` before the first
+    last_checksum: Option<bool>,
+    started: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+impl ConnectionStats {
+    pub fn new() -> Self {
+        ConnectionStats::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Stats starting now according to `clock`, which also measures the uptime.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        ConnectionStats {
+            state: ConnectionState::Connecting,
+            messages: 0,
+            last_checksum: None,
+            started: clock.instant(),
+            clock,
+        }
+    }
+
+    pub fn set_state(&mut self, state: ConnectionState) {
+        self.state = state;
+    }
+
+    pub fn record_message(&mut self) {
+        self.messages += 1;
+    }
+
+    pub fn record_checksum(&mut self, matched: bool) {
+        self.last_checksum = Some(matched);
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    pub fn last_checksum(&self) -> Option<bool> {
+        self.last_checksum
+    }
+
+    /// When the stats were created, i.e. when the feed loop started.
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Time since the stats were created, i.e. since the feed loop started.
+    pub fn uptime(&self) -> Duration {
+        self.clock.instant() - self.started
+    }
+
+    /// Formats the stats as a single line, with the uptime in whole seconds.
+    pub fn format_status(&self) -> String {
+        let state = match self.state {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected => "disconnected",
+        };
+        let checksum = match self.last_checksum {
+            Some(true) => "ok",
+            Some(false) => "FAILED",
+            None => "-",
+        };
+        let uptime = Duration::from_secs(self.uptime().as_secs());
+        format!(
+            "{} messages {} checksum {} up {}",
+            state,
+            self.messages,
+            checksum,
+            humantime::format_duration(uptime)
+        )
+    }
+}
+
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        ConnectionStats::new()
+    }
+}
+
+/// Totals of a whole run, summed up on exit. Shared between the feed loop, which counts
+/// the reconnects, and the output observing the updated books.
+#[derive(Debug, Default)]
+pub struct SessionStats {
+    counts: Mutex<SessionCounts>,
+}
+
+/// What [`SessionStats`] counted so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SessionCounts {
+    /// update entries applied to a book
+    pub updates: u64,
+    /// snapshot entries applied to a book
+    pub snapshots: u64,
+    pub reconnects: u64,
+    /// applied entries whose checksum didn't match the book
+    pub checksum_failures: u64,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        SessionStats::default()
+    }
+
+    /// Counts the `applied` entries.
+    pub fn observe(&self, applied: &[AppliedEntry]) {
+        let mut counts = self.counts.lock().unwrap();
+        for applied in applied {
+            match applied.kind {
+                UpdateKind::Snapshot => counts.snapshots += 1,
+                UpdateKind::Update => counts.updates += 1,
+            }
+            if applied.checksum_failed {
+                counts.checksum_failures += 1;
+            }
+        }
+    }
+
+    pub fn record_reconnect(&self) {
+        self.counts.lock().unwrap().reconnects += 1;
+    }
+
+    pub fn counts(&self) -> SessionCounts {
+        *self.counts.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::feed::BookUpdate;
+    use crate::orderbook::{route_entries, Orderbook, PriceLevel};
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+
+    #[test]
+    fn status_line_follows_the_counters() {
+        let mut stats = ConnectionStats::new();
+        assert_eq!(
+            stats.format_status(),
+            "connecting messages 0 checksum - up 0s"
+        );
+
+        stats.set_state(ConnectionState::Connected);
+        stats.record_message();
+        stats.record_message();
+        stats.record_checksum(false);
+        assert_eq!(stats.messages(), 2);
+        assert_eq!(stats.last_checksum(), Some(false));
+        assert_eq!(
+            stats.format_status(),
+            "connected messages 2 checksum FAILED up 0s"
+        );
+
+        stats.record_checksum(true);
+        stats.set_state(ConnectionState::Disconnected);
+        assert!(stats
+            .format_status()
+            .starts_with("disconnected messages 2 checksum ok"));
+    }
+
+    #[test]
+    fn session_counts_tell_snapshots_from_updates() {
+        let entry = |price: &str, qty: &str| PriceLevel {
+            price: price.parse().unwrap(),
+            qty: qty.parse().unwrap(),
+        };
+        let book_update = |kind, bids| BookUpdate {
+            symbol: String::from("BTC/USD"),
+            kind,
+            bids,
+            asks: vec![entry("101", "1")],
+            checksum: None,
+            timestamp: None,
+        };
+        let mut books = HashMap::from([(String::from("BTC/USD"), Orderbook::new("BTC/USD"))]);
+        let session = SessionStats::new();
+        let mut failing = book_update(UpdateKind::Update, vec![entry("100", "3")]);
+        failing.checksum = Some(0);
+
+        // entries applied to the same book at once still count one by one
+        session.observe(&route_entries(
+            &mut books,
+            vec![
+                book_update(UpdateKind::Snapshot, vec![entry("100", "1")]),
+                book_update(UpdateKind::Update, vec![entry("100", "2")]),
+            ],
+        ));
+        session.observe(&route_entries(&mut books, vec![failing]));
+        session.record_reconnect();
+
+        assert_eq!(
+            session.counts(),
+            SessionCounts {
+                updates: 2,
+                snapshots: 1,
+                reconnects: 1,
+                checksum_failures: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn uptime_follows_the_clock() {
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let stats = ConnectionStats::with_clock(clock.clone());
+
+        clock.advance(Duration::from_millis(65_900));
+
+        assert_eq!(stats.uptime(), Duration::from_millis(65_900));
+        assert_eq!(
+            stats.format_status(),
+            "connecting messages 0 checksum - up 1m 5s"
+        );
+    }
+}
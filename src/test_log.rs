@@ -0,0 +1,47 @@
+//! Log capturing for tests that check what the library logs.
+
+use log::{Level, Metadata, Record};
+use std::cell::RefCell;
+use std::sync::Once;
+
+thread_local! {
+    static LOGGED: RefCell<Vec<(Level, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Keeps the records logged by each test thread so tests can inspect their own.
+struct CapturingLogger;
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        LOGGED.with(|logged| {
+            logged
+                .borrow_mut()
+                .push((record.level(), record.args().to_string()))
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Runs `f` and returns the messages it logged at `level`.
+pub fn logged(level: Level, f: impl FnOnce()) -> Vec<String> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&CapturingLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+    LOGGED.with(|logged| logged.borrow_mut().clear());
+    f();
+    LOGGED.with(|logged| {
+        logged
+            .borrow()
+            .iter()
+            .filter(|(logged_level, _)| *logged_level == level)
+            .map(|(_, message)| message.clone())
+            .collect()
+    })
+}
@@ -0,0 +1,51 @@
+//! Helpers for tests comparing books against the snapshots they are expected to match.
+
+use crate::orderbook::{BookSnapshot, Orderbook, PriceLevel};
+
+/// Describes every way `actual` differs from `expected`, one line per mismatching symbol or
+/// level; empty when they hold the same levels. The snapshot times are ignored.
+pub fn snapshot_diff(actual: &BookSnapshot, expected: &BookSnapshot) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    if actual.symbol != expected.symbol {
+        mismatches.push(format!(
+            "symbol: expected {}, got {}",
+            expected.symbol, actual.symbol
+        ));
+    }
+    side_diff("bid", &actual.bids, &expected.bids, &mut mismatches);
+    side_diff("ask", &actual.asks, &expected.asks, &mut mismatches);
+    mismatches
+}
+
+/// Compares the levels of one side position by position, counting from 1 at the top.
+fn side_diff(
+    side: &str,
+    actual: &[PriceLevel],
+    expected: &[PriceLevel],
+    mismatches: &mut Vec<String>,
+) {
+    let format = |level: &PriceLevel| format!("{} x {}", level.price, level.qty);
+    for position in 0..actual.len().max(expected.len()) {
+        let mismatch = match (actual.get(position), expected.get(position)) {
+            (Some(actual), Some(expected)) if actual == expected => continue,
+            (Some(actual), Some(expected)) => {
+                format!("expected {}, got {}", format(expected), format(actual))
+            }
+            (None, Some(expected)) => format!("missing {}", format(expected)),
+            (Some(actual), None) => format!("unexpected {}", format(actual)),
+            (None, None) => continue,
+        };
+        mismatches.push(format!("{} level {}: {}", side, position + 1, mismatch));
+    }
+}
+
+/// Panics listing the mismatches unless `orderbook` holds exactly the levels of `expected`.
+pub fn assert_book_eq(orderbook: &Orderbook, expected: &BookSnapshot) {
+    let mismatches = snapshot_diff(&orderbook.to_snapshot(), expected);
+    assert!(
+        mismatches.is_empty(),
+        "{} book differs from the expected snapshot:\n{}",
+        orderbook.symbol(),
+        mismatches.join("\n")
+    );
+}
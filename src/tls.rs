@@ -0,0 +1,31 @@
+//! TLS settings of the websocket connection, for endpoints other than the exchange's own.
+
+use log::warn;
+use native_tls::{Certificate, TlsConnector};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use tokio_tungstenite::Connector;
+
+/// Builds the connector for `wss://` endpoints, additionally trusting the PEM certificate
+/// in `ca_cert` and with `insecure` accepting any certificate and host name. `None` with
+/// neither, which keeps the system's verification.
+pub fn connector(
+    ca_cert: Option<&Path>,
+    insecure: bool,
+) -> Result<Option<Connector>, Box<dyn Error>> {
+    if ca_cert.is_none() && !insecure {
+        return Ok(None);
+    }
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = ca_cert {
+        builder.add_root_certificate(Certificate::from_pem(&fs::read(path)?)?);
+    }
+    if insecure {
+        warn!("Certificate verification is disabled, the connection can be intercepted");
+        builder
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+    Ok(Some(Connector::NativeTls(builder.build()?)))
+}